@@ -1,10 +1,7 @@
-use std::iter;
-
-use gates::mux4way16;
-
 use crate::*;
 
-struct HackCtrl {
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HackCtrl {
     zx: bool,
     nx: bool,
     zy: bool, 
@@ -14,13 +11,13 @@ struct HackCtrl {
 }
 
 impl HackCtrl {
-    fn new(zx: bool, nx: bool, zy: bool, ny: bool, f: bool, no: bool) -> HackCtrl {
+    pub fn new(zx: bool, nx: bool, zy: bool, ny: bool, f: bool, no: bool) -> HackCtrl {
         HackCtrl{zx, nx, zy, ny, f, no}
     }
 }
 
-
-struct RISCvCtrl {
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RISCvCtrl {
     ir: bool,   // R-Type 1, I-Type 0
     al: bool,   // Logic  1, Arith  0
     c: bool, 
@@ -29,15 +26,15 @@ struct RISCvCtrl {
 }
 
 impl RISCvCtrl {
-    fn new(ir: bool, al: bool, c: bool, d: bool, pn: bool) -> RISCvCtrl {
+    pub fn new(ir: bool, al: bool, c: bool, d: bool, pn: bool) -> RISCvCtrl {
         RISCvCtrl{ ir, al, c, d, pn }
     }
 }
 
-struct HackOut {
-    out: [bool; 16],
-    zr: bool,
-    ng: bool,
+pub struct HackOut {
+    pub out: [bool; 16],
+    pub zr: bool,
+    pub ng: bool,
 }
 
 impl HackOut {
@@ -45,11 +42,25 @@ impl HackOut {
         HackOut{out, zr, ng}
     }
 }
+
+pub struct RISCvOut {
+    pub out: [bool; 32],
+    pub zr: bool,   // out=0
+    pub ng: bool,   // out<0
+    pub cr: bool,   // adder carry out, for subtraction set iff X>=Y unsigned
+    pub ov: bool,   // adder signed overflow
+}
+
+impl RISCvOut {
+    fn new(out: [bool; 32], zr: bool, ng: bool, cr: bool, ov: bool) -> RISCvOut {
+        RISCvOut{ out, zr, ng, cr, ov }
+    }
+}
 /// half_adder - Add two bits
 /// Inputs: bit1, bit2
 /// Outputs: (sum, carry)
 /// Function: sum is LSB of bit1 + bit2, carry is MSB
-fn half_adder(bit1: bool, bit2: bool) -> (bool, bool) {
+pub fn half_adder(bit1: bool, bit2: bool) -> (bool, bool) {
     (gates::xor(bit1, bit2), 
      gates::and(bit1, bit2))
 }
@@ -58,32 +69,182 @@ fn half_adder(bit1: bool, bit2: bool) -> (bool, bool) {
 /// Inputs: bit1, bit2, carry
 /// Outputs: (sum, carry_out)
 /// Function: sum is LSB of bit1 + bit2 + carry, carry_out is MSB
-fn full_adder(bit1: bool, bit2: bool, carry: bool) -> (bool, bool) {
+pub fn full_adder(bit1: bool, bit2: bool, carry: bool) -> (bool, bool) {
     let (half_sum, half_carr_y) = half_adder(bit1, bit2);
     let (full_sum, half_carry) = half_adder(half_sum, carry);
     (full_sum, gates::xor(half_carr_y, half_carry))
 }
 
-// carry-ripple adder
-// TODO replace with carry-lookahead
+/// Lookahead4 - 4-bit carry-lookahead unit
+/// Inputs: p[4], g[4], carry
+/// Outputs: carries[5]
+/// Function: carries[0]=carry, carries[i+1]=g[i] + p[i]g[i-1] + ... + p[i]..p[0]carry
+///
+/// Every carry is a flat and/or of the inputs, none waits on the carry below it.
+/// The same unit serves bits inside a block and blocks inside a group.
+fn lookahead4(p: &[bool], g: &[bool], carry: bool) -> [bool; 5] {
+    let mut carries = [carry; 5];
+    for i in 0..4 {
+        let mut generate = g[i];
+        let mut propagate = p[i];
+        for j in (0..i).rev() {
+            generate = gates::or(generate, gates::and(propagate, g[j]));
+            propagate = gates::and(propagate, p[j]);
+        }
+        carries[i+1] = gates::or(generate, gates::and(propagate, carry));
+    }
+    carries
+}
+
+/// Group4 - Propagate and generate of four bits (or blocks) taken together
+/// Inputs: p[4], g[4]
+/// Outputs: (group_p, group_g)
+/// Function: group_p=p[3]p[2]p[1]p[0], group_g=g[3] + p[3]g[2] + p[3]p[2]g[1] + p[3]p[2]p[1]g[0]
+fn group4(p: &[bool], g: &[bool]) -> (bool, bool) {
+    let group_p = gates::and(gates::and(p[0], p[1]), gates::and(p[2], p[3]));
+    let group_g = gates::or(gates::or(g[3], gates::and(p[3], g[2])),
+                            gates::or(gates::and(gates::and(p[3], p[2]), g[1]),
+                                      gates::and(gates::and(p[3], p[2]), gates::and(p[1], g[0]))));
+    (group_p, group_g)
+}
+
+/// CLA16 - Carries of a 16-bit group from its bit and block propagate/generate
+/// Inputs: p[16], g[16], block_p[4], block_g[4], carry
+/// Outputs: carries[17]
+/// Function: a group-level Lookahead4 gives each block its carry in,
+///           then each block's Lookahead4 gives its bits theirs
+fn cla16(p: &[bool], g: &[bool], block_p: &[bool], block_g: &[bool], carry: bool) -> [bool; 17] {
+    let block_carries = lookahead4(block_p, block_g, carry);
+    let mut carries = [false; 17];
+    for b in 0..4 {
+        let bits = lookahead4(&p[4*b..4*b+4], &g[4*b..4*b+4], block_carries[b]);
+        carries[4*b..4*b+4].copy_from_slice(&bits[0..4]);
+    }
+    carries[16] = block_carries[4];
+    carries
+}
+
+// propagate and generate are the only per-bit gates a lookahead adder needs
+fn propagate_generate<const N: usize>(val1: &[bool; N], val2: &[bool; N]) -> ([bool; N], [bool; N]) {
+    let mut p = [false; N];
+    let mut g = [false; N];
+    for i in 0..N {
+        p[i] = gates::xor(val1[i], val2[i]);
+        g[i] = gates::and(val1[i], val2[i]);
+    }
+    (p, g)
+}
+
+/// Adder32 - Interchangeable 32-bit adder circuits
+/// Inputs: val1[32], val2[32], carry
+/// Outputs: (res[32], carry_out, overflow)
+/// Function: res=val1+val2+carry
+///
+/// carry_out is the carry out of bit 31.
+/// overflow is carry[31] xor carry[32], set iff the signed result wrapped.
+pub trait Adder32 {
+    fn add32_carry(val1: &[bool; 32], val2: &[bool; 32], carry: bool) -> ([bool; 32], bool, bool);
+}
+
+/// RippleCarry - 32 chained full adders, each waits on the carry below it
+pub struct RippleCarry;
+
+impl Adder32 for RippleCarry {
+    fn add32_carry(val1: &[bool; 32], val2: &[bool; 32], carry: bool) -> ([bool; 32], bool, bool) {
+        let mut res: [bool; 32] = [false; 32];
+        let mut carry_bus: [bool; 33] = [false; 33];
+        carry_bus[0] = carry;
+        for i in 0..32 {
+            (res[i], carry_bus[i+1]) = full_adder(val1[i], val2[i], carry_bus[i]);
+        }
+        (res, carry_bus[32], gates::xor(carry_bus[31], carry_bus[32]))
+    }
+}
+
+/// CarryLookahead - 4-bit CLA blocks, a group lookahead per 16 bits,
+/// and a top-level lookahead between the two 16-bit groups
+pub struct CarryLookahead;
+
+impl Adder32 for CarryLookahead {
+    fn add32_carry(val1: &[bool; 32], val2: &[bool; 32], carry: bool) -> ([bool; 32], bool, bool) {
+        let (p, g) = propagate_generate(val1, val2);
+
+        let mut block_p = [false; 8];
+        let mut block_g = [false; 8];
+        for b in 0..8 {
+            (block_p[b], block_g[b]) = group4(&p[4*b..4*b+4], &g[4*b..4*b+4]);
+        }
+        let (lo_p, lo_g) = group4(&block_p[0..4], &block_g[0..4]);
+        let (hi_p, hi_g) = group4(&block_p[4..8], &block_g[4..8]);
+        let group_carries = lookahead4(&[lo_p, hi_p, false, false], &[lo_g, hi_g, false, false], carry);
+
+        let mut carry_bus = [false; 33];
+        carry_bus[0..17].copy_from_slice(&cla16(&p[0..16], &g[0..16], &block_p[0..4], &block_g[0..4], group_carries[0]));
+        carry_bus[16..33].copy_from_slice(&cla16(&p[16..32], &g[16..32], &block_p[4..8], &block_g[4..8], group_carries[1]));
+
+        let mut res = [false; 32];
+        for i in 0..32 {
+            res[i] = gates::xor(p[i], carry_bus[i]);
+        }
+        (res, carry_bus[32], gates::xor(carry_bus[31], carry_bus[32]))
+    }
+}
+
+/// CarrySelect - four 8-bit ripple blocks; the upper three are built twice,
+/// once for each carry in, and the real carry only drives the output muxes
+pub struct CarrySelect;
+
+impl Adder32 for CarrySelect {
+    fn add32_carry(val1: &[bool; 32], val2: &[bool; 32], carry: bool) -> ([bool; 32], bool, bool) {
+        let mut res = [false; 32];
+        let mut carry_bus = [false; 33];
+        carry_bus[0] = carry;
+        // the lowest block already has its real carry in
+        for i in 0..8 {
+            (res[i], carry_bus[i+1]) = full_adder(val1[i], val2[i], carry_bus[i]);
+        }
+        for block in 1..4 {
+            let lo = 8*block;
+            let mut sums = [[false; 8]; 2];
+            let mut carries = [[false; 9]; 2];
+            for assumed in 0..2 {
+                carries[assumed][0] = assumed == 1;
+                for i in 0..8 {
+                    (sums[assumed][i], carries[assumed][i+1]) = full_adder(val1[lo+i], val2[lo+i], carries[assumed][i]);
+                }
+            }
+            let sel = carry_bus[lo];
+            for i in 0..8 {
+                res[lo+i] = gates::mux(sums[0][i], sums[1][i], sel);
+                carry_bus[lo+i+1] = gates::mux(carries[0][i+1], carries[1][i+1], sel);
+            }
+        }
+        (res, carry_bus[32], gates::xor(carry_bus[31], carry_bus[32]))
+    }
+}
+
 /// Add16 - Add two 16-bit values
 /// Inputs: val1[16], val2[16]
 /// Outputs: res[16]
 /// Function: res=val1+val2
 /// 
-/// Integer 2’s complement addition.
+/// Integer 2’s complement addition, two-level carry-lookahead.
 /// Overflow is neither detected nor handled.
-fn add16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] {
+pub fn add16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] {
+    let (p, g) = propagate_generate(val1, val2);
+    let mut block_p = [false; 4];
+    let mut block_g = [false; 4];
+    for b in 0..4 {
+        (block_p[b], block_g[b]) = group4(&p[4*b..4*b+4], &g[4*b..4*b+4]);
+    }
+    let carries = cla16(&p, &g, &block_p, &block_g, false);
     let mut res: [bool; 16] = [false; 16];
-    let mut carry_bus: [bool; 17] = [false; 17]; // prevent bus overflow on final bit
     for i in 0..16 {
-        (res[i], carry_bus[i+1]) = full_adder(val1[i], val2[i], carry_bus[i]);
+        res[i] = gates::xor(p[i], carries[i]);
     }
     res
 }
 
-// carry-ripple adder
-// TODO replace with carry-lookahead
 /// Add32 - Add two 32-bit values
 /// Inputs: val1[32], val2[32]
 /// Outputs: res[32]
@@ -91,12 +252,19 @@ fn add16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] {
 /// 
 /// Integer 2’s complement addition.
 /// Overflow is neither detected nor handled.
-fn add32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] {
-    let mut res: [bool; 32] = [false; 32];
-    let mut carry_bus: [bool; 33] = [false; 33]; // prevent bus overflow on final bit
-    for i in 0..32 {
-        (res[i], carry_bus[i+1]) = full_adder(val1[i], val2[i], carry_bus[i]);
-    }
+pub fn add32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] {
+    add32_carry(val1, val2, false).0
+}
+
+/// Add32Carry - Add32 with carry in, carry out and overflow (see Adder32)
+/// Uses CarryLookahead, riscv_alu_with picks any other Adder32.
+pub fn add32_carry(val1: &[bool; 32], val2: &[bool; 32], carry: bool) -> ([bool; 32], bool, bool) {
+    CarryLookahead::add32_carry(val1, val2, carry)
+}
+
+fn bit_to_bus32(bit: bool) -> [bool; 32] {
+    let mut res = [false; 32];
+    res[0] = bit;
     res
 }
 
@@ -107,9 +275,9 @@ fn add32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] {
 /// 
 /// Integer 2’s complement addition.
 /// Overflow is neither detected nor handled.
-fn inc16(val: &[bool; 16]) -> [bool; 16] {
+pub fn inc16(val: &[bool; 16]) -> [bool; 16] {
     let one = utils::bytes_to_boollist(&[0,1]);
-    add16(&val, &one)
+    add16(val, &one)
 }
 
 /// Inc32 - Increment value by 1
@@ -119,17 +287,43 @@ fn inc16(val: &[bool; 16]) -> [bool; 16] {
 /// 
 /// Integer 2’s complement addition.
 /// Overflow is neither detected nor handled.
-fn inc32(val: &[bool; 32]) -> [bool; 32] {
+pub fn inc32(val: &[bool; 32]) -> [bool; 32] {
     let one = utils::bytes_to_boollist32(&[0,0,0,1]);
-    add32(&val, &one)
+    add32(val, &one)
+}
+
+/// BarrelShift32 - Shift a 32-bit value by 0-31 places
+/// Inputs: val[32], shamt[5], right, arith
+/// Outputs: res[32]
+/// Function: if right then res = val >> shamt, filling with val[31] when arith
+///           else res = val << shamt
+///
+/// Logarithmic shifter: five mux32 stages, stage k shifts by 2^k when shamt[k] is set.
+/// Left shifts reuse the right-shifting stages on the bit-reversed bus.
+pub fn barrel_shift32(val: &[bool; 32], shamt: &[bool], right: bool, arith: bool) -> [bool; 32] {
+    let mut reversed = *val;
+    reversed.reverse();
+    let fill = gates::and(gates::and(right, arith), val[31]);
+
+    let mut res = gates::mux32(&reversed, val, right);
+    for stage in 0..5 {
+        let dist = 1 << stage;
+        let mut shifted = [fill; 32];
+        shifted[0..32-dist].copy_from_slice(&res[dist..32]);
+        res = gates::mux32(&res, &shifted, shamt[stage]);
+    }
+
+    let mut unreversed = res;
+    unreversed.reverse();
+    gates::mux32(&unreversed, &res, right)
 }
 
 /// RISCvALU - ALU core for RISC V CPU
 /// 
 /// opcode,func3,func7,inst,RISCvCtrl 
 /// 0110011,b001,0x00,SLL,10010
-/// 0110011,b101,0x20,SRL,11011
-/// 0110011,b101,0x00,SRA,11010
+/// 0110011,b101,0x00,SRL,11010
+/// 0110011,b101,0x20,SRA,11011
 /// 0110011,b010,0x00,SLT,10100
 /// 0110011,b011,0x00,SLTU,10110
 ///
@@ -142,15 +336,49 @@ fn inc32(val: &[bool; 32]) -> [bool; 32] {
 /// 0010011,b101,0x20,SRAI,01011
 /// 0010011,b010,SLTI, 0010x
 /// 0010011,b011,SLTIU,0011x
-// TODO implement shift operations
-pub fn riscv_alu(val1: &[bool; 32], val2: &[bool; 32], ctrl: &RISCvCtrl) -> [bool; 32] {
-    let rs2 = gates::mux32(val2, &inc32(&gates::not32(val2)), ctrl.pn);
-    let logic_res = gates::mux4way32(&[gates::xor32(&val1, &rs2), 
-                                                        [false;32], 
-                                                        gates::or32(&val1, &rs2), 
-                                                        gates::and32(&val1, &rs2)],
-                                                  (ctrl.c, ctrl.d));
-	gates::mux32(&add32(&val1, &rs2), &logic_res, ctrl.al)
+///
+/// pn only negates Y for R-Type, for I-Type it is immediate bit 10.
+/// Shifts take shamt from Y[5] and treat pn as arithmetic-right.
+/// SLT/SLTU subtract Y and output 0 or 1 from the adder flags.
+///
+/// Outputs: RISCvOut
+///            out[32]: 32-bit output
+///            zr: True iff out=0
+///            ng: True iff out<0
+///            cr: Carry out of the adder
+///            ov: Signed overflow of the adder
+/// For SUB, X<Y signed iff ng!=ov, X<Y unsigned iff !cr, X=Y iff zr
+pub fn riscv_alu(val1: &[bool; 32], val2: &[bool; 32], ctrl: &RISCvCtrl) -> RISCvOut {
+    riscv_alu_with::<CarryLookahead>(val1, val2, ctrl)
+}
+
+/// RISCvALU built around a chosen Adder32 circuit
+pub fn riscv_alu_with<A: Adder32>(val1: &[bool; 32], val2: &[bool; 32], ctrl: &RISCvCtrl) -> RISCvOut {
+    // SUB, SLT(I) and SLT(I)U all subtract
+    let sub = gates::or(gates::and(ctrl.ir, ctrl.pn), gates::and(gates::not(ctrl.al), ctrl.c));
+    let (sum, cr, ov) = A::add32_carry(val1, &gates::mux32(val2, &gates::not32(val2), sub), sub);
+
+    let arith_res = gates::mux4way32(&[sum, 
+                                       barrel_shift32(val1, &val2[0..5], false, false), 
+                                       bit_to_bus32(gates::xor(sum[31], ov)), 
+                                       bit_to_bus32(gates::not(cr))],
+                                     (ctrl.c, ctrl.d));
+    let logic_res = gates::mux4way32(&[gates::xor32(val1, val2), 
+                                       barrel_shift32(val1, &val2[0..5], true, ctrl.pn), 
+                                       gates::or32(val1, val2), 
+                                       gates::and32(val1, val2)],
+                                     (ctrl.c, ctrl.d));
+	let out = gates::mux32(&arith_res, &logic_res, ctrl.al);
+
+    let zr_1 = gates::or(gates::or8way(out[0], out[1], out[2], out[3], out[4], out[5], out[6], out[7]),
+                         gates::or8way(out[8], out[9], out[10], out[11], out[12], out[13], out[14], out[15]));
+    let zr_2 = gates::or(gates::or8way(out[16], out[17], out[18], out[19], out[20], out[21], out[22], out[23]),
+                         gates::or8way(out[24], out[25], out[26], out[27], out[28], out[29], out[30], out[31]));
+    let zr = gates::not(gates::or(zr_1, zr_2));
+
+    trace_ports!(("x", val1), ("y", val2), ("ir", &[ctrl.ir]), ("al", &[ctrl.al]), ("c", &[ctrl.c]), ("d", &[ctrl.d]), ("pn", &[ctrl.pn]),
+                 ("out", &out), ("zr", &[zr]), ("ng", &[out[31]]), ("cr", &[cr]), ("ov", &[ov]));
+    RISCvOut::new(out, zr, out[31], cr, ov)
 }
 
 /// Hack_ALU - ALU as specified by nand2tetris
@@ -181,14 +409,14 @@ pub fn riscv_alu(val1: &[bool; 32], val2: &[bool; 32], ctrl: &RISCvCtrl) -> [boo
 /// }
 /// 
 /// Overflow is neither detected nor handled.
-fn hack_alu(val1: &[bool; 16], val2: &[bool; 16], ctrl: &HackCtrl) -> HackOut {
+pub fn hack_alu(val1: &[bool; 16], val2: &[bool; 16], ctrl: &HackCtrl) -> HackOut {
 
     let zero = utils::bytes_to_boollist(&[0,0]);
     
-    let _x = gates::mux16(&val1, &zero, ctrl.zx);
+    let _x = gates::mux16(val1, &zero, ctrl.zx);
     let x = gates::mux16(&_x, &gates::not16(&_x), ctrl.nx);
     
-    let _y = gates::mux16(&val2, &zero, ctrl.zy);
+    let _y = gates::mux16(val2, &zero, ctrl.zy);
     let y = gates::mux16(&_y, &gates::not16(&_y), ctrl.ny);
     
     let _out = gates::mux16(&gates::and16(&x, &y), &add16(&x, &y), ctrl.f);
@@ -198,6 +426,8 @@ fn hack_alu(val1: &[bool; 16], val2: &[bool; 16], ctrl: &HackCtrl) -> HackOut {
     let zr_2 = gates::or8way(out[8], out[9], out[10], out[11], out[12], out[13], out[14], out[15]);
     let zr = gates::not(gates::or(zr_1, zr_2));
 
+    trace_ports!(("x", val1), ("y", val2), ("zx", &[ctrl.zx]), ("nx", &[ctrl.nx]), ("zy", &[ctrl.zy]), ("ny", &[ctrl.ny]),
+                 ("f", &[ctrl.f]), ("no", &[ctrl.no]), ("out", &out), ("zr", &[zr]), ("ng", &[out[15]]));
     HackOut::new(out, zr, out[15])
 }
 
@@ -227,6 +457,58 @@ mod tests {
         assert_eq!(full_adder(true, true, true), (true, true));
     }
 
+    #[test]
+    fn test_lookahead4_group4_work() {
+        // every 4-bit block against a ripple of full adders
+        for a in 0..16u8 {
+            for b in 0..16u8 {
+                for carry in [false, true] {
+                    let bits = |x: u8| [x & 1 == 1, x & 2 == 2, x & 4 == 4, x & 8 == 8];
+                    let (val1, val2) = (bits(a), bits(b));
+                    let (p, g) = propagate_generate(&val1, &val2);
+                    let carries = lookahead4(&p, &g, carry);
+                    let (group_p, group_g) = group4(&p, &g);
+
+                    let mut ripple = [carry; 5];
+                    for i in 0..4 {
+                        (_, ripple[i+1]) = full_adder(val1[i], val2[i], ripple[i]);
+                    }
+                    assert_eq!(carries, ripple);
+                    assert_eq!(group_p, a ^ b == 15);
+                    assert_eq!(group_g, a as u16 + b as u16 > 15);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_adders_match_ripple() {
+        let w = |x: u32| utils::bytes_to_boollist32(&utils::split_u32(x));
+        let mut vals = vec![0u32, 1, 0x7fffffff, 0x80000000, 0xffffffff, 0x0000ffff, 0xffff0000, 0x00ff00ff];
+        vals.extend(utils::Rng::new(0x2545f491).take(24));
+        for a in &vals {
+            for b in &vals {
+                for carry in [false, true] {
+                    let ripple = RippleCarry::add32_carry(&w(*a), &w(*b), carry);
+                    assert_eq!(ripple.0, w(a.wrapping_add(*b).wrapping_add(carry as u32)));
+                    assert_eq!(CarryLookahead::add32_carry(&w(*a), &w(*b), carry), ripple, "cla {:x} {:x}", a, b);
+                    assert_eq!(CarrySelect::add32_carry(&w(*a), &w(*b), carry), ripple, "csel {:x} {:x}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_riscv_alu_with_works() {
+        let val1 = utils::bytes_to_boollist32(&[0x7f,0xff,0xff,0xff]);
+        let val2 = utils::bytes_to_boollist32(&[0x00,0x00,0x00,0x01]);
+        let ctrl = RISCvCtrl::new(true, false, false, false, false);
+        let ripple = riscv_alu_with::<RippleCarry>(&val1, &val2, &ctrl);
+        for res in [riscv_alu_with::<CarryLookahead>(&val1, &val2, &ctrl), riscv_alu_with::<CarrySelect>(&val1, &val2, &ctrl)] {
+            assert_eq!((res.out, res.zr, res.ng, res.cr, res.ov), (ripple.out, ripple.zr, ripple.ng, ripple.cr, ripple.ov));
+        }
+    }
+
     #[test]
     fn test_add16_works() {
         let val1 = [00,12];
@@ -272,30 +554,142 @@ mod tests {
 
         // 10000 - ADD
         let ctrl = RISCvCtrl::new(true, false, false, false, false);
-        let out = riscv_alu(&val1, &val2, &ctrl);
+        let out = riscv_alu(&val1, &val2, &ctrl).out;
         assert_eq!(utils::boollist_to_bytes(&out), utils::boollist_to_bytes(&sum));
 
         // 10001 - SUB
         let ctrl = RISCvCtrl::new(true, false, false, false, true);
-        let out = riscv_alu(&val1, &val2, &ctrl);
+        let out = riscv_alu(&val1, &val2, &ctrl).out;
         assert_eq!(utils::boollist_to_bytes(&out), utils::boollist_to_bytes(&neg_one));
 
         // 11000 - XOR
         let ctrl = RISCvCtrl::new(true, true, false, false, false);
-        let out = riscv_alu(&val1, &val2, &ctrl);
+        let out = riscv_alu(&val1, &val2, &ctrl).out;
         assert_eq!(utils::boollist_to_bytes(&out), utils::boollist_to_bytes(&one));
 
         // 11100 - OR
         let ctrl = RISCvCtrl::new(true, true, true, false, false);
-        let out = riscv_alu(&val1, &val2, &ctrl);
+        let out = riscv_alu(&val1, &val2, &ctrl).out;
         assert_eq!(utils::boollist_to_bytes(&out), utils::boollist_to_bytes(&val2));
 
         // 11110 - AND
         let ctrl = RISCvCtrl::new(true, true, true, true, false);
-        let out = riscv_alu(&val1, &val2, &ctrl);
+        let out = riscv_alu(&val1, &val2, &ctrl).out;
         assert_eq!(utils::boollist_to_bytes(&out), utils::boollist_to_bytes(&val1));
     }
 
+    #[test]
+    fn test_barrel_shift32_works() {
+        for val in [0x80000f0fu32, 0x12345678, 0xffffffff, 1] {
+            let bits = utils::bytes_to_boollist32(&utils::split_u32(val));
+            for shamt in 0..32u16 {
+                let shamt_bits = utils::gen_memaddr(shamt);
+                let sll = barrel_shift32(&bits, &shamt_bits[0..5], false, false);
+                let srl = barrel_shift32(&bits, &shamt_bits[0..5], true, false);
+                let sra = barrel_shift32(&bits, &shamt_bits[0..5], true, true);
+                assert_eq!(utils::boollist_to_u32(&sll), val << shamt);
+                assert_eq!(utils::boollist_to_u32(&srl), val >> shamt);
+                assert_eq!(utils::boollist_to_u32(&sra), ((val as i32) >> shamt) as u32);
+            }
+        }
+    }
+
+    #[test]
+    fn test_riscv_alu_shifts_work() {
+        let val1 = utils::bytes_to_boollist32(&[0x80,0x00,0x0f,0x0f]);
+        let val2 = utils::bytes_to_boollist32(&[0x40,0x00,0x00,0x04]); // bit 30 set must not affect shamt
+
+        // 10010 - SLL
+        let ctrl = RISCvCtrl::new(true, false, false, true, false);
+        assert_eq!(riscv_alu(&val1, &val2, &ctrl).out, utils::bytes_to_boollist32(&[0x00,0x00,0xf0,0xf0]));
+
+        // 11010 - SRL
+        let ctrl = RISCvCtrl::new(true, true, false, true, false);
+        assert_eq!(riscv_alu(&val1, &val2, &ctrl).out, utils::bytes_to_boollist32(&[0x08,0x00,0x00,0xf0]));
+
+        // 11011 - SRA
+        let ctrl = RISCvCtrl::new(true, true, false, true, true);
+        assert_eq!(riscv_alu(&val1, &val2, &ctrl).out, utils::bytes_to_boollist32(&[0xf8,0x00,0x00,0xf0]));
+
+        // 00010 - SLLI
+        let ctrl = RISCvCtrl::new(false, false, false, true, false);
+        assert_eq!(riscv_alu(&val1, &val2, &ctrl).out, utils::bytes_to_boollist32(&[0x00,0x00,0xf0,0xf0]));
+
+        // 01010 - SRLI
+        let ctrl = RISCvCtrl::new(false, true, false, true, false);
+        assert_eq!(riscv_alu(&val1, &val2, &ctrl).out, utils::bytes_to_boollist32(&[0x08,0x00,0x00,0xf0]));
+
+        // 01011 - SRAI
+        let ctrl = RISCvCtrl::new(false, true, false, true, true);
+        assert_eq!(riscv_alu(&val1, &val2, &ctrl).out, utils::bytes_to_boollist32(&[0xf8,0x00,0x00,0xf0]));
+
+        // 0000x - ADDI ignores pn
+        let ctrl = RISCvCtrl::new(false, false, false, false, true);
+        assert_eq!(riscv_alu(&val1, &val2, &ctrl).out, add32(&val1, &val2));
+    }
+
+    #[test]
+    fn test_add32_carry_works() {
+        let w = |x: u32| utils::bytes_to_boollist32(&utils::split_u32(x));
+        assert_eq!(add32_carry(&w(12), &w(13), false), (w(25), false, false));
+        assert_eq!(add32_carry(&w(12), &w(13), true), (w(26), false, false));
+        assert_eq!(add32_carry(&w(0xffffffff), &w(1), false), (w(0), true, false));
+        assert_eq!(add32_carry(&w(0x7fffffff), &w(1), false), (w(0x80000000), false, true));
+        assert_eq!(add32_carry(&w(0x80000000), &w(0x80000000), false), (w(0), true, true));
+    }
+
+    #[test]
+    fn test_riscv_alu_flags_work() {
+        let w = |x: u32| utils::bytes_to_boollist32(&utils::split_u32(x));
+        let vals = [0u32, 1, 12, 13, 0x7fffffff, 0x80000000, 0x80000001, 0xfffffff3, 0xffffffff];
+
+        // 10001 - SUB, the flags drive every branch condition
+        let ctrl = RISCvCtrl::new(true, false, false, false, true);
+        for a in vals {
+            for b in vals {
+                let res = riscv_alu(&w(a), &w(b), &ctrl);
+                assert_eq!(res.out, w(a.wrapping_sub(b)));
+                assert_eq!(res.zr, a == b, "{:x} == {:x}", a, b);
+                assert_eq!(res.ng, (a.wrapping_sub(b) as i32) < 0);
+                assert_eq!(res.ng != res.ov, (a as i32) < (b as i32), "{:x} < {:x}", a, b);
+                assert_eq!(!res.cr, a < b, "{:x} <u {:x}", a, b);
+            }
+        }
+
+        // 10000 - ADD
+        let ctrl = RISCvCtrl::new(true, false, false, false, false);
+        let res = riscv_alu(&w(0x7fffffff), &w(1), &ctrl);
+        assert_eq!((res.zr, res.ng, res.cr, res.ov), (false, true, false, true));
+        let res = riscv_alu(&w(0xffffffff), &w(1), &ctrl);
+        assert_eq!((res.zr, res.ng, res.cr, res.ov), (true, false, true, false));
+    }
+
+    #[test]
+    fn test_riscv_alu_slt_works() {
+        let neg_one = utils::bytes_to_boollist32(&[0xff,0xff,0xff,0xff]);
+        let twelve = utils::bytes_to_boollist32(&[00,00,00,12]);
+        let zero = utils::bytes_to_boollist32(&[00,00,00,00]);
+        let one = utils::bytes_to_boollist32(&[00,00,00,1]);
+
+        // 10100 - SLT
+        let ctrl = RISCvCtrl::new(true, false, true, false, false);
+        assert_eq!(riscv_alu(&neg_one, &twelve, &ctrl).out, one);
+        assert_eq!(riscv_alu(&twelve, &neg_one, &ctrl).out, zero);
+
+        // 10110 - SLTU
+        let ctrl = RISCvCtrl::new(true, false, true, true, false);
+        assert_eq!(riscv_alu(&neg_one, &twelve, &ctrl).out, zero);
+        assert_eq!(riscv_alu(&twelve, &neg_one, &ctrl).out, one);
+
+        // 0010x - SLTI
+        let ctrl = RISCvCtrl::new(false, false, true, false, true);
+        assert_eq!(riscv_alu(&neg_one, &twelve, &ctrl).out, one);
+
+        // 0011x - SLTIU
+        let ctrl = RISCvCtrl::new(false, false, true, true, true);
+        assert_eq!(riscv_alu(&twelve, &neg_one, &ctrl).out, one);
+    }
+
     #[test]
     fn test_hack_alu_works() {
         let val1 = bytes_to_boollist(&[00,12]);
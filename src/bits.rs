@@ -0,0 +1,222 @@
+// Bits<N> is an N-bit bus as a value, for datapaths of any width.
+// Bit order is the one every bus in the crate uses: bits[i] carries 2^i,
+// so bits[0] is the LSB and bits[N-1] the MSB. Integers convert with that weighting,
+// and Display prints the MSB first like a binary literal.
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitXor, Index, IndexMut, Not};
+use crate::*;
+
+/// Bits - An N-bit bus with gate-level bitwise operations, muxes and dmuxes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Bits<const N: usize>([bool; N]);
+
+impl<const N: usize> Bits<N> {
+    pub fn new(bits: [bool; N]) -> Bits<N> {
+        Bits(bits)
+    }
+
+    pub fn zero() -> Bits<N> {
+        Bits([false; N])
+    }
+
+    pub fn to_array(&self) -> [bool; N] {
+        self.0
+    }
+
+    pub fn msb(&self) -> bool {
+        N > 0 && self.0[N - 1]
+    }
+
+    /// Low N bits of val, bits past 64 are zero
+    pub fn from_u64(val: u64) -> Bits<N> {
+        let mut bits = [false; N];
+        for i in 0..N.min(64) {
+            bits[i] = (val >> i) & 1 == 1;
+        }
+        Bits(bits)
+    }
+
+    /// The bus as an unsigned integer, panics on buses wider than 64 bits
+    pub fn to_u64(&self) -> u64 {
+        assert!(N <= 64, "Bits<{}> does not fit in a u64", N);
+        self.0.iter().rev().fold(0, |val, bit| (val << 1) | *bit as u64)
+    }
+
+    /// Zero-extend or truncate to M bits
+    pub fn resize<const M: usize>(&self) -> Bits<M> {
+        let mut bits = [false; M];
+        bits[..N.min(M)].copy_from_slice(&self.0[..N.min(M)]);
+        Bits(bits)
+    }
+
+    /// Sign-extend (copy the MSB upwards) or truncate to M bits
+    pub fn sign_extend<const M: usize>(&self) -> Bits<M> {
+        let mut bits = [self.msb(); M];
+        bits[..N.min(M)].copy_from_slice(&self.0[..N.min(M)]);
+        Bits(bits)
+    }
+
+    /// self when sel=0, other when sel=1
+    pub fn mux(&self, other: &Bits<N>, sel: bool) -> Bits<N> {
+        Bits(gates::mux_n(&self.0, &other.0, sel))
+    }
+
+    /// vals[2*s0 + s1] for sel=(s0, s1)
+    pub fn mux4way(vals: &[Bits<N>; 4], sel: (bool, bool)) -> Bits<N> {
+        Bits(gates::mux4way_n(&vals.map(|val| val.0), sel))
+    }
+
+    /// vals[4*s0 + 2*s1 + s2] for sel=(s0, s1, s2)
+    pub fn mux8way(vals: &[Bits<N>; 8], sel: (bool, bool, bool)) -> Bits<N> {
+        Bits(gates::mux8way_n(&vals.map(|val| val.0), sel))
+    }
+
+    /// (self, 0) when sel=0, (0, self) when sel=1
+    pub fn dmux(&self, sel: bool) -> (Bits<N>, Bits<N>) {
+        let (a, b) = gates::dmux_n(&self.0, sel);
+        (Bits(a), Bits(b))
+    }
+
+    /// self on output 2*sel1 + sel2, zero on the others
+    pub fn dmux4way(&self, sel1: bool, sel2: bool) -> [Bits<N>; 4] {
+        gates::dmux4way_n(&self.0, sel1, sel2).map(Bits)
+    }
+
+    /// self on output 4*sel1 + 2*sel2 + sel3, zero on the others
+    pub fn dmux8way(&self, sel1: bool, sel2: bool, sel3: bool) -> [Bits<N>; 8] {
+        gates::dmux8way_n(&self.0, sel1, sel2, sel3).map(Bits)
+    }
+}
+
+impl<const N: usize> Default for Bits<N> {
+    fn default() -> Bits<N> { Bits::zero() }
+}
+
+impl<const N: usize> From<[bool; N]> for Bits<N> {
+    fn from(bits: [bool; N]) -> Bits<N> { Bits(bits) }
+}
+
+impl<const N: usize> From<Bits<N>> for [bool; N] {
+    fn from(bits: Bits<N>) -> [bool; N] { bits.0 }
+}
+
+impl<const N: usize> Index<usize> for Bits<N> {
+    type Output = bool;
+
+    fn index(&self, idx: usize) -> &bool { &self.0[idx] }
+}
+
+impl<const N: usize> IndexMut<usize> for Bits<N> {
+    fn index_mut(&mut self, idx: usize) -> &mut bool { &mut self.0[idx] }
+}
+
+impl<const N: usize> Not for Bits<N> {
+    type Output = Bits<N>;
+
+    fn not(self) -> Bits<N> { Bits(gates::not_n(&self.0)) }
+}
+
+impl<const N: usize> BitAnd for Bits<N> {
+    type Output = Bits<N>;
+
+    fn bitand(self, other: Bits<N>) -> Bits<N> { Bits(gates::and_n(&self.0, &other.0)) }
+}
+
+impl<const N: usize> BitOr for Bits<N> {
+    type Output = Bits<N>;
+
+    fn bitor(self, other: Bits<N>) -> Bits<N> { Bits(gates::or_n(&self.0, &other.0)) }
+}
+
+impl<const N: usize> BitXor for Bits<N> {
+    type Output = Bits<N>;
+
+    fn bitxor(self, other: Bits<N>) -> Bits<N> { Bits(gates::xor_n(&self.0, &other.0)) }
+}
+
+impl<const N: usize> fmt::Display for Bits<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for bit in self.0.iter().rev() {
+            write!(f, "{}", *bit as u8)?;
+        }
+        Ok(())
+    }
+}
+
+// integers convert losslessly to and from the bus of their own width
+macro_rules! impl_int_conversions {
+    ($($int:ident: $width:expr),*) => {$(
+        impl From<$int> for Bits<$width> {
+            fn from(val: $int) -> Bits<$width> { Bits::from_u64(val as u64) }
+        }
+
+        impl From<Bits<$width>> for $int {
+            fn from(bits: Bits<$width>) -> $int { bits.to_u64() as $int }
+        }
+    )*};
+}
+
+impl_int_conversions!(u8: 8, u16: 16, u32: 32, u64: 64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int_conversions_work() {
+        let byte = Bits::from(0b1000_0001u8);
+        assert_eq!((byte[0], byte[1], byte[7]), (true, false, true));
+        assert_eq!(byte.to_string(), "10000001");
+        assert_eq!(u8::from(byte), 0x81);
+
+        assert_eq!(Bits::from(0xbeefu16).to_array(), utils::bytes_to_boollist(&[0xbe, 0xef]));
+        assert_eq!(Bits::from(0xdeadbeefu32).to_array(), utils::bytes_to_boollist32(&[0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(u64::from(Bits::from(0x0123_4567_89ab_cdefu64)), 0x0123_4567_89ab_cdef);
+        assert_eq!(Bits::<4>::from_u64(0x1f).to_u64(), 0xf);
+        let wide = Bits::<80>::from_u64(u64::MAX);
+        assert!(wide[63] && !wide[64]);
+    }
+
+    #[test]
+    fn test_resize_works() {
+        let val = Bits::from(0x80u8);
+        assert_eq!(u16::from(val.resize::<16>()), 0x0080);
+        assert_eq!(u16::from(val.sign_extend::<16>()), 0xff80);
+        assert_eq!(u32::from(Bits::from(0x7fu8).sign_extend::<32>()), 0x7f);
+        assert_eq!(u8::from(Bits::from(0x1234u16).resize::<8>()), 0x34);
+    }
+
+    #[test]
+    fn test_bitwise_ops_work() {
+        let (a, b) = (Bits::from(0b1100u8), Bits::from(0b1010u8));
+        assert_eq!(u8::from(!a), 0b1111_0011);
+        assert_eq!(u8::from(a & b), 0b1000);
+        assert_eq!(u8::from(a | b), 0b1110);
+        assert_eq!(u8::from(a ^ b), 0b0110);
+
+        let (a, b) = (Bits::from(u64::MAX), Bits::from(1u64 << 63));
+        assert_eq!(u64::from(a ^ b), u64::MAX >> 1);
+    }
+
+    #[test]
+    fn test_mux_dmux_work() {
+        let vals = [1u64, 2, 3, 4, 5, 6, 7, 8].map(Bits::<64>::from);
+        assert_eq!(vals[0].mux(&vals[1], false), vals[0]);
+        assert_eq!(vals[0].mux(&vals[1], true), vals[1]);
+        assert_eq!(Bits::mux4way(&[vals[0], vals[1], vals[2], vals[3]], (true, false)), vals[2]);
+        for i in 0..8 {
+            let sel = (i & 4 != 0, i & 2 != 0, i & 1 != 0);
+            assert_eq!(Bits::mux8way(&vals, sel), vals[i]);
+
+            let outs = vals[7].dmux8way(sel.0, sel.1, sel.2);
+            for (j, out) in outs.iter().enumerate() {
+                assert_eq!(*out, if i == j { vals[7] } else { Bits::zero() });
+            }
+        }
+
+        let val = Bits::from(0xa5u8);
+        assert_eq!(val.dmux(false), (val, Bits::zero()));
+        assert_eq!(val.dmux(true), (Bits::zero(), val));
+        assert_eq!(val.dmux4way(false, true), [Bits::zero(), val, Bits::zero(), Bits::zero()]);
+    }
+}
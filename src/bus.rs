@@ -0,0 +1,348 @@
+// Memory-mapped I/O: a Bus decodes the address into chip-select lines and
+// routes each access to the RAM, ROM or peripheral mapped at that region.
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use crate::*;
+use memory::{HackMemory, RamChip, RamBackend, Register, ROM32K, WordMemory};
+
+/// Device - Anything that can sit on a Bus: RAM chips, ROMs and peripherals
+pub trait Device: Any + Send {
+    /// Number of 16-bit words the device decodes, a power of two
+    fn words(&self) -> usize;
+
+    /// One clock of the bus, every mapped device is clocked on every cycle
+    /// select is the chip-select from the bus decoder and load is already gated by it;
+    /// addr is the full bus address, a device only decodes its low bits.
+    /// Returns the addressed word from before the tick, ignored by the bus when unselected.
+    fn clk_cycle(&mut self, val: &[bool], addr: &[bool], select: bool, load: bool) -> [bool; 16];
+}
+
+// an unselected RAM has nothing to do but hold its contents, so it isn't clocked
+impl<R: RamChip + 'static> Device for R {
+    fn words(&self) -> usize { R::WORDS }
+
+    fn clk_cycle(&mut self, val: &[bool], addr: &[bool], select: bool, load: bool) -> [bool; 16] {
+        if !select { return [false; 16]; }
+        RamChip::clk_cycle(self, val, addr, load)
+    }
+}
+
+// read-only from the bus, writes are dropped
+impl<B: RamBackend> Device for ROM32K<B> {
+    fn words(&self) -> usize { 32768 }
+
+    fn clk_cycle(&mut self, _val: &[bool], addr: &[bool], select: bool, _load: bool) -> [bool; 16] {
+        if !select { return [false; 16]; }
+        ROM32K::clk_cycle(self, addr)
+    }
+}
+
+// the Hack data memory as one device, e.g. a Computer's default memory
+impl<B: RamBackend> Device for HackMemory<B> {
+    fn words(&self) -> usize { 32768 }
+
+    fn clk_cycle(&mut self, val: &[bool], addr: &[bool], select: bool, load: bool) -> [bool; 16] {
+        if !select { return [false; 16]; }
+        HackMemory::clk_cycle(self, val, addr, load)
+    }
+}
+
+// Chip name: Keyboard
+// Outputs: out[16]
+// Function: out is the code of the key currently pressed, 0 when none
+// Comment: read-only from the bus, set_key stands in for the physical keyboard.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Keyboard {
+    key: Register,
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard{ key: Register::new() }
+    }
+
+    pub fn set_key(&mut self, key: &[bool; 16]) {
+        self.key.clk_cycle(key, true);
+    }
+}
+
+impl Device for Keyboard {
+    fn words(&self) -> usize { 1 }
+
+    fn clk_cycle(&mut self, _val: &[bool], _addr: &[bool], _select: bool, _load: bool) -> [bool; 16] {
+        self.key.read()
+    }
+}
+
+// Chip name: Timer
+// Inputs: in[16], load
+// Outputs: out[16]
+// Function: If load(t-1) then count(t)=in(t-1)
+// else count(t)=count(t-1)+1
+// out(t)=count(t)
+// Comment: counts every bus cycle whether or not it is addressed.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Timer {
+    count: Register,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer{ count: Register::new() }
+    }
+
+    pub fn read(&self) -> [bool; 16] {
+        self.count.read()
+    }
+}
+
+impl Device for Timer {
+    fn words(&self) -> usize { 1 }
+
+    fn clk_cycle(&mut self, val: &[bool], _addr: &[bool], _select: bool, load: bool) -> [bool; 16] {
+        let mut data = [false; 16];
+        data.copy_from_slice(&val[0..16]);
+        let next = gates::mux16(&adder::inc16(&self.count.read()), &data, load);
+        self.count.clk_cycle(&next, true)
+    }
+}
+
+/// Uart - Serial port with a transmit and a receive queue, two words
+/// Word 0 (data): writing sends the low byte, reading takes the next received byte (0 if none).
+/// Word 1 (status): bit 0 is set while received bytes are waiting, bit 1 (ready to send) is always set.
+/// Comment: behavioral, send and take_output stand in for the other end of the line.
+#[derive(Clone, Debug, Default)]
+pub struct Uart {
+    rx: VecDeque<u8>,
+    tx: Vec<u8>,
+}
+
+impl Uart {
+    pub fn new() -> Uart {
+        Uart{ rx: VecDeque::new(), tx: Vec::new() }
+    }
+
+    /// Queue bytes for the machine to receive
+    pub fn send(&mut self, bytes: &[u8]) {
+        self.rx.extend(bytes);
+    }
+
+    /// Bytes the machine has transmitted since the last call
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.tx)
+    }
+}
+
+impl Device for Uart {
+    fn words(&self) -> usize { 2 }
+
+    fn clk_cycle(&mut self, val: &[bool], addr: &[bool], select: bool, load: bool) -> [bool; 16] {
+        if !select { return [false; 16]; }
+        let byte = match (addr[0], load) {
+            (false, true) => { self.tx.push(utils::boollist_to_u32(&val[0..8]) as u8); 0 }
+            (false, false) => self.rx.pop_front().unwrap_or(0) as u16,
+            (true, _) => 0b10 | !self.rx.is_empty() as u16,
+        };
+        utils::bytes_to_boollist(&utils::split_u16(byte))
+    }
+}
+
+/// BusError - Why a device can't be mapped
+#[derive(Debug, PartialEq)]
+pub enum BusError {
+    Misaligned { base: u32, words: usize },
+    OutOfRange { base: u32, words: usize },
+    Overlap { base: u32, other: u32 },
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusError::Misaligned{ base, words } =>
+                write!(f, "base 0x{:x} is not a multiple of the device size ({} words)", base, words),
+            BusError::OutOfRange{ base, words } =>
+                write!(f, "{} words at 0x{:x} run past the end of the bus", words, base),
+            BusError::Overlap{ base, other } =>
+                write!(f, "region at 0x{:x} overlaps the device at 0x{:x}", base, other),
+        }
+    }
+}
+
+struct Region {
+    base: u32,
+    low_bits: usize,    // address bits the device decodes itself
+    device: Box<dyn Device>,
+}
+
+/// Bus - Word-addressed bus of addr_bits address lines with devices mapped at aligned regions
+/// Function: each region's chip-select is high when the address bits above the device's
+///           own match its base; load is routed to the selected device like dmux, and
+///           out is the selected device's out. Unmapped addresses read 0 and drop writes.
+pub struct Bus {
+    addr_bits: usize,
+    regions: Vec<Region>,
+}
+
+impl Bus {
+    pub fn new(addr_bits: usize) -> Bus {
+        assert!(addr_bits <= 32, "a bus of {} address bits is wider than 32", addr_bits);
+        Bus{ addr_bits, regions: Vec::new() }
+    }
+
+    /// Map device at word address base, which must be aligned to the device's size
+    pub fn map<D: Device>(&mut self, base: u32, device: D) -> Result<(), BusError> {
+        let words = device.words();
+        assert!(words.is_power_of_two(), "device size {} is not a power of two", words);
+        if !(base as usize).is_multiple_of(words) {
+            return Err(BusError::Misaligned{ base, words });
+        }
+        if base as u64 + words as u64 > 1u64 << self.addr_bits {
+            return Err(BusError::OutOfRange{ base, words });
+        }
+        let end = base as u64 + words as u64;
+        if let Some(other) = self.regions.iter()
+            .find(|r| (r.base as u64) < end && (base as u64) < r.base as u64 + (1u64 << r.low_bits)) {
+            return Err(BusError::Overlap{ base, other: other.base });
+        }
+        self.regions.push(Region{ base, low_bits: words.trailing_zeros() as usize, device: Box::new(device) });
+        Ok(())
+    }
+
+    /// The device of type D mapped at base, e.g. to press a key or read a UART's output
+    pub fn device_mut<D: Device>(&mut self, base: u32) -> Option<&mut D> {
+        let region = self.regions.iter_mut().find(|r| r.base == base)?;
+        (region.device.as_mut() as &mut dyn Any).downcast_mut::<D>()
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
+        let mut out = [false; 16];
+        for region in self.regions.iter_mut() {
+            // chip-select: every address bit above the device's own matches its base
+            let select = (region.low_bits..self.addr_bits)
+                .fold(true, |acc, i| gates::and(acc, match (region.base >> i) & 1 {
+                    1 => addr[i],
+                    _ => gates::not(addr[i]),
+                }));
+            let res = region.device.clk_cycle(val, addr, select, gates::and(load, select));
+            out = gates::or16(&out, &gates::and16(&res, &[select; 16]));
+        }
+        out
+    }
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let regions: Vec<(u32, usize)> = self.regions.iter().map(|r| (r.base, 1 << r.low_bits)).collect();
+        f.debug_struct("Bus").field("addr_bits", &self.addr_bits).field("regions", &regions).finish()
+    }
+}
+
+// buses nest: a Bus is a device covering its whole address space
+impl Device for Bus {
+    fn words(&self) -> usize { 1 << self.addr_bits }
+
+    fn clk_cycle(&mut self, val: &[bool], addr: &[bool], select: bool, load: bool) -> [bool; 16] {
+        let out = Bus::clk_cycle(self, val, addr, load);
+        gates::and16(&out, &[select; 16])
+    }
+}
+
+// word access from outside the machine clocks the bus, so timers advance too
+impl WordMemory for Bus {
+    fn size(&self) -> usize { 1 << self.addr_bits }
+
+    fn read_word(&mut self, addr: usize) -> [bool; 16] {
+        self.clk_cycle(&[false; 16], &utils::bytes_to_boollist32(&utils::split_u32(addr as u32)), false)
+    }
+
+    fn write_word(&mut self, addr: usize, val: &[bool; 16]) {
+        self.clk_cycle(val, &utils::bytes_to_boollist32(&utils::split_u32(addr as u32)), true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::{HackMemory, RAMBank, RAM16384, RAM4096, RAM8};
+
+    fn word(val: u16) -> [bool; 16] {
+        utils::bytes_to_boollist(&utils::split_u16(val))
+    }
+
+    fn addr(val: u32) -> [bool; 32] {
+        utils::bytes_to_boollist32(&utils::split_u32(val))
+    }
+
+    #[test]
+    fn test_hack_map_matches_hack_memory() {
+        let mut bus = Bus::new(15);
+        bus.map(0x0000, RAM16384::new()).unwrap();
+        bus.map(0x4000, RAMBank::<RAM4096, 2>::new()).unwrap();
+        bus.map(0x6000, Keyboard::new()).unwrap();
+        let mut hack = HackMemory::new();
+
+        let key = word(75);
+        bus.device_mut::<Keyboard>(0x6000).unwrap().set_key(&key);
+        hack.set_key(&key);
+        for (i, a) in [0x0003u32, 0x3fff, 0x4000, 0x5fff, 0x0003, 0x4000, 0x6000].iter().enumerate() {
+            let val = word(0x1111 * i as u16);
+            assert_eq!(bus.clk_cycle(&val, &addr(*a), true), hack.clk_cycle(&val, &addr(*a), true), "write {:x}", a);
+            assert_eq!(bus.clk_cycle(&val, &addr(*a), false), hack.clk_cycle(&val, &addr(*a), false), "read {:x}", a);
+        }
+        assert_eq!(bus.read_word(0x6000), key);
+        // nothing is mapped past the keyboard
+        assert_eq!(bus.read_word(0x6001), word(0));
+    }
+
+    #[test]
+    fn test_devices_work() {
+        let mut bus = Bus::new(16);
+        bus.map(0x0000, RAM8::new()).unwrap();
+        bus.map(0x8000, Timer::new()).unwrap();
+        bus.map(0x8002, Uart::new()).unwrap();
+
+        // the timer counts every cycle and can be reloaded
+        bus.write_word(0x8000, &word(100));
+        bus.write_word(0x0001, &word(7));
+        assert_eq!(bus.read_word(0x8000), word(101));
+        assert_eq!(bus.read_word(0x0001), word(7));
+
+        bus.write_word(0x8002, &word(b'h' as u16));
+        bus.write_word(0x8002, &word(0x0169));  // only the low byte is sent
+        assert_eq!(bus.device_mut::<Uart>(0x8002).unwrap().take_output(), b"hi");
+
+        assert_eq!(bus.read_word(0x8003), word(0b10));
+        bus.device_mut::<Uart>(0x8002).unwrap().send(b"ok");
+        assert_eq!(bus.read_word(0x8003), word(0b11));
+        assert_eq!(bus.read_word(0x8002), word(b'o' as u16));
+        assert_eq!(bus.read_word(0x8002), word(b'k' as u16));
+        assert_eq!(bus.read_word(0x8003), word(0b10));
+        assert!(bus.device_mut::<Timer>(0x8002).is_none());
+    }
+
+    #[test]
+    fn test_nested_bus_works() {
+        let mut bus = Bus::new(16);
+        bus.map(0x0000, RAM8::new()).unwrap();
+        assert!(bus.map(0x0000, Bus::new(16)).is_err());
+
+        let mut io = Bus::new(9);
+        io.map(0x0100, Timer::new()).unwrap();
+        bus.map(0x0200, io).unwrap();
+        bus.write_word(0x0300, &word(5));
+        assert_eq!(bus.read_word(0x0300), word(5));
+        assert_eq!(bus.read_word(0x0300), word(6));
+    }
+
+    #[test]
+    fn test_map_errors_work() {
+        let mut bus = Bus::new(12);
+        assert_eq!(bus.map(0x0004, RAM8::new()), Err(BusError::Misaligned{ base: 4, words: 8 }));
+        assert_eq!(bus.map(0x1000, Keyboard::new()), Err(BusError::OutOfRange{ base: 0x1000, words: 1 }));
+        bus.map(0x0008, RAM8::new()).unwrap();
+        assert_eq!(bus.map(0x000f, Timer::new()), Err(BusError::Overlap{ base: 0xf, other: 8 }));
+        assert_eq!(bus.map(0x0000, RAM4096::new()), Err(BusError::Overlap{ base: 0, other: 8 }));
+        bus.map(0x0010, Timer::new()).unwrap();
+    }
+}
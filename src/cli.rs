@@ -0,0 +1,672 @@
+use std::collections::HashMap;
+use std::{fs, io, path};
+use std::io::Write;
+use nandputer::*;
+use memory::FastRAM;
+
+const USAGE: &str = "\
+usage: nandputer [COMMAND] [OPTIONS]
+
+commands:
+  repl                  interactive testbench (the default)
+  run PROGRAM           execute an RV32I program until it halts
+  asm SOURCE [OUTPUT]   assemble RV32I (.s) to a flat binary, or Hack (.asm) to .hack
+  disasm PROGRAM        disassemble an RV32I program
+  test SCRIPT           run a test script, exit status 1 on the first failed expectation;
+                        .tst scripts drive .hdl or built-in chips and check their .cmp file
+  dump PROGRAM          load a program, run it if --max-cycles is given, dump its memory
+  cost                  nand and DFF evaluations of one operation of each chip (needs the count feature)
+
+options:
+  --mem-size BYTES      CPU memory, a multiple of 4 up to 2MB (default 16384)
+  --ram-size BYTES      repl RAM chip: 16, 128 (default), 1024, 8192 or 32768
+  --max-cycles N        cycle limit for run, test and dump (default 1000000)
+  --trace               print every executed instruction
+  --fast-ram            behavioral RAM instead of the gate-level tree, for long runs
+  --pc ADDR             starting pc, overrides the ELF entry point
+  --cost                report the nand and DFF evaluations of a run, per instruction with --trace
+                        (needs the count feature)
+  --vcd FILE            write a waveform of the run for GTKWave (needs the vcd feature)
+  --watch PATHS         comma-separated signals or scopes for --vcd, e.g. pc,PC32,ALU.out
+  --start ADDR          first byte for disasm and dump (default 0)
+  --count BYTES         bytes for disasm and dump (default: the loaded image)
+  --format FORMAT       dump format: readmemh (default), hex or bin
+  -o FILE               output file for asm and dump
+
+PROGRAM is an ELF executable, RV32I assembly (.s), Intel HEX (.hex),
+$readmemh text (.mem) or a flat little endian binary.
+Numbers are decimal or 0x-prefixed hex.";
+
+const DEFAULT_MAX_CYCLES: usize = 1_000_000;
+
+/// Opts - Positional arguments and flags shared by every subcommand
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Opts {
+    positional: Vec<String>,
+    mem_size: Option<u32>,
+    ram_size: Option<u32>,
+    max_cycles: Option<usize>,
+    trace: bool,
+    fast_ram: bool,
+    cost: bool,
+    pc: Option<u32>,
+    vcd: Option<String>,
+    watch: Option<String>,
+    start: Option<u32>,
+    count: Option<u32>,
+    format: Option<String>,
+    output: Option<String>,
+}
+
+// decimal, or hex with a 0x prefix
+pub fn parse_num(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_opts(args: &[String]) -> Result<Opts, String> {
+    let mut opts = Opts::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || -> Result<String, String> {
+            args.next().cloned().ok_or_else(|| format!("{} needs a value", arg))
+        };
+        let num = |text: String| parse_num(&text).ok_or_else(|| format!("{} needs a number, got '{}'", arg, text));
+        match arg.as_str() {
+            "--mem-size" => opts.mem_size = Some(num(value()?)?),
+            "--ram-size" => opts.ram_size = Some(num(value()?)?),
+            "--max-cycles" => opts.max_cycles = Some(num(value()?)? as usize),
+            "--trace" => opts.trace = true,
+            "--fast-ram" => opts.fast_ram = true,
+            "--cost" => opts.cost = true,
+            "--pc" => opts.pc = Some(num(value()?)?),
+            "--vcd" => opts.vcd = Some(value()?),
+            "--watch" => opts.watch = Some(value()?),
+            "--start" => opts.start = Some(num(value()?)?),
+            "--count" => opts.count = Some(num(value()?)?),
+            "--format" => opts.format = Some(value()?),
+            "-o" => opts.output = Some(value()?),
+            flag if flag.starts_with('-') => return Err(format!("unknown option '{}'", flag)),
+            _ => opts.positional.push(arg.clone()),
+        }
+    }
+    Ok(opts)
+}
+
+fn one_positional<'a>(opts: &'a Opts, what: &str) -> Result<&'a str, String> {
+    match opts.positional.as_slice() {
+        [arg] => Ok(arg),
+        _ => Err(format!("expected one {}\n{}", what, USAGE)),
+    }
+}
+
+/// Window - The first size words of a memory, so --mem-size bounds the image loaders
+struct Window<'a, M: WordMemory> {
+    mem: &'a mut M,
+    size: usize,
+    end: usize,     // one past the highest word written
+}
+
+impl<M: WordMemory> WordMemory for Window<'_, M> {
+    fn size(&self) -> usize { self.size }
+
+    fn read_word(&mut self, addr: usize) -> [bool; 16] {
+        self.mem.read_word(addr)
+    }
+
+    fn write_word(&mut self, addr: usize, val: &[bool; 16]) {
+        self.end = self.end.max(addr + 1);
+        self.mem.write_word(addr, val);
+    }
+}
+
+/// Loaded - A CPU with a program in memory
+struct Loaded<B: RamBackend> {
+    cpu: Cpu<B>,
+    end: u32,       // one past the highest byte loaded
+    symbols: HashMap<String, u32>,
+}
+
+// one past the highest byte of any segment, None if that is past the 32-bit address space
+fn image_end(segments: &[elf::Segment]) -> Option<u32> {
+    segments.iter().try_fold(0, |end: u32, seg| Some(end.max(seg.paddr.checked_add(seg.data.len() as u32)?)))
+}
+
+// a CPU with --mem-size bytes of memory
+fn new_cpu<B: RamBackend>(opts: &Opts) -> Result<Cpu<B>, String> {
+    let mem_size = opts.mem_size.unwrap_or(cpu::MEM_BYTES);
+    if mem_size > cpu::MAX_MEM_BYTES {
+        return Err(format!("--mem-size {} is larger than the {} byte CPU memory", mem_size, cpu::MAX_MEM_BYTES));
+    }
+    if !mem_size.is_multiple_of(4) {
+        return Err(format!("--mem-size {} is not a whole number of words", mem_size));
+    }
+    let mut cpu = Cpu::with_backend();
+    cpu.set_mem_size(mem_size);
+    Ok(cpu)
+}
+
+fn load_program<B: RamBackend>(path: &str, opts: &Opts) -> Result<Loaded<B>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut cpu = new_cpu(opts)?;
+    let mem_size = cpu.mem_size();
+    let err = |e: String| format!("{}: {}", path, e);
+
+    let mut symbols = HashMap::new();
+    let end;
+    if bytes.starts_with(b"\x7fELF") {
+        let elf = elf::parse(&bytes).map_err(|e| err(e.to_string()))?;
+        end = match image_end(&elf.segments) {
+            Some(end) if end <= mem_size => end,
+            Some(end) => return Err(err(format!("image ends at 0x{:x}, past --mem-size {}", end, mem_size))),
+            None => return Err(err(format!("image ends past 0xffffffff, past --mem-size {}", mem_size))),
+        };
+        elf::load(&mut cpu, &elf).map_err(|e| err(e.to_string()))?;
+        symbols = elf.symbols;
+    } else {
+        let ext = path::Path::new(path).extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let mut window = Window{ mem: cpu.mem(), size: mem_size as usize / 2, end: 0 };
+        match ext {
+            "s" | "S" => {
+                let image = rv_asm::assemble(&String::from_utf8_lossy(&bytes)).map_err(|e| err(e.to_string()))?;
+                memfile::load_bin(&mut window, &image.bytes, 0).map_err(|e| err(e.to_string()))?;
+                symbols = image.symbols;
+            }
+            "hex" => { memfile::load_ihex(&mut window, &String::from_utf8_lossy(&bytes), 0).map_err(|e| err(e.to_string()))?; }
+            "mem" | "vmem" => { memfile::load_readmemh(&mut window, &String::from_utf8_lossy(&bytes), 0).map_err(|e| err(e.to_string()))?; }
+            _ => { memfile::load_bin(&mut window, &bytes, 0).map_err(|e| err(e.to_string()))?; }
+        }
+        end = 2 * window.end as u32;
+    }
+
+    if let Some(pc) = opts.pc {
+        cpu.set_pc(&bytes_to_boollist32(&split_u32(pc)));
+    }
+    Ok(Loaded{ cpu, end, symbols })
+}
+
+fn print_regs<B: RamBackend>(cpu: &Cpu<B>) {
+    for i in 0..32 {
+        print!("{:>4}: {:08x}", rv_asm::ABI[i], boollist_to_u32(&cpu.read_reg(i)));
+        if i % 4 == 3 { println!(); } else { print!("  "); }
+    }
+}
+
+// --trace prints a disassembled line for every instruction executed
+fn stdout_trace() -> Option<cpu::TraceSink> {
+    Some(Box::new(|pc, inst| println!("{}", disasm::trace_line(pc, inst))))
+}
+
+fn run_cpu<B: RamBackend>(cpu: &mut Cpu<B>, opts: &Opts) -> usize {
+    let max_cycles = opts.max_cycles.unwrap_or(DEFAULT_MAX_CYCLES);
+    cpu.set_trace(if opts.trace { stdout_trace() } else { None });
+    let cycles = match opts.cost && opts.trace {
+        // one cycle at a time, so each trace line is followed by its cost
+        true => {
+            let mut cycles = 0;
+            while cycles < max_cycles && !cpu.is_halted() {
+                println!("{:>12}{}", "", count::measure(|| cpu.clk_cycle()).1);
+                cycles += 1;
+            }
+            cycles
+        }
+        false => cpu.run(max_cycles),
+    };
+    cpu.set_trace(None);
+    cycles
+}
+
+fn needs_count(what: &str) -> Result<(), String> {
+    match count::ENABLED {
+        true => Ok(()),
+        false => Err(format!("{} needs nandputer built with --features count", what)),
+    }
+}
+
+fn cmd_run<B: RamBackend>(opts: &Opts) -> Result<(), String> {
+    let mut loaded = load_program::<B>(one_positional(opts, "PROGRAM")?, opts)?;
+    if opts.vcd.is_some() && !vcd::ENABLED {
+        return Err("--vcd needs nandputer built with --features vcd".to_string());
+    }
+    let watch = opts.watch.as_deref().map_or(Vec::new(), |paths| paths.split(',').collect());
+    if opts.cost { needs_count("--cost")?; }
+    if opts.vcd.is_some() { vcd::start("Cpu", &watch); }
+    let (cycles, cost) = count::measure(|| run_cpu(&mut loaded.cpu, opts));
+    if let (Some(path), Some(trace)) = (&opts.vcd, vcd::stop()) {
+        fs::write(path, trace.to_string()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    print_regs(&loaded.cpu);
+    let pc = boollist_to_u32(&loaded.cpu.pc());
+    if !loaded.cpu.is_halted() {
+        return Err(format!("no halt after {} cycles, pc {:08x}", cycles, pc));
+    }
+    println!("halted after {} cycles at pc {:08x}", cycles, pc);
+    if opts.cost {
+        println!("{} evaluations, {} nand per cycle", cost, cost.nand / cycles.max(1) as u64);
+    }
+    Ok(())
+}
+
+// one operation of each chip, counted from a fresh chip
+fn chip_costs() -> Vec<(&'static str, count::Counts)> {
+    use adder::{Adder32, RippleCarry, CarryLookahead, CarrySelect};
+    use memory::RamChip;
+    fn ram<C: RamChip>() -> count::Counts {
+        let mut chip = C::new();
+        count::measure(|| chip.clk_cycle(&[true; 16], &[false; 32], true)).1
+    }
+    let (a, b) = (&[true; 32], &[false; 32]);
+    let add = adder::RISCvCtrl::new(true, false, false, false, false);
+    let addi = rv_asm::assemble("addi a0, a0, 1").unwrap();
+    let mut cpu = Cpu::new();
+    cpu.load_program(&addi.words());
+    let mut hack = HackCpu::new();
+    let (mut reg, mut pc, mut regs) = (memory::Register32::new(), memory::PC32::new(), memory::RegisterFile32::new());
+    let five = [true, false, true, false, false];
+    vec![
+        ("add32 RippleCarry", count::measure(|| RippleCarry::add32_carry(a, b, false)).1),
+        ("add32 CarryLookahead", count::measure(|| CarryLookahead::add32_carry(a, b, false)).1),
+        ("add32 CarrySelect", count::measure(|| CarrySelect::add32_carry(a, b, false)).1),
+        ("inc32", count::measure(|| adder::inc32(a)).1),
+        ("riscv_alu add RippleCarry", count::measure(|| adder::riscv_alu_with::<RippleCarry>(a, b, &add)).1),
+        ("riscv_alu add CarryLookahead", count::measure(|| adder::riscv_alu_with::<CarryLookahead>(a, b, &add)).1),
+        ("riscv_alu add CarrySelect", count::measure(|| adder::riscv_alu_with::<CarrySelect>(a, b, &add)).1),
+        ("hack_alu x+y", count::measure(|| adder::hack_alu(&[true; 16], &[false; 16], &adder::HackCtrl::new(false, false, false, false, true, false))).1),
+        ("Register32", count::measure(|| reg.clk_cycle(a, true)).1),
+        ("PC32", count::measure(|| pc.clk_cycle(a, true, true, false, false)).1),
+        ("RegisterFile32 write", count::measure(|| regs.clk_cycle(&five, &five, &five, a, true)).1),
+        ("RAM8", ram::<memory::RAM8>()),
+        ("RAM64", ram::<memory::RAM64>()),
+        ("RAM512", ram::<memory::RAM512>()),
+        ("RAM4096", ram::<memory::RAM4096>()),
+        ("RAM16384", ram::<memory::RAM16384>()),
+        ("HackCpu D=A", count::measure(|| hack.clk_cycle(&[false; 16], &bytes_to_boollist(&[0xec, 0x10]), false)).1),
+        ("Cpu addi", count::measure(|| cpu.clk_cycle()).1),
+    ]
+}
+
+fn cmd_cost() -> Result<(), String> {
+    needs_count("cost")?;
+    println!("{:<30}{:>12}{:>8}", "chip", "nand", "dff");
+    for (chip, cost) in chip_costs() {
+        println!("{:<30}{:>12}{:>8}", chip, cost.nand, cost.dff);
+    }
+    Ok(())
+}
+
+// default output: the input path with a new extension
+fn output_path(input: &str, opts: &Opts, ext: &str) -> String {
+    opts.output.clone().unwrap_or_else(|| path::Path::new(input).with_extension(ext).to_string_lossy().into_owned())
+}
+
+fn cmd_asm(opts: &Opts) -> Result<(), String> {
+    // an output path may also follow the source, as the old hack-asm command took it
+    let (input, opts) = match opts.positional.as_slice() {
+        [input, output] if opts.output.is_none() => (input.as_str(), &Opts{ output: Some(output.clone()), ..Default::default() }),
+        _ => (one_positional(opts, "SOURCE")?, opts),
+    };
+    let src = fs::read_to_string(input).map_err(|e| format!("{}: {}", input, e))?;
+    // .asm is nand2tetris Hack assembly, anything else RV32I
+    let (output, data, what) = if input.ends_with(".asm") {
+        let words = hack_asm::assemble(&src).map_err(|e| format!("{}: {}", input, e))?;
+        (output_path(input, opts, "hack"), hack_asm::to_hack_text(&words).into_bytes(), format!("{} words", words.len()))
+    } else {
+        let image = rv_asm::assemble(&src).map_err(|e| format!("{}: {}", input, e))?;
+        let len = image.bytes.len();
+        (output_path(input, opts, "bin"), image.bytes, format!("{} bytes", len))
+    };
+    fs::write(&output, data).map_err(|e| format!("{}: {}", output, e))?;
+    println!("Wrote {} to {}", what, output);
+    Ok(())
+}
+
+// --start/--count as a byte range, defaulting to the loaded image
+fn byte_range<B: RamBackend>(loaded: &Loaded<B>, opts: &Opts) -> (u32, u32) {
+    let start = opts.start.unwrap_or(0);
+    let count = opts.count.unwrap_or(loaded.end.saturating_sub(start));
+    (start, count)
+}
+
+fn cmd_disasm<B: RamBackend>(opts: &Opts) -> Result<(), String> {
+    let mut loaded = load_program::<B>(one_positional(opts, "PROGRAM")?, opts)?;
+    let (start, count) = byte_range(&loaded, opts);
+    let mut labels: HashMap<u32, Vec<&String>> = HashMap::new();
+    for (name, addr) in &loaded.symbols {
+        labels.entry(*addr).or_default().push(name);
+    }
+
+    for pc in (start & !3..start.saturating_add(count)).step_by(4) {
+        if pc >= loaded.cpu.mem_size() { break; }
+        if let Some(names) = labels.get_mut(&pc) {
+            names.sort();
+            for name in names.iter() { println!("{}:", name); }
+        }
+        let inst = loaded.cpu.read_mem(&bytes_to_boollist32(&split_u32(pc)));
+        println!("{}", disasm::trace_line(pc, boollist_to_u32(&inst)));
+    }
+    Ok(())
+}
+
+fn cmd_dump<B: RamBackend>(opts: &Opts) -> Result<(), String> {
+    let mut loaded = load_program::<B>(one_positional(opts, "PROGRAM")?, opts)?;
+    if opts.max_cycles.is_some() {
+        run_cpu(&mut loaded.cpu, opts);
+    }
+    let (start, count) = byte_range(&loaded, opts);
+    if start % 2 != 0 || count % 2 != 0 {
+        return Err("--start and --count must be even, memory is dumped in 16-bit words".to_string());
+    }
+
+    let mem = loaded.cpu.mem();
+    let (start, count) = (start as usize / 2, count as usize / 2);
+    let data = match opts.format.as_deref().unwrap_or("readmemh") {
+        "readmemh" => memfile::dump_readmemh(mem, start, count).map(String::into_bytes),
+        "hex" => memfile::dump_ihex(mem, start, count).map(String::into_bytes),
+        "bin" => memfile::dump_bin(mem, start, count),
+        format => return Err(format!("unknown dump format '{}'", format)),
+    }.map_err(|e| e.to_string())?;
+
+    match &opts.output {
+        Some(output) => fs::write(output, data).map_err(|e| format!("{}: {}", output, e)),
+        None => io::stdout().write_all(&data).map_err(|e| e.to_string()),
+    }
+}
+
+fn expect_eq(what: &str, expected: u32, got: u32) -> Result<(), String> {
+    if expected != got {
+        return Err(format!("expected {} = 0x{:08x}, got 0x{:08x}", what, expected, got));
+    }
+    Ok(())
+}
+
+/// Run a test script against the RV32I CPU, returning the number of expectations checked
+/// One command per line, # starts a comment:
+///   load PROGRAM          fresh CPU with PROGRAM, relative to the script's directory
+///   pc ADDR               set the pc
+///   run [CYCLES]          clock until halt or CYCLES (default --max-cycles)
+///   expect REG VALUE      register by x-name or ABI name, or pc
+///   expect mem ADDR VALUE memory word containing ADDR
+///   expect halted
+fn run_script<B: RamBackend>(text: &str, dir: &path::Path, opts: &Opts) -> Result<usize, String> {
+    let mut cpu: Option<Cpu<B>> = None;
+    let mut checked = 0;
+    for (i, line) in text.lines().enumerate() {
+        let err = |msg: String| format!("line {}: {}", i + 1, msg);
+        let tokens: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        if tokens.is_empty() { continue; }
+        let num = |text: &str| parse_num(text).ok_or_else(|| err(format!("bad number '{}'", text)));
+
+        if let ["load", program] = tokens.as_slice() {
+            let path = dir.join(program);
+            cpu = Some(load_program(&path.to_string_lossy(), opts).map_err(err)?.cpu);
+            continue;
+        }
+        let cpu = cpu.as_mut().ok_or_else(|| err("no program loaded".to_string()))?;
+        match tokens.as_slice() {
+            ["pc", addr] => cpu.set_pc(&bytes_to_boollist32(&split_u32(num(addr)?))),
+            ["run"] => { run_cpu(cpu, opts); }
+            ["run", cycles] => { run_cpu(cpu, &Opts{ max_cycles: Some(num(cycles)? as usize), ..opts.clone() }); }
+            ["expect", "halted"] => {
+                if !cpu.is_halted() { return Err(err("expected the CPU to be halted".to_string())); }
+                checked += 1;
+            }
+            ["expect", "pc", val] => {
+                expect_eq("pc", num(val)?, boollist_to_u32(&cpu.pc())).map_err(err)?;
+                checked += 1;
+            }
+            ["expect", "mem", addr, val] => {
+                let got = cpu.read_mem(&bytes_to_boollist32(&split_u32(num(addr)?)));
+                expect_eq(&format!("mem[{}]", addr), num(val)?, boollist_to_u32(&got)).map_err(err)?;
+                checked += 1;
+            }
+            ["expect", reg, val] => {
+                let idx = rv_asm::parse_reg(reg).ok_or_else(|| err(format!("unknown register '{}'", reg)))?;
+                expect_eq(reg, num(val)?, boollist_to_u32(&cpu.read_reg(idx as usize))).map_err(err)?;
+                checked += 1;
+            }
+            _ => return Err(err(format!("unknown command '{}'", line.trim()))),
+        }
+    }
+    Ok(checked)
+}
+
+fn cmd_test<B: RamBackend>(opts: &Opts) -> Result<(), String> {
+    let script = one_positional(opts, "SCRIPT")?;
+    if script.ends_with(".tst") {
+        let report = tst::run_file(path::Path::new(script)).map_err(|e| format!("{}: {}", script, e))?;
+        match report.compared {
+            Some(cmp) => println!("{}: {} lines match {}", script, report.lines.len(), cmp),
+            None => println!("{}: {} lines output", script, report.lines.len()),
+        }
+        return Ok(());
+    }
+    let text = fs::read_to_string(script).map_err(|e| format!("{}: {}", script, e))?;
+    let dir = path::Path::new(script).parent().unwrap_or(path::Path::new("."));
+    let checked = run_script::<B>(&text, dir, opts).map_err(|e| format!("{}: {}", script, e))?;
+    println!("{}: {} expectations passed", script, checked);
+    Ok(())
+}
+
+// the repl RAM chip for --ram-size, in bytes of 16-bit words
+fn repl_ram(ram_size: Option<u32>, fast_ram: bool) -> Result<Box<dyn WordMemory>, String> {
+    Ok(match (ram_size.unwrap_or(128), fast_ram) {
+        (16, false) => Box::new(memory::RAM8::new()),
+        (128, false) => Box::new(memory::RAM64::new()),
+        (1024, false) => Box::new(memory::RAM512::new()),
+        (8192, false) => Box::new(memory::RAM4096::new()),
+        (32768, false) => Box::new(memory::RAM16384::new()),
+        (16, true) => Box::new(FastRAM::<8>::new()),
+        (128, true) => Box::new(FastRAM::<64>::new()),
+        (1024, true) => Box::new(FastRAM::<512>::new()),
+        (8192, true) => Box::new(FastRAM::<4096>::new()),
+        (32768, true) => Box::new(FastRAM::<16384>::new()),
+        (size, _) => return Err(format!("no RAM chip of {} bytes, pick 16, 128, 1024, 8192 or 32768", size)),
+    })
+}
+
+fn prompt(text: &str) -> Option<String> {
+    print!("{}", text);
+    io::stdout().flush().unwrap();
+    let mut input = String::new();
+    match io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(input),
+    }
+}
+
+fn repl<B: RamBackend>(opts: &Opts) -> Result<(), String> {
+    let mut mem = repl_ram(opts.ram_size, opts.fast_ram)?;
+    let mut cpu = new_cpu::<B>(opts)?;
+
+    while let Some(input) = prompt("nandputer> ") {
+        let input_switch: Vec<&str> = input.split_whitespace().collect();
+
+        match input_switch.as_slice() {
+            ["m"] => {
+                for i in 0..mem.size().min(64) {
+                    if i % 4 == 0 { print!("\n{:02x}: ", i); }
+                    print!("\t{:04x}", boollist_to_u32(&mem.read_word(i)));
+                }
+                println!();
+            }
+            ["w"] => {
+                let Some(addr) = prompt("addr# ").and_then(|input| input.trim().parse::<u16>().ok()) else {
+                    println!("Invalid addr input");
+                    continue;
+                };
+                let Some(data) = prompt("data# ").and_then(|input| input.trim().parse::<u16>().ok()) else {
+                    println!("Invalid data input");
+                    continue;
+                };
+                if addr as usize >= mem.size() {
+                    println!("Invalid addr input");
+                    continue;
+                }
+                mem.write_word(addr as usize, &bytes_to_boollist(&split_u16(data)));
+                println!("Wrote {} to {}", data, addr);
+            }
+            ["l", path] => {
+                match load_program(path, opts) {
+                    Ok(loaded) => {
+                        cpu = loaded.cpu;
+                        println!("Loaded {}", path);
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            ["d", addr, count] => {
+                let (Some(addr), Some(count)) = (parse_num(addr), parse_num(count)) else {
+                    println!("usage: d ADDR COUNT");
+                    continue;
+                };
+                for i in 0..count {
+                    let pc = (addr & !3).wrapping_add(4 * i);
+                    let inst = cpu.read_mem(&bytes_to_boollist32(&split_u32(pc)));
+                    println!("{}", disasm::trace_line(pc, boollist_to_u32(&inst)));
+                }
+            }
+            ["r", cycles] => {
+                let Some(cycles) = parse_num(cycles) else {
+                    println!("usage: r CYCLES");
+                    continue;
+                };
+                cpu.set_trace(stdout_trace());
+                let ran = cpu.run(cycles as usize);
+                cpu.set_trace(None);
+                println!("Ran {} cycles{}", ran, if cpu.is_halted() { ", halted" } else { "" });
+            }
+            ["q"] => { println!("Terminating..."); return Ok(()); }
+            _   => println!("Invalid input"),
+        }
+    }
+    Ok(())
+}
+
+fn dispatch<B: RamBackend>(cmd: &str, opts: &Opts) -> Result<(), String> {
+    match cmd {
+        "repl" => repl::<B>(opts),
+        "run" => cmd_run::<B>(opts),
+        "asm" | "hack-asm" => cmd_asm(opts),
+        "disasm" => cmd_disasm::<B>(opts),
+        "test" => cmd_test::<B>(opts),
+        "dump" => cmd_dump::<B>(opts),
+        "cost" => cmd_cost(),
+        "help" => { println!("{}", USAGE); Ok(()) }
+        _ => Err(format!("unknown command '{}'\n{}", cmd, USAGE)),
+    }
+}
+
+/// Entry point for the nandputer binary, returns the process exit status
+pub fn main(args: &[String]) -> i32 {
+    let (cmd, rest) = match args.split_first() {
+        Some((cmd, rest)) if !cmd.starts_with('-') => (cmd.as_str(), rest),
+        _ => ("repl", args),
+    };
+    let res = parse_opts(rest).and_then(|opts| match opts.fast_ram {
+        true => dispatch::<Behavioral>(cmd, &opts),
+        false => dispatch::<GateLevel>(cmd, &opts),
+    });
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("nandputer: {}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_opts_works() {
+        let opts = parse_opts(&args("prog.s --max-cycles 0x40 --trace --fast-ram --pc 16 -o out.bin --mem-size 0x8000 --ram-size 8192")).unwrap();
+        assert_eq!(opts, Opts{
+            positional: vec!["prog.s".to_string()],
+            mem_size: Some(0x8000),
+            ram_size: Some(8192),
+            max_cycles: Some(64),
+            trace: true,
+            fast_ram: true,
+            pc: Some(16),
+            output: Some("out.bin".to_string()),
+            ..Default::default()
+        });
+        assert!(parse_opts(&args("--max-cycles")).is_err());
+        assert!(parse_opts(&args("--pc zz")).is_err());
+        assert!(parse_opts(&args("--bogus")).is_err());
+    }
+
+    #[test]
+    fn test_sizes_work() {
+        // --ram-size picks the repl chip, --mem-size the CPU memory, each with its own default
+        assert_eq!(repl_ram(None, true).unwrap().size(), 64);
+        assert_eq!(repl_ram(Some(8192), false).unwrap().size(), 4096);
+        assert!(repl_ram(Some(16384), true).is_err());
+        assert_eq!(new_cpu::<Behavioral>(&Opts::default()).unwrap().mem_size(), cpu::MEM_BYTES);
+        assert_eq!(new_cpu::<Behavioral>(&Opts{ mem_size: Some(0x8000), ..Default::default() }).unwrap().mem_size(), 0x8000);
+        assert!(new_cpu::<Behavioral>(&Opts{ mem_size: Some(6), ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_image_end_works() {
+        let seg = |paddr: u32, len: usize| elf::Segment{ paddr, data: vec![0; len] };
+        assert_eq!(image_end(&[]), Some(0));
+        assert_eq!(image_end(&[seg(0x100, 8), seg(0x10, 4)]), Some(0x108));
+        // a high p_paddr must not wrap around under --mem-size
+        assert_eq!(image_end(&[seg(0x10, 4), seg(0xffff_fffc, 8)]), None);
+    }
+
+    #[test]
+    fn test_run_script_works() {
+        let dir = std::env::temp_dir().join(format!("nandputer-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("sum.s"), "
+                li a0, 4
+                li a1, 0
+        loop:   add a1, a1, a0
+                addi a0, a0, -1
+                bne a0, zero, loop
+                sw a1, 64(zero)
+                ecall
+        ").unwrap();
+
+        let script = "
+            # sum 4..1
+            load sum.s
+            run 4
+            expect a1 4
+            run
+            expect halted
+            expect x11 10
+            expect pc 0x18
+            expect mem 64 10
+        ";
+        assert_eq!(run_script::<GateLevel>(script, &dir, &Opts::default()), Ok(5));
+        assert_eq!(run_script::<Behavioral>(script, &dir, &Opts::default()), Ok(5));
+        // run N keeps the other flags, --trace included
+        assert_eq!(run_script::<Behavioral>(script, &dir, &Opts{ trace: true, ..Default::default() }), Ok(5));
+        assert_eq!(run_script::<Behavioral>("load sum.s\nrun\nexpect a1 11", &dir, &Opts::default()),
+                   Err("line 3: expected a1 = 0x0000000b, got 0x0000000a".to_string()));
+        assert!(run_script::<Behavioral>("run", &dir, &Opts::default()).is_err());
+        assert!(run_script::<Behavioral>("load sum.s\nexpect q9 0", &dir, &Opts::default()).is_err());
+
+        // --mem-size bounds where the image may land
+        let small = Opts{ mem_size: Some(16), ..Default::default() };
+        assert!(load_program::<Behavioral>(&dir.join("sum.s").to_string_lossy(), &small).is_err());
+        let odd = Opts{ mem_size: Some(1022), ..Default::default() };
+        assert!(load_program::<Behavioral>(&dir.join("sum.s").to_string_lossy(), &odd).is_err());
+        let big = Opts{ mem_size: Some(cpu::MAX_MEM_BYTES), ..Default::default() };
+        assert_eq!(load_program::<Behavioral>(&dir.join("sum.s").to_string_lossy(), &big).unwrap().cpu.mem_size(), cpu::MAX_MEM_BYTES);
+        let huge = Opts{ mem_size: Some(2 * cpu::MAX_MEM_BYTES), ..Default::default() };
+        assert!(load_program::<Behavioral>(&dir.join("sum.s").to_string_lossy(), &huge).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
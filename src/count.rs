@@ -0,0 +1,179 @@
+// Evaluation counts of the two primitives everything else is built from:
+// gates::nand and DFF::clk_cycle. With the count feature both bump a per-thread
+// counter, so measure(|| adder::add32(..)) is the cost of one add32 and
+// total() the cost of a whole run. Without it the primitives are left
+// untouched and every count stays zero.
+use std::cell::Cell;
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub};
+
+thread_local! {
+    static NAND: Cell<u64> = const { Cell::new(0) };
+    static DFF: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Whether the primitives were built with the counters
+pub const ENABLED: bool = cfg!(feature = "count");
+
+/// Counts - nand and DFF evaluations
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub nand: u64,
+    pub dff: u64,
+}
+
+impl Add for Counts {
+    type Output = Counts;
+    fn add(self, other: Counts) -> Counts {
+        Counts{ nand: self.nand + other.nand, dff: self.dff + other.dff }
+    }
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Counts) {
+        *self = *self + other;
+    }
+}
+
+// saturating, so total() - before after a reset is zero rather than a panic
+impl Sub for Counts {
+    type Output = Counts;
+    fn sub(self, other: Counts) -> Counts {
+        Counts{ nand: self.nand.saturating_sub(other.nand), dff: self.dff.saturating_sub(other.dff) }
+    }
+}
+
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} nand, {} dff", self.nand, self.dff)
+    }
+}
+
+/// Called by gates::nand
+#[cfg_attr(not(feature = "count"), allow(dead_code))]
+#[inline(always)]
+pub(crate) fn tally_nand() {
+    NAND.with(|n| n.set(n.get() + 1));
+}
+
+/// Called by DFF::clk_cycle
+#[cfg_attr(not(feature = "count"), allow(dead_code))]
+#[inline(always)]
+pub(crate) fn tally_dff() {
+    DFF.with(|n| n.set(n.get() + 1));
+}
+
+/// Evaluations on this thread since it started or since the last reset
+pub fn total() -> Counts {
+    Counts{ nand: NAND.with(Cell::get), dff: DFF.with(Cell::get) }
+}
+
+pub fn reset() {
+    set(Counts::default());
+}
+
+fn set(counts: Counts) {
+    NAND.with(|n| n.set(counts.nand));
+    DFF.with(|n| n.set(counts.dff));
+}
+
+/// Run f and return what it evaluated, leaving the running total to include it
+/// f starts from zero and the total is restored afterwards, so measures nest and
+/// a reset inside f only drops what f had evaluated before it.
+pub fn measure<R>(f: impl FnOnce() -> R) -> (R, Counts) {
+    let before = total();
+    set(Counts::default());
+    let res = f();
+    let cost = total();
+    set(before + cost);
+    (res, cost)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "count")]
+    use crate::*;
+
+    #[test]
+    fn test_counts_arithmetic_works() {
+        let a = Counts{ nand: 10, dff: 2 };
+        let b = Counts{ nand: 3, dff: 1 };
+        assert_eq!(a + b, Counts{ nand: 13, dff: 3 });
+        assert_eq!(a - b, Counts{ nand: 7, dff: 1 });
+        assert_eq!(b - a, Counts::default());
+        let mut c = Counts::default();
+        c += a;
+        c += b;
+        assert_eq!(c, a + b);
+        assert_eq!(a.to_string(), "10 nand, 2 dff");
+    }
+
+    #[test]
+    #[cfg(feature = "count")]
+    fn test_gates_and_registers_count() {
+        assert_eq!(measure(|| gates::not(true)).1, Counts{ nand: 1, dff: 0 });
+        // or is three nands, and two, nand one
+        assert_eq!(measure(|| gates::xor(true, false)).1, Counts{ nand: 6, dff: 0 });
+        assert_eq!(measure(|| gates::xor16(&[true; 16], &[false; 16])).1.nand, 16 * 6);
+
+        // a BitRegister is a mux in front of its DFF
+        let mut reg = memory::Register::new();
+        let mux = measure(|| gates::mux(false, true, true)).1;
+        let (_, cost) = measure(|| reg.clk_cycle(&[true; 16], true));
+        assert_eq!(cost, Counts{ nand: 16 * mux.nand, dff: 16 });
+
+        let before = total();
+        reg.clk_cycle(&[false; 16], false);
+        reg.clk_cycle(&[false; 16], false);
+        assert_eq!(total() - before, cost + cost);
+        reset();
+        assert_eq!(total(), Counts::default());
+    }
+
+    #[test]
+    #[cfg(feature = "count")]
+    fn test_measure_nests() {
+        let not = Counts{ nand: 1, dff: 0 };
+        let before = total();
+        let (inner, outer) = measure(|| {
+            gates::not(true);
+            let inner = measure(|| { gates::not(true); gates::not(false); }).1;
+            gates::not(true);
+            inner
+        });
+        assert_eq!(inner, not + not);
+        assert_eq!(outer, not + not + not + not);
+        assert_eq!(total() - before, outer);
+
+        // a reset inside only drops what came before it in the closure
+        let before = total();
+        let cost = measure(|| { gates::not(true); reset(); gates::not(true); }).1;
+        assert_eq!(cost, not);
+        assert_eq!(total(), before + not);
+        let cost = measure(|| { measure(|| { gates::not(true); reset(); }); gates::not(true); }).1;
+        assert_eq!(cost, not);
+    }
+
+    #[test]
+    #[cfg(feature = "count")]
+    fn test_adders_compare() {
+        use adder::{Adder32, RippleCarry, CarryLookahead, CarrySelect};
+        let val1 = utils::bytes_to_boollist32(&utils::split_u32(0x1234_5678));
+        let val2 = utils::bytes_to_boollist32(&utils::split_u32(0x0fed_cba9));
+        let ripple = measure(|| RippleCarry::add32_carry(&val1, &val2, false)).1;
+        let lookahead = measure(|| CarryLookahead::add32_carry(&val1, &val2, false)).1;
+        let select = measure(|| CarrySelect::add32_carry(&val1, &val2, false)).1;
+        // combinational, and the cost of a gate network does not depend on its inputs
+        assert_eq!((ripple.dff, lookahead.dff, select.dff), (0, 0, 0));
+        assert_eq!(measure(|| RippleCarry::add32_carry(&[true; 32], &[true; 32], true)).1, ripple);
+        // a full adder is two half adders (xor 6 + and 2) and an xor, 22 nands; plus the overflow xor
+        assert_eq!(ripple.nand, 32 * 22 + 6);
+        // the upper three blocks ripple twice and pick with 16 muxes of 8 nands each
+        assert_eq!(select.nand, 8 * 22 + 3 * (2 * 8 * 22 + 16 * 8) + 6);
+        // lookahead trades ripple's chain for wider gates and pays for it in evaluations
+        assert_eq!(lookahead.nand, 1406);
+        assert!(ripple.nand < lookahead.nand && lookahead.nand < select.nand);
+        assert_eq!(measure(|| adder::add32(&val1, &val2)).1, lookahead);
+    }
+}
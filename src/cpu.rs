@@ -0,0 +1,441 @@
+use std::fmt;
+use crate::*;
+use memory::{PC32, RegisterFile32, ByteRAM, RamBackend, GateLevel};
+use bus::{Bus, Device};
+
+const ZERO: [bool; 32] = [false; 32];
+const WORD: (bool, bool) = (true, false);
+
+/// Default size of the CPU's main memory in bytes
+pub const MEM_BYTES: u32 = 16384;
+/// Largest main memory set_mem_size accepts
+pub const MAX_MEM_BYTES: u32 = memory::BYTE_RAM_MAX;
+/// Byte address of word 0 of the I/O bus
+pub const IO_BASE: u32 = 0x8000_0000;
+
+/// BranchCond - Evaluate BEQ/BNE/BLT/BGE/BLTU/BGEU from the ALU flags of rs1-rs2
+/// Function: funct3[2] picks less-than over equality, funct3[1] unsigned,
+///           funct3[0] inverts the condition
+fn branch_taken(flags: &adder::RISCvOut, funct3: &[bool; 3]) -> bool {
+    let lt = gates::mux(gates::xor(flags.ng, flags.ov), gates::not(flags.cr), funct3[1]);
+    gates::xor(gates::mux(flags.zr, lt, funct3[2]), funct3[0])
+}
+
+/// TraceSink - Receives (pc, instruction) of every instruction the CPU executes
+pub type TraceSink = Box<dyn FnMut(u32, u32) + Send>;
+
+// IO: a 16-bit bus word as a load result, lb/lbu take its low byte, lh/lhu/lw all of it
+fn io_extend(val: &[bool; 16], width: (bool, bool), signed: bool) -> [bool; 32] {
+    let byte = gates::not(gates::or(width.0, width.1));
+    let sign = gates::and(gates::mux(val[15], val[7], byte), signed);
+    let mut res = [sign; 32];
+    res[0..8].copy_from_slice(&val[0..8]);
+    for i in 8..16 {
+        res[i] = gates::mux(val[i], sign, byte);
+    }
+    res
+}
+
+/// Cpu - Single-cycle RV32I processor
+/// State: pc (PC32), x0..x31 (RegisterFile32), 16KB to 2MB main memory (ByteRAM on backend B),
+///        an I/O bus (bus::Bus) whose word n is at byte IO_BASE + 2n
+/// Function: each clk_cycle fetches the word at pc, decodes it with decoder::decode,
+///           executes it through the ALU and latches rd, memory and pc.
+///           Loads and stores with address bit 31 set go to the I/O bus instead of memory,
+///           one bus word per access: stores write rs2[15..0], lb/lbu read the word's low byte.
+///           ECALL, EBREAK, unknown opcodes and misaligned or out of range accesses halt the CPU.
+pub struct Cpu<B: RamBackend = GateLevel> {
+    pc: PC32,
+    regs: RegisterFile32,
+    mem: ByteRAM<B>,
+    io: Bus,
+    halted: bool,
+    trace: Option<TraceSink>,
+}
+
+impl<B: RamBackend> fmt::Debug for Cpu<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cpu")
+            .field("pc", &self.pc)
+            .field("regs", &self.regs)
+            .field("mem", &self.mem)
+            .field("io", &self.io)
+            .field("halted", &self.halted)
+            .field("trace", &self.trace.is_some())
+            .finish()
+    }
+}
+
+impl Cpu {
+    pub fn new() -> Cpu {
+        Cpu::with_backend()
+    }
+}
+
+impl<B: RamBackend> Cpu<B> {
+    /// A CPU whose main memory is built on backend B, e.g. memory::Behavioral
+    pub fn with_backend() -> Cpu<B> {
+        Cpu{ pc: PC32::new(), regs: RegisterFile32::new(), mem: ByteRAM::with_backend(), io: Bus::new(16),
+             halted: false, trace: None }
+    }
+
+    /// Write program words into memory starting at address 0
+    pub fn load_program(&mut self, program: &[u32]) {
+        for (i, word) in program.iter().enumerate() {
+            let addr = utils::bytes_to_boollist32(&utils::split_u32(4 * i as u32));
+            let val = utils::bytes_to_boollist32(&utils::split_u32(*word));
+            self.mem.clk_cycle(&val, &addr, WORD, false, true);
+        }
+    }
+
+    pub fn pc(&self) -> [bool; 32] {
+        self.pc.read()
+    }
+
+    pub fn set_pc(&mut self, pc: &[bool; 32]) {
+        self.pc.clk_cycle(pc, false, false, true, false);
+    }
+
+    pub fn read_reg(&self, idx: usize) -> [bool; 32] {
+        self.regs.read(idx)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Read the memory word containing addr
+    pub fn read_mem(&mut self, addr: &[bool; 32]) -> [bool; 32] {
+        let mut aligned = *addr;
+        aligned[0] = false;
+        aligned[1] = false;
+        self.mem.clk_cycle(&ZERO, &aligned, WORD, false, false).0
+    }
+
+    /// Write the memory word containing addr
+    pub fn write_mem(&mut self, addr: &[bool; 32], val: &[bool; 32]) {
+        let mut aligned = *addr;
+        aligned[0] = false;
+        aligned[1] = false;
+        self.mem.clk_cycle(val, &aligned, WORD, false, true);
+    }
+
+    /// Main memory, for loaders and dumpers
+    pub fn mem(&mut self) -> &mut ByteRAM<B> {
+        &mut self.mem
+    }
+
+    /// Main memory size in bytes, MEM_BYTES unless set_mem_size changed it
+    pub fn mem_size(&self) -> u32 {
+        self.mem.bytes()
+    }
+
+    /// Resize main memory, a multiple of 4 up to MAX_MEM_BYTES; contents are kept
+    pub fn set_mem_size(&mut self, bytes: u32) {
+        self.mem.set_size(bytes);
+    }
+
+    /// The I/O bus, 16 address bits unless set_io replaces it; map devices here
+    pub fn io(&mut self) -> &mut Bus {
+        &mut self.io
+    }
+
+    /// Replace the I/O bus, e.g. with a wider one; it decodes byte address bits [addr_bits..1]
+    pub fn set_io(&mut self, bus: Bus) {
+        assert!(bus.words() <= 1 << 30, "an I/O bus of {} words does not fit above IO_BASE", bus.words());
+        self.io = bus;
+    }
+
+    /// Hand (pc, instruction) of every instruction executed to sink, or stop tracing with None
+    /// e.g. Some(Box::new(|pc, inst| println!("{}", disasm::trace_line(pc, inst))))
+    pub fn set_trace(&mut self, sink: Option<TraceSink>) {
+        self.trace = sink;
+    }
+
+    pub fn clk_cycle(&mut self) {
+        if self.halted { return; }
+
+        let pc = self.pc.read();
+        let (inst, misfetch) = trace_child!("fetch", self.mem.clk_cycle(&ZERO, &pc, WORD, false, false));
+        if let Some(sink) = &mut self.trace {
+            sink(utils::boollist_to_u32(&pc), utils::boollist_to_u32(&inst));
+        }
+        let dec = decoder::decode(&inst);
+        // ECALL, EBREAK, illegal instructions and misaligned or out of range fetches stop the machine at pc
+        if gates::or(dec.halt, misfetch) {
+            self.halted = true;
+            return;
+        }
+
+        let (rs1, rs2) = trace_child!("RegisterFile32", self.regs.clk_cycle(&dec.rs1, &dec.rs2, &dec.rd, &ZERO, false));
+        let alu = trace_child!("ALU", adder::riscv_alu(&gates::mux32(&rs1, &pc, dec.alu_pc),
+                                                         &gates::mux32(&rs2, &dec.imm, dec.alu_imm),
+                                                         &dec.ctrl));
+
+        // memory or the I/O bus is only clocked when the instruction touches it
+        let mut mem_out = ZERO;
+        if gates::or(dec.mem_read, dec.mem_write) {
+            let width = (dec.funct3[1], dec.funct3[0]);
+            let signed = gates::not(dec.funct3[2]);
+            let (out, fault) = match alu.out[31] {
+                false => trace_child!("ByteRAM", self.mem.clk_cycle(&rs2, &alu.out, width, signed, dec.mem_write)),
+                true => trace_child!("IO", self.io_access(&rs2, &alu.out, width, signed, dec.mem_write)),
+            };
+            // there is no trap handler, a misaligned or out of range access stops the machine at pc
+            if fault {
+                self.halted = true;
+                return;
+            }
+            mem_out = out;
+        }
+
+        let pc_next = adder::add32(&pc, &utils::bytes_to_boollist32(&[0, 0, 0, 4]));
+        let link = gates::or(dec.jal, dec.jalr);
+        let mut rd_val = gates::mux32(&alu.out, &mem_out, dec.mem_read);
+        rd_val = gates::mux32(&rd_val, &dec.imm, dec.lui);
+        rd_val = gates::mux32(&rd_val, &pc_next, link);
+        trace_child!("RegisterFile32", self.regs.clk_cycle(&dec.rs1, &dec.rs2, &dec.rd, &rd_val, dec.reg_write));
+
+        let taken = gates::or(gates::and(dec.branch, branch_taken(&alu, &dec.funct3)), dec.jal);
+        let mut jalr_target = alu.out;
+        jalr_target[0] = false;
+        let target = gates::mux32(&adder::add32(&pc, &dec.imm), &jalr_target, dec.jalr);
+        trace_child!("PC32", self.pc.clk_cycle(&target, true, true, gates::or(taken, dec.jalr), false));
+        trace_ports!(("pc", &pc), ("instruction", &inst));
+    }
+
+    // one access to the I/O bus, faulting on odd halfword/word addresses and addresses past the bus
+    fn io_access(&mut self, val: &[bool; 32], addr: &[bool; 32], width: (bool, bool), signed: bool, load: bool) -> ([bool; 32], bool) {
+        let misaligned = gates::and(gates::or(width.0, width.1), addr[0]);
+        let bits = self.io.words().trailing_zeros() as usize;
+        let past_end = addr[bits + 1..31].iter().fold(false, |acc, bit| gates::or(acc, *bit));
+        let fault = gates::or(misaligned, past_end);
+        if fault { return (ZERO, true); }
+        let out = self.io.clk_cycle(&val[0..16], &addr[1..32], load);
+        (io_extend(&out, width, signed), false)
+    }
+
+    /// Clock the CPU until it halts or max_cycles have elapsed
+    /// Returns the number of cycles executed
+    pub fn run(&mut self, max_cycles: usize) -> usize {
+        let mut cycles = 0;
+        while cycles < max_cycles && !self.halted {
+            self.clk_cycle();
+            cycles += 1;
+        }
+        cycles
+    }
+}
+
+impl<B: RamBackend> Default for Cpu<B> {
+    fn default() -> Cpu<B> { Cpu::with_backend() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(val: u32) -> [bool; 32] {
+        utils::bytes_to_boollist32(&utils::split_u32(val))
+    }
+
+    #[test]
+    fn test_cpu_op_works() {
+        let program = [
+            0x00c00093, // addi x1, x0, 12
+            0x00d00113, // addi x2, x0, 13
+            0x002081b3, // add x3, x1, x2
+            0x40208233, // sub x4, x1, x2
+            0x0020c2b3, // xor x5, x1, x2
+            0x0020e333, // or x6, x1, x2
+            0x0020f3b3, // and x7, x1, x2
+            0x00122433, // slt x8, x4, x1
+            0x001234b3, // sltu x9, x4, x1
+            0x00300513, // addi x10, x0, 3
+            0x00a095b3, // sll x11, x1, x10
+            0x00a25633, // srl x12, x4, x10
+            0x40a256b3, // sra x13, x4, x10
+            0x00411713, // slli x14, x2, 4
+            0x41f25793, // srai x15, x4, 31
+            0x01c25813, // srli x16, x4, 28
+            0x00d0a893, // slti x17, x1, 13
+            0xfff0b913, // sltiu x18, x1, -1
+            0xfff0c993, // xori x19, x1, -1
+            0x0030ea13, // ori x20, x1, 3
+            0x0040fa93, // andi x21, x1, 4
+            0x40000b13, // addi x22, x0, 1024
+            0xdeadcbb7, // lui x23, 0xdeadc
+            0xeefb8b93, // addi x23, x23, -273
+            0x00001c17, // auipc x24, 1
+            0x00000073, // ecall
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load_program(&program);
+        assert_eq!(cpu.run(100), 26);
+        assert!(cpu.is_halted());
+
+        let expected = [0, 12, 13, 25, 0xffffffff, 1, 13, 12, 1, 0, 3, 96, 0x1fffffff, 0xffffffff,
+                        208, 0xffffffff, 15, 1, 1, 0xfffffff3, 15, 4, 1024, 0xdeadbeef, 0x1060];
+        for (i, val) in expected.iter().enumerate() {
+            assert_eq!(utils::boollist_to_u32(&cpu.read_reg(i)), *val, "x{}", i);
+        }
+        assert_eq!(cpu.pc(), word(100));
+    }
+
+    #[test]
+    fn test_cpu_load_store_works() {
+        let program = [
+            0xdeadc0b7, // lui x1, 0xdeadc
+            0xeef08093, // addi x1, x1, -273
+            0x10000113, // addi x2, x0, 256
+            0x00112023, // sw x1, 0(x2)
+            0x00012183, // lw x3, 0(x2)
+            0x00010203, // lb x4, 0(x2)
+            0x00114283, // lbu x5, 1(x2)
+            0x00211303, // lh x6, 2(x2)
+            0x00015383, // lhu x7, 0(x2)
+            0x000100a3, // sb x0, 1(x2)
+            0x00211123, // sh x2, 2(x2)
+            0x00012403, // lw x8, 0(x2)
+            0x00112223, // sw x1, 4(x2)
+            0x00412483, // lw x9, 4(x2)
+            0x00000073, // ecall
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load_program(&program);
+        cpu.run(100);
+
+        let expected = [0, 0xdeadbeef, 256, 0xdeadbeef, 0xffffffef, 0xbe, 0xffffdead, 0xbeef, 0x010000ef, 0xdeadbeef];
+        for (i, val) in expected.iter().enumerate() {
+            assert_eq!(utils::boollist_to_u32(&cpu.read_reg(i)), *val, "x{}", i);
+        }
+    }
+
+    #[test]
+    fn test_cpu_branch_jump_works() {
+        let program = [
+            0x00000093, // addi x1, x0, 0
+            0x00500113, // addi x2, x0, 5
+            0x002080b3, // loop: add x1, x1, x2
+            0xfff10113, // addi x2, x2, -1
+            0xfe011ce3, // bne x2, x0, loop
+            0xfff00193, // addi x3, x0, -1
+            0x0001c463, // blt x3, x0, neg
+            0x00100213, // addi x4, x0, 1
+            0x0001e463, // neg: bltu x3, x0, bad
+            0x00305463, // bge x0, x3, ge
+            0x00200213, // bad: addi x4, x0, 2
+            0x0001f463, // ge: bgeu x3, x0, skip
+            0x00300213, // addi x4, x0, 3
+            0x00000463, // skip: beq x0, x0, call
+            0x00400213, // addi x4, x0, 4
+            0x00c002ef, // call: jal x5, func
+            0x00700393, // addi x7, x0, 7
+            0x00000073, // ecall
+            0x00600313, // func: addi x6, x0, 6
+            0x00028067, // jalr x0, 0(x5)
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load_program(&program);
+        cpu.run(100);
+
+        let expected = [0, 15, 0, 0xffffffff, 0, 64, 6, 7];
+        for (i, val) in expected.iter().enumerate() {
+            assert_eq!(utils::boollist_to_u32(&cpu.read_reg(i)), *val, "x{}", i);
+        }
+        assert_eq!(cpu.pc(), word(68));
+    }
+
+    #[test]
+    fn test_cpu_misaligned_halts() {
+        let program = [
+            0x10000113, // addi x2, x0, 256
+            0x00112023, // sw x1, 0(x2)
+            0x00211183, // lh x3, 2(x2)
+            0x00112123, // sw x1, 2(x2)
+            0x00100213, // addi x4, x0, 1
+            0x00000073, // ecall
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load_program(&program);
+        assert_eq!(cpu.run(100), 4);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc(), word(12));
+        assert_eq!(utils::boollist_to_u32(&cpu.read_reg(4)), 0);
+    }
+
+    #[test]
+    fn test_cpu_out_of_range_halts() {
+        let program = [
+            0x00500093, // addi x1, x0, 5
+            0x00004137, // lui x2, 4
+            0x00112023, // sw x1, 0(x2)
+            0x00100213, // addi x4, x0, 1
+            0x00000073, // ecall
+        ];
+        // 16384 is one past the default memory, and would alias address 0
+        let mut cpu = Cpu::new();
+        cpu.load_program(&program);
+        cpu.run(100);
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc(), word(8));
+        assert_eq!(utils::boollist_to_u32(&cpu.read_reg(4)), 0);
+        assert_eq!(cpu.read_mem(&word(0)), word(program[0]));
+
+        let mut cpu = Cpu::new();
+        cpu.set_mem_size(MAX_MEM_BYTES);
+        assert_eq!(cpu.mem_size(), MAX_MEM_BYTES);
+        cpu.load_program(&program);
+        cpu.run(100);
+        assert_eq!(cpu.pc(), word(16));
+        assert_eq!(utils::boollist_to_u32(&cpu.read_reg(4)), 1);
+        assert_eq!(cpu.read_mem(&word(16384)), word(5));
+    }
+
+    #[test]
+    fn test_cpu_trace_works() {
+        let program = [
+            0x00500093, // addi x1, x0, 5
+            0x00000463, // beq x0, x0, 8
+            0x00100213, // addi x4, x0, 1
+            0x00000073, // ecall
+        ];
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let mut cpu = Cpu::<memory::Behavioral>::with_backend();
+        cpu.load_program(&program);
+        cpu.set_trace(Some(Box::new(move |pc, inst| sink.lock().unwrap().push((pc, inst)))));
+        assert!(format!("{:?}", cpu).contains("trace: true"));
+        cpu.clk_cycle();
+        cpu.clk_cycle();
+        cpu.set_trace(None);
+        cpu.run(10);
+        // nothing is traced once the sink is removed
+        assert_eq!(*seen.lock().unwrap(), vec![(0, program[0]), (4, program[1])]);
+        assert_eq!(cpu.pc(), word(12));
+    }
+
+    #[test]
+    fn test_cpu_backends_match() {
+        let program = [
+            0xdeadc0b7, // lui x1, 0xdeadc
+            0xeef08093, // addi x1, x1, -273
+            0x10000113, // addi x2, x0, 256
+            0x00112023, // sw x1, 0(x2)
+            0x00114283, // lbu x5, 1(x2)
+            0x00211123, // sh x2, 2(x2)
+            0x00012403, // lw x8, 0(x2)
+            0x00000073, // ecall
+        ];
+        let mut gate = Cpu::new();
+        let mut fast = Cpu::<memory::Behavioral>::with_backend();
+        gate.load_program(&program);
+        fast.load_program(&program);
+        assert_eq!(gate.run(100), fast.run(100));
+        assert_eq!(gate.pc(), fast.pc());
+        for i in 0..32 {
+            assert_eq!(gate.read_reg(i), fast.read_reg(i), "x{}", i);
+        }
+        assert_eq!(gate.read_mem(&word(256)), fast.read_mem(&word(256)));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::*;
+use adder::RISCvCtrl;
+
+/// DecodeOut - Fields and control lines of one RV32I instruction
+pub struct DecodeOut {
+    pub opcode: [bool; 7],
+    pub funct3: [bool; 3],
+    pub funct7: [bool; 7],
+    pub rd: [bool; 5],
+    pub rs1: [bool; 5],
+    pub rs2: [bool; 5],
+
+    pub imm_i: [bool; 32],
+    pub imm_s: [bool; 32],
+    pub imm_b: [bool; 32],
+    pub imm_u: [bool; 32],
+    pub imm_j: [bool; 32],
+    pub imm: [bool; 32],    // immediate of this instruction's format
+
+    pub ctrl: RISCvCtrl,
+    pub alu_imm: bool,      // ALU Y is imm instead of rs2
+    pub alu_pc: bool,       // ALU X is pc instead of rs1 (AUIPC)
+    pub reg_write: bool,
+    pub mem_read: bool,
+    pub mem_write: bool,
+    pub branch: bool,
+    pub jal: bool,
+    pub jalr: bool,
+    pub lui: bool,
+    pub halt: bool,         // ECALL, EBREAK, illegal opcode or reserved funct3/funct7
+}
+
+// immediates are pure wiring: bits are rerouted from the instruction, no gates involved
+fn sign_extend(imm: &[bool]) -> [bool; 32] {
+    let mut res = [imm[imm.len()-1]; 32];
+    res[0..imm.len()].copy_from_slice(imm);
+    res
+}
+
+fn imm_i(inst: &[bool; 32]) -> [bool; 32] {
+    sign_extend(&inst[20..32])
+}
+
+fn imm_s(inst: &[bool; 32]) -> [bool; 32] {
+    let mut imm = [false; 12];
+    imm[0..5].copy_from_slice(&inst[7..12]);
+    imm[5..12].copy_from_slice(&inst[25..32]);
+    sign_extend(&imm)
+}
+
+fn imm_b(inst: &[bool; 32]) -> [bool; 32] {
+    let mut imm = [false; 13];
+    imm[1..5].copy_from_slice(&inst[8..12]);
+    imm[5..11].copy_from_slice(&inst[25..31]);
+    imm[11] = inst[7];
+    imm[12] = inst[31];
+    sign_extend(&imm)
+}
+
+fn imm_u(inst: &[bool; 32]) -> [bool; 32] {
+    let mut res = [false; 32];
+    res[12..32].copy_from_slice(&inst[12..32]);
+    res
+}
+
+fn imm_j(inst: &[bool; 32]) -> [bool; 32] {
+    let mut imm = [false; 21];
+    imm[1..11].copy_from_slice(&inst[21..31]);
+    imm[11] = inst[20];
+    imm[12..20].copy_from_slice(&inst[12..20]);
+    imm[20] = inst[31];
+    sign_extend(&imm)
+}
+
+/// MatchOpcode - True iff opcode[7] equals pattern
+/// The pattern only decides where inverters go, the compare itself is an and-tree.
+fn match_opcode(opcode: &[bool; 7], pattern: u8) -> bool {
+    let mut res = true;
+    for i in 0..7 {
+        let bit = match (pattern >> i) & 1 {
+            1 => opcode[i],
+            _ => gates::not(opcode[i]),
+        };
+        res = gates::and(res, bit);
+    }
+    res
+}
+
+/// RISCvDecoder - Split a 32-bit RV32I instruction into fields and control lines
+/// Inputs: inst[32]
+/// Outputs: DecodeOut
+/// Function: opcode=inst[6..0], rd=inst[11..7], funct3=inst[14..12], rs1=inst[19..15],
+///           rs2=inst[24..20], funct7=inst[31..25]
+///           OP/OP-IMM drive RISCvCtrl from the alu_notes.txt table:
+///             ir=opcode[5], al=funct3[2], c=funct3[1], d=funct3[0], pn=funct7[5]
+///           BRANCH drives SUB so the ALU flags give the condition,
+///           every other opcode drives ADD for address/target arithmetic
+pub fn decode(inst: &[bool; 32]) -> DecodeOut {
+    let mut opcode = [false; 7];
+    opcode.copy_from_slice(&inst[0..7]);
+    let mut funct3 = [false; 3];
+    funct3.copy_from_slice(&inst[12..15]);
+    let mut funct7 = [false; 7];
+    funct7.copy_from_slice(&inst[25..32]);
+    let mut rd = [false; 5];
+    rd.copy_from_slice(&inst[7..12]);
+    let mut rs1 = [false; 5];
+    rs1.copy_from_slice(&inst[15..20]);
+    let mut rs2 = [false; 5];
+    rs2.copy_from_slice(&inst[20..25]);
+
+    let is_load   = match_opcode(&opcode, 0b0000011);
+    let is_fence  = match_opcode(&opcode, 0b0001111);
+    let is_op_imm = match_opcode(&opcode, 0b0010011);
+    let is_auipc  = match_opcode(&opcode, 0b0010111);
+    let is_store  = match_opcode(&opcode, 0b0100011);
+    let is_op     = match_opcode(&opcode, 0b0110011);
+    let is_lui    = match_opcode(&opcode, 0b0110111);
+    let is_branch = match_opcode(&opcode, 0b1100011);
+    let is_jalr   = match_opcode(&opcode, 0b1100111);
+    let is_jal    = match_opcode(&opcode, 0b1101111);
+
+    let (i, s, b, u, j) = (imm_i(inst), imm_s(inst), imm_b(inst), imm_u(inst), imm_j(inst));
+    let mut imm = gates::mux32(&i, &s, is_store);
+    imm = gates::mux32(&imm, &b, is_branch);
+    imm = gates::mux32(&imm, &u, gates::or(is_lui, is_auipc));
+    imm = gates::mux32(&imm, &j, is_jal);
+
+    let alu_op = gates::or(is_op, is_op_imm);
+    let ctrl = RISCvCtrl::new(gates::or(gates::and(alu_op, opcode[5]), is_branch),
+                              gates::and(alu_op, funct3[2]),
+                              gates::and(alu_op, funct3[1]),
+                              gates::and(alu_op, funct3[0]),
+                              gates::or(gates::and(alu_op, funct7[5]), is_branch));
+
+    let writes_alu = gates::or(gates::or(alu_op, is_load), is_auipc);
+    let writes_link = gates::or(is_jal, is_jalr);
+    let reg_write = gates::or(gates::or(writes_alu, writes_link), is_lui);
+
+    // funct3 values RV32I reserves: LOAD 011/110/111, STORE 011 and up, BRANCH 010/011, JALR all but 000
+    let load_ok = gates::not(gates::and(funct3[1], gates::or(funct3[0], funct3[2])));
+    let store_ok = gates::not(gates::or(funct3[2], gates::and(funct3[1], funct3[0])));
+    let branch_ok = gates::not(gates::and(gates::not(funct3[2]), funct3[1]));
+    let funct3_zero = gates::not(gates::or(gates::or(funct3[2], funct3[1]), funct3[0]));
+    let known_mem = gates::or(gates::and(is_load, load_ok), gates::and(is_store, store_ok));
+    let known_jump = gates::or(gates::or(gates::and(is_branch, branch_ok), gates::and(is_jalr, funct3_zero)), is_jal);
+
+    // funct7 is 0x00 for OP and the OP-IMM shifts, 0x20 only for SUB, SRA and SRAI (RV32M's 0x01 included)
+    let funct7_low = gates::or(gates::or(funct7[0], funct7[1]), gates::or(funct7[2], funct7[3]));
+    let funct7_rest = gates::not(gates::or(funct7_low, gates::or(funct7[4], funct7[6])));
+    let funct3_sr = gates::and(gates::and(funct3[2], gates::not(funct3[1])), funct3[0]);
+    let op_ok = gates::and(funct7_rest, gates::or(gates::not(funct7[5]), gates::or(funct3_zero, funct3_sr)));
+    let shamt_ok = gates::and(funct7_rest, gates::or(gates::not(funct7[5]), funct3_sr));
+    // the other OP-IMM instructions keep imm[11:5] where funct7 would be
+    let is_shift = gates::and(gates::not(funct3[1]), funct3[0]);
+    let op_imm_ok = gates::or(gates::not(is_shift), shamt_ok);
+    let known_op = gates::or(gates::and(is_op, op_ok), gates::and(is_op_imm, op_imm_ok));
+    let known_alu = gates::or(gates::or(known_op, is_auipc), gates::or(is_lui, is_fence));
+    let known = gates::or(gates::or(known_mem, known_jump), known_alu);
+
+    DecodeOut {
+        opcode, funct3, funct7, rd, rs1, rs2,
+        imm_i: i, imm_s: s, imm_b: b, imm_u: u, imm_j: j, imm,
+        ctrl,
+        alu_imm: gates::not(gates::or(is_op, is_branch)),
+        alu_pc: is_auipc,
+        reg_write,
+        mem_read: is_load,
+        mem_write: is_store,
+        branch: is_branch,
+        jal: is_jal,
+        jalr: is_jalr,
+        lui: is_lui,
+        halt: gates::not(known),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(val: u32) -> [bool; 32] {
+        utils::bytes_to_boollist32(&utils::split_u32(val))
+    }
+
+    #[test]
+    fn test_imm_decode_works() {
+        assert_eq!(imm_i(&word(0xfff0b913)), word(0xffffffff)); // sltiu x18, x1, -1
+        assert_eq!(imm_s(&word(0x00112223)), word(4));          // sw x1, 4(x2)
+        assert_eq!(imm_b(&word(0xfe011ce3)), word(0xfffffff8)); // bne x2, x0, -8
+        assert_eq!(imm_u(&word(0xdeadc0b7)), word(0xdeadc000)); // lui x1, 0xdeadc
+        assert_eq!(imm_j(&word(0x00c002ef)), word(12));         // jal x5, 12
+    }
+
+    #[test]
+    fn test_decode_fields_work() {
+        let out = decode(&word(0x40a256b3)); // sra x13, x4, x10
+        assert_eq!(utils::boollist_to_u32(&out.opcode), 0b0110011);
+        assert_eq!(utils::boollist_to_u32(&out.funct3), 0b101);
+        assert_eq!(utils::boollist_to_u32(&out.funct7), 0x20);
+        assert_eq!(utils::boollist_to_u32(&out.rd), 13);
+        assert_eq!(utils::boollist_to_u32(&out.rs1), 4);
+        assert_eq!(utils::boollist_to_u32(&out.rs2), 10);
+    }
+
+    #[test]
+    fn test_decode_ctrl_works() {
+        // rows of alu_notes.txt, RISCvCtrl as ir al c d pn
+        let rows = [
+            (0x002081b3, RISCvCtrl::new(true, false, false, false, false)),  // add
+            (0x40208233, RISCvCtrl::new(true, false, false, false, true)),   // sub
+            (0x00a095b3, RISCvCtrl::new(true, false, false, true, false)),   // sll
+            (0x00122433, RISCvCtrl::new(true, false, true, false, false)),   // slt
+            (0x001234b3, RISCvCtrl::new(true, false, true, true, false)),    // sltu
+            (0x0020c2b3, RISCvCtrl::new(true, true, false, false, false)),   // xor
+            (0x00a25633, RISCvCtrl::new(true, true, false, true, false)),    // srl
+            (0x40a256b3, RISCvCtrl::new(true, true, false, true, true)),     // sra
+            (0x0020e333, RISCvCtrl::new(true, true, true, false, false)),    // or
+            (0x0020f3b3, RISCvCtrl::new(true, true, true, true, false)),     // and
+            (0x00c00093, RISCvCtrl::new(false, false, false, false, false)), // addi
+            (0x00411713, RISCvCtrl::new(false, false, false, true, false)),  // slli
+            (0x41f25793, RISCvCtrl::new(false, true, false, true, true)),    // srai
+            (0x00d0a893, RISCvCtrl::new(false, false, true, false, false)),  // slti
+            (0xfff0b913, RISCvCtrl::new(false, false, true, true, true)),    // sltiu
+            (0xfff0c993, RISCvCtrl::new(false, true, false, false, true)),   // xori
+            (0xfe011ce3, RISCvCtrl::new(true, false, false, false, true)),   // bne -> sub
+            (0x00012183, RISCvCtrl::new(false, false, false, false, false)), // lw -> add
+            (0x00112023, RISCvCtrl::new(false, false, false, false, false)), // sw -> add
+        ];
+        for (inst, ctrl) in rows {
+            assert_eq!(decode(&word(inst)).ctrl, ctrl, "{:08x}", inst);
+        }
+    }
+
+    #[test]
+    fn test_decode_control_lines_work() {
+        let out = decode(&word(0x00012183)); // lw x3, 0(x2)
+        assert_eq!((out.reg_write, out.mem_read, out.mem_write, out.alu_imm, out.halt), (true, true, false, true, false));
+        assert_eq!(out.imm, out.imm_i);
+
+        let out = decode(&word(0x00112223)); // sw x1, 4(x2)
+        assert_eq!((out.reg_write, out.mem_read, out.mem_write, out.alu_imm, out.halt), (false, false, true, true, false));
+        assert_eq!(out.imm, word(4));
+
+        let out = decode(&word(0xfe011ce3)); // bne x2, x0, -8
+        assert_eq!((out.reg_write, out.branch, out.alu_imm), (false, true, false));
+        assert_eq!(out.imm, word(0xfffffff8));
+
+        let out = decode(&word(0x00001c17)); // auipc x24, 1
+        assert_eq!((out.reg_write, out.alu_pc, out.alu_imm), (true, true, true));
+        assert_eq!(out.imm, word(0x1000));
+
+        let out = decode(&word(0x00c002ef)); // jal x5, 12
+        assert_eq!((out.reg_write, out.jal, out.jalr), (true, true, false));
+        assert_eq!(out.imm, word(12));
+
+        assert_eq!(decode(&word(0x00000073)).halt, true);  // ecall
+        assert_eq!(decode(&word(0x00100073)).halt, true);  // ebreak
+        assert_eq!(decode(&word(0x0ff0000f)).halt, false); // fence
+        assert_eq!(decode(&word(0x00000000)).halt, true);  // illegal
+    }
+
+    #[test]
+    fn test_decode_reserved_funct3_halts() {
+        // lb lh lw lbu lhu decode, funct3 011 110 111 are reserved
+        for (funct3, halt) in [(0, false), (1, false), (2, false), (3, true), (4, false), (5, false), (6, true), (7, true)] {
+            assert_eq!(decode(&word(0x00012183 & !0x7000 | funct3 << 12)).halt, halt, "load funct3 {:03b}", funct3);
+        }
+        // sb sh sw, funct3 011 and up are reserved
+        for funct3 in 0..8 {
+            assert_eq!(decode(&word(0x00112223 & !0x7000 | funct3 << 12)).halt, funct3 >= 3, "store funct3 {:03b}", funct3);
+        }
+        // beq bne blt bge bltu bgeu, funct3 010 011 are reserved
+        for funct3 in 0..8 {
+            assert_eq!(decode(&word(0xfe011ce3 & !0x7000 | funct3 << 12)).halt, funct3 == 2 || funct3 == 3, "branch funct3 {:03b}", funct3);
+        }
+        // jalr x1, 0(x5) only with funct3 000
+        for funct3 in 0..8 {
+            assert_eq!(decode(&word(0x000280e7 | funct3 << 12)).halt, funct3 != 0, "jalr funct3 {:03b}", funct3);
+        }
+    }
+
+    #[test]
+    fn test_decode_reserved_funct7_halts() {
+        // add x1, x2, x3 with every funct3: only sub and sra also take funct7 0x20
+        for funct3 in 0..8 {
+            let inst = 0x003100b3 | funct3 << 12;
+            assert!(!decode(&word(inst)).halt, "op funct3 {:03b}", funct3);
+            assert_eq!(decode(&word(inst | 0x20 << 25)).halt, funct3 != 0 && funct3 != 5, "op funct3 {:03b} funct7 0x20", funct3);
+            for funct7 in [0x01, 0x02, 0x10, 0x40, 0x60] {
+                assert!(decode(&word(inst | funct7 << 25)).halt, "op funct3 {:03b} funct7 {:#x}", funct3, funct7);
+            }
+        }
+        assert!(decode(&word(0x02000033)).halt); // mul x0, x0, x0
+        assert!(decode(&word(0x0230c0b3)).halt); // div x1, x1, x3
+
+        assert!(!decode(&word(0x00109093)).halt); // slli x1, x1, 1
+        assert!(decode(&word(0x40109093)).halt);  // slli with funct7 0x20
+        assert!(decode(&word(0x02109093)).halt);  // slli x1, x1, 33
+        assert!(!decode(&word(0x0010d093)).halt); // srli x1, x1, 1
+        assert!(!decode(&word(0x4010d093)).halt); // srai x1, x1, 1
+        assert!(decode(&word(0x0210d093)).halt);  // srli x1, x1, 33
+        assert!(decode(&word(0x8010d093)).halt);  // srai with funct7 0x40
+        // imm[11:5] of the rest of OP-IMM is an immediate, not a funct7
+        for inst in [0xfff00093, 0x8000a093, 0x4000b093, 0xfff0c093, 0x0200e093, 0xfe00f093] {
+            assert!(!decode(&word(inst)).halt, "{:08x}", inst);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// RV32I disassembler, the inverse of rv_asm for debugging and traces.
+// Registers print with their ABI names, branch and jump targets as absolute addresses.
+use crate::*;
+use rv_asm::ABI;
+
+fn reg(idx: u32) -> &'static str {
+    ABI[(idx & 0x1f) as usize]
+}
+
+fn imm_i(inst: u32) -> i32 {
+    inst as i32 >> 20
+}
+
+fn imm_s(inst: u32) -> i32 {
+    (inst & 0xfe000000) as i32 >> 20 | ((inst >> 7) & 0x1f) as i32
+}
+
+fn imm_b(inst: u32) -> i32 {
+    (inst & 0x80000000) as i32 >> 19 | ((inst & 0x80) << 4) as i32
+        | ((inst >> 20) & 0x7e0) as i32 | ((inst >> 7) & 0x1e) as i32
+}
+
+fn imm_j(inst: u32) -> i32 {
+    (inst & 0x80000000) as i32 >> 11 | (inst & 0xff000) as i32
+        | ((inst >> 9) & 0x800) as i32 | ((inst >> 20) & 0x7fe) as i32
+}
+
+/// Disassemble one instruction fetched from pc
+/// Unknown encodings print as a .word directive.
+pub fn disassemble(inst: u32, pc: u32) -> String {
+    let opcode = inst & 0x7f;
+    let rd = (inst >> 7) & 0x1f;
+    let funct3 = (inst >> 12) & 0x7;
+    let rs1 = (inst >> 15) & 0x1f;
+    let rs2 = (inst >> 20) & 0x1f;
+    let funct7 = inst >> 25;
+    let target = |offset: i32| pc.wrapping_add(offset as u32);
+
+    let text = match opcode {
+        0b0110111 => Some(format!("lui {}, 0x{:x}", reg(rd), inst >> 12)),
+        0b0010111 => Some(format!("auipc {}, 0x{:x}", reg(rd), inst >> 12)),
+        0b1101111 => Some(match rd {
+            0 => format!("j 0x{:x}", target(imm_j(inst))),
+            _ => format!("jal {}, 0x{:x}", reg(rd), target(imm_j(inst))),
+        }),
+        0b1100111 if funct3 == 0 => Some(match (rd, rs1, imm_i(inst)) {
+            (0, 1, 0) => "ret".to_string(),
+            (rd, rs1, imm) => format!("jalr {}, {}({})", reg(rd), imm, reg(rs1)),
+        }),
+        0b1100011 => ["beq", "bne", "", "", "blt", "bge", "bltu", "bgeu"]
+            .get(funct3 as usize)
+            .filter(|name| !name.is_empty())
+            .map(|name| format!("{} {}, {}, 0x{:x}", name, reg(rs1), reg(rs2), target(imm_b(inst)))),
+        0b0000011 => ["lb", "lh", "lw", "", "lbu", "lhu", "", ""]
+            .get(funct3 as usize)
+            .filter(|name| !name.is_empty())
+            .map(|name| format!("{} {}, {}({})", name, reg(rd), imm_i(inst), reg(rs1))),
+        0b0100011 => ["sb", "sh", "sw", "", "", "", "", ""]
+            .get(funct3 as usize)
+            .filter(|name| !name.is_empty())
+            .map(|name| format!("{} {}, {}({})", name, reg(rs2), imm_s(inst), reg(rs1))),
+        0b0010011 => match (funct3, funct7) {
+            (0b000, _) => Some(match (rd, rs1, imm_i(inst)) {
+                (0, 0, 0) => "nop".to_string(),
+                (rd, 0, imm) => format!("li {}, {}", reg(rd), imm),
+                (rd, rs1, 0) => format!("mv {}, {}", reg(rd), reg(rs1)),
+                (rd, rs1, imm) => format!("addi {}, {}, {}", reg(rd), reg(rs1), imm),
+            }),
+            (0b001, 0) => Some(format!("slli {}, {}, {}", reg(rd), reg(rs1), rs2)),
+            (0b101, 0) => Some(format!("srli {}, {}, {}", reg(rd), reg(rs1), rs2)),
+            (0b101, 0x20) => Some(format!("srai {}, {}, {}", reg(rd), reg(rs1), rs2)),
+            (0b001 | 0b101, _) => None,
+            (funct3, _) => {
+                let name = ["", "", "slti", "sltiu", "xori", "", "ori", "andi"][funct3 as usize];
+                Some(format!("{} {}, {}, {}", name, reg(rd), reg(rs1), imm_i(inst)))
+            }
+        },
+        0b0110011 => match (funct7, funct3) {
+            (0x00, _) => Some(["add", "sll", "slt", "sltu", "xor", "srl", "or", "and"][funct3 as usize]),
+            (0x20, 0b000) => Some("sub"),
+            (0x20, 0b101) => Some("sra"),
+            _ => None,
+        }.map(|name| format!("{} {}, {}, {}", name, reg(rd), reg(rs1), reg(rs2))),
+        0b0001111 => Some("fence".to_string()),
+        0b1110011 => match inst {
+            0x00000073 => Some("ecall".to_string()),
+            0x00100073 => Some("ebreak".to_string()),
+            _ => None,
+        },
+        _ => None,
+    };
+    text.unwrap_or_else(|| format!(".word 0x{:08x}", inst))
+}
+
+/// One trace line: pc, raw instruction word and its disassembly
+pub fn trace_line(pc: u32, inst: u32) -> String {
+    format!("{:08x}: {:08x}  {}", pc, inst, disassemble(inst, pc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_imm_decode_works() {
+        assert_eq!(imm_i(0xfff0b913), -1);
+        assert_eq!(imm_s(0x00112223), 4);
+        assert_eq!(imm_s(0xfe112e23), -4);
+        assert_eq!(imm_b(0xfe011ce3), -8);
+        assert_eq!(imm_j(0x00c002ef), 12);
+        assert_eq!(imm_j(0xff9ff06f), -8);
+    }
+
+    #[test]
+    fn test_disassemble_works() {
+        let rows = [
+            (0x002081b3, "add gp, ra, sp"),
+            (0x40208233, "sub tp, ra, sp"),
+            (0x40a256b3, "sra a3, tp, a0"),
+            (0x00c00093, "li ra, 12"),
+            (0xeefb8b93, "addi s7, s7, -273"),
+            (0x00058513, "mv a0, a1"),
+            (0x00000013, "nop"),
+            (0xfff0b913, "sltiu s2, ra, -1"),
+            (0x41f25793, "srai a5, tp, 31"),
+            (0xdeadcbb7, "lui s7, 0xdeadc"),
+            (0x00001c17, "auipc s8, 0x1"),
+            (0x00211303, "lh t1, 2(sp)"),
+            (0x00015383, "lhu t2, 0(sp)"),
+            (0xfe112e23, "sw ra, -4(sp)"),
+            (0x00028067, "jalr zero, 0(t0)"),
+            (0x00008067, "ret"),
+            (0x0ff0000f, "fence"),
+            (0x00000073, "ecall"),
+            (0x00100073, "ebreak"),
+            (0x00000000, ".word 0x00000000"),
+            (0x7e0090b3, ".word 0x7e0090b3"),
+        ];
+        for (inst, text) in rows {
+            assert_eq!(disassemble(inst, 0), text, "{:08x}", inst);
+        }
+    }
+
+    #[test]
+    fn test_targets_resolve() {
+        assert_eq!(disassemble(0xfe011ce3, 16), "bne sp, zero, 0x8");
+        assert_eq!(disassemble(0x00c002ef, 60), "jal t0, 0x48");
+        assert_eq!(disassemble(0xff9ff06f, 8), "j 0x0");
+        assert_eq!(trace_line(16, 0xfe011ce3), "00000010: fe011ce3  bne sp, zero, 0x8");
+    }
+}
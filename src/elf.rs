@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::*;
+
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+/// ElfError - Why an ELF file can't be run on the nandputer
+#[derive(Debug, PartialEq)]
+pub enum ElfError {
+    Truncated,
+    BadMagic,
+    WrongClass(u8),         // e_ident[EI_CLASS], 1 is ELFCLASS32
+    WrongEndianness(u8),    // e_ident[EI_DATA], 1 is ELFDATA2LSB
+    WrongMachine(u16),      // e_machine, 243 is EM_RISCV
+    SegmentOutOfRange { addr: u32, size: u32 },
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::Truncated => write!(f, "file is truncated"),
+            ElfError::BadMagic => write!(f, "not an ELF file"),
+            ElfError::WrongClass(class) => write!(f, "ELF class {} is not ELF32", class),
+            ElfError::WrongEndianness(data) => write!(f, "ELF data encoding {} is not little endian", data),
+            ElfError::WrongMachine(machine) => write!(f, "machine {} is not RISC-V", machine),
+            ElfError::SegmentOutOfRange{ addr, size } =>
+                write!(f, "segment of {} bytes at 0x{:08x} does not fit in memory", size, addr),
+        }
+    }
+}
+
+/// Segment - One PT_LOAD program header, data is zero-filled up to p_memsz
+pub struct Segment {
+    pub paddr: u32,
+    pub data: Vec<u8>,
+}
+
+/// Elf - The parts of an ELF32 RISC-V executable the nandputer runs
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: HashMap<String, u32>,
+}
+
+fn read_u8(bytes: &[u8], off: usize) -> Result<u8, ElfError> {
+    bytes.get(off).copied().ok_or(ElfError::Truncated)
+}
+
+fn read_u16(bytes: &[u8], off: usize) -> Result<u16, ElfError> {
+    let slice = bytes.get(off..off + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes([slice[0], slice[1]]))
+}
+
+fn read_u32(bytes: &[u8], off: usize) -> Result<u32, ElfError> {
+    let slice = bytes.get(off..off + 4).ok_or(ElfError::Truncated)?;
+    Ok(u32::from_le_bytes([slice[0], slice[1], slice[2], slice[3]]))
+}
+
+fn read_str(bytes: &[u8], off: usize) -> Result<String, ElfError> {
+    let tail = bytes.get(off..).ok_or(ElfError::Truncated)?;
+    let len = tail.iter().position(|b| *b == 0).ok_or(ElfError::Truncated)?;
+    Ok(String::from_utf8_lossy(&tail[..len]).into_owned())
+}
+
+// named function, object and untyped symbols from every SHT_SYMTAB section
+fn parse_symbols(bytes: &[u8]) -> Result<HashMap<String, u32>, ElfError> {
+    let shoff = read_u32(bytes, 0x20)? as usize;
+    let shentsize = read_u16(bytes, 0x2e)? as usize;
+    let shnum = read_u16(bytes, 0x30)? as usize;
+
+    let mut symbols = HashMap::new();
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        if read_u32(bytes, sh + 0x04)? != SHT_SYMTAB { continue; }
+        let offset = read_u32(bytes, sh + 0x10)? as usize;
+        let size = read_u32(bytes, sh + 0x14)? as usize;
+        let link = read_u32(bytes, sh + 0x18)? as usize;
+        let entsize = (read_u32(bytes, sh + 0x24)? as usize).max(16);
+        let strtab = read_u32(bytes, shoff + link * shentsize + 0x10)? as usize;
+
+        for sym in (offset..offset + size).step_by(entsize) {
+            let name = read_u32(bytes, sym)? as usize;
+            let value = read_u32(bytes, sym + 0x04)?;
+            let kind = read_u8(bytes, sym + 0x0c)? & 0xf;
+            if name == 0 || kind > 2 { continue; }
+            symbols.insert(read_str(bytes, strtab + name)?, value);
+        }
+    }
+    Ok(symbols)
+}
+
+/// Parse an ELF32 little endian RISC-V executable
+pub fn parse(bytes: &[u8]) -> Result<Elf, ElfError> {
+    if bytes.get(0..4).ok_or(ElfError::Truncated)? != b"\x7fELF" {
+        return Err(ElfError::BadMagic);
+    }
+    match read_u8(bytes, 4)? {
+        1 => {},
+        class => return Err(ElfError::WrongClass(class)),
+    }
+    match read_u8(bytes, 5)? {
+        1 => {},
+        data => return Err(ElfError::WrongEndianness(data)),
+    }
+    match read_u16(bytes, 0x12)? {
+        EM_RISCV => {},
+        machine => return Err(ElfError::WrongMachine(machine)),
+    }
+
+    let entry = read_u32(bytes, 0x18)?;
+    let phoff = read_u32(bytes, 0x1c)? as usize;
+    let phentsize = read_u16(bytes, 0x2a)? as usize;
+    let phnum = read_u16(bytes, 0x2c)? as usize;
+
+    let mut segments = Vec::new();
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        if read_u32(bytes, ph)? != PT_LOAD { continue; }
+        let offset = read_u32(bytes, ph + 0x04)? as usize;
+        let paddr = read_u32(bytes, ph + 0x0c)?;
+        let filesz = read_u32(bytes, ph + 0x10)? as usize;
+        let memsz = read_u32(bytes, ph + 0x14)? as usize;
+
+        // p_memsz is only a claim, check it before allocating the zero fill
+        let size = memsz.max(filesz) as u32;
+        if paddr.checked_add(size).is_none_or(|end| end > cpu::MAX_MEM_BYTES) {
+            return Err(ElfError::SegmentOutOfRange{ addr: paddr, size });
+        }
+        let mut data = bytes.get(offset..offset + filesz).ok_or(ElfError::Truncated)?.to_vec();
+        data.resize(size as usize, 0);
+        segments.push(Segment{ paddr, data });
+    }
+
+    Ok(Elf{ entry, segments, symbols: parse_symbols(bytes)? })
+}
+
+/// Load every segment into the CPU's memory and point pc at e_entry
+/// Words only partly covered by a segment keep their other bytes.
+pub fn load<B: memory::RamBackend>(cpu: &mut cpu::Cpu<B>, elf: &Elf) -> Result<(), ElfError> {
+    for seg in &elf.segments {
+        let size = seg.data.len() as u32;
+        if seg.paddr.checked_add(size).is_none_or(|end| end > cpu.mem_size()) {
+            return Err(ElfError::SegmentOutOfRange{ addr: seg.paddr, size });
+        }
+    }
+
+    for seg in &elf.segments {
+        let start = seg.paddr & !3;
+        let end = seg.paddr + seg.data.len() as u32;
+        for addr in (start..end).step_by(4) {
+            let addr_bits = utils::bytes_to_boollist32(&utils::split_u32(addr));
+            let mut word = [0u8; 4];
+            if addr < seg.paddr || addr + 4 > end {
+                word = utils::boollist_to_u32(&cpu.read_mem(&addr_bits)).to_le_bytes();
+            }
+            for (i, byte) in word.iter_mut().enumerate() {
+                let byte_addr = addr + i as u32;
+                if (seg.paddr..end).contains(&byte_addr) {
+                    *byte = seg.data[(byte_addr - seg.paddr) as usize];
+                }
+            }
+            let val = utils::bytes_to_boollist32(&utils::split_u32(u32::from_le_bytes(word)));
+            cpu.write_mem(&addr_bits, &val);
+        }
+    }
+
+    cpu.set_pc(&utils::bytes_to_boollist32(&utils::split_u32(elf.entry)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u16(buf: &mut [u8], off: usize, val: u16) {
+        buf[off..off + 2].copy_from_slice(&val.to_le_bytes());
+    }
+
+    fn put_u32(buf: &mut [u8], off: usize, val: u32) {
+        buf[off..off + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    // Minimal executable: one PT_LOAD of text at paddr with a .bss tail, a symtab and its strtab
+    fn build_elf(text: &[u8], paddr: u32, bss: u32, entry: u32) -> Vec<u8> {
+        let strtab = b"\0_start\0counter\0";
+        let text_off = 0x54;
+        let symtab_off = text_off + text.len();
+        let strtab_off = symtab_off + 3 * 16;
+        let shoff = strtab_off + strtab.len();
+        let mut buf = vec![0u8; shoff + 3 * 40];
+
+        buf[0..4].copy_from_slice(b"\x7fELF");
+        buf[4] = 1;
+        buf[5] = 1;
+        buf[6] = 1;
+        put_u16(&mut buf, 0x10, 2);
+        put_u16(&mut buf, 0x12, EM_RISCV);
+        put_u32(&mut buf, 0x14, 1);
+        put_u32(&mut buf, 0x18, entry);
+        put_u32(&mut buf, 0x1c, 0x34);
+        put_u32(&mut buf, 0x20, shoff as u32);
+        put_u16(&mut buf, 0x28, 0x34);
+        put_u16(&mut buf, 0x2a, 0x20);
+        put_u16(&mut buf, 0x2c, 1);
+        put_u16(&mut buf, 0x2e, 40);
+        put_u16(&mut buf, 0x30, 3);
+
+        put_u32(&mut buf, 0x34, PT_LOAD);
+        put_u32(&mut buf, 0x38, text_off as u32);
+        put_u32(&mut buf, 0x3c, paddr);
+        put_u32(&mut buf, 0x40, paddr);
+        put_u32(&mut buf, 0x44, text.len() as u32);
+        put_u32(&mut buf, 0x48, text.len() as u32 + bss);
+        buf[text_off..symtab_off].copy_from_slice(text);
+
+        // null symbol, _start (FUNC), counter (OBJECT)
+        put_u32(&mut buf, symtab_off + 16, 1);
+        put_u32(&mut buf, symtab_off + 20, entry);
+        buf[symtab_off + 28] = 0x12;
+        put_u32(&mut buf, symtab_off + 32, 8);
+        put_u32(&mut buf, symtab_off + 36, paddr + text.len() as u32);
+        buf[symtab_off + 44] = 0x11;
+        buf[strtab_off..shoff].copy_from_slice(strtab);
+
+        // null section, .symtab linked to .strtab
+        let sh = shoff + 40;
+        put_u32(&mut buf, sh + 0x04, SHT_SYMTAB);
+        put_u32(&mut buf, sh + 0x10, symtab_off as u32);
+        put_u32(&mut buf, sh + 0x14, 3 * 16);
+        put_u32(&mut buf, sh + 0x18, 2);
+        put_u32(&mut buf, sh + 0x24, 16);
+        let sh = shoff + 80;
+        put_u32(&mut buf, sh + 0x04, 3);
+        put_u32(&mut buf, sh + 0x10, strtab_off as u32);
+        put_u32(&mut buf, sh + 0x14, strtab.len() as u32);
+        buf
+    }
+
+    #[test]
+    fn test_parse_works() {
+        let elf = parse(&build_elf(&[1, 2, 3, 4, 5, 6], 0x102, 2, 0x104)).unwrap();
+        assert_eq!(elf.entry, 0x104);
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!(elf.segments[0].paddr, 0x102);
+        assert_eq!(elf.segments[0].data, vec![1, 2, 3, 4, 5, 6, 0, 0]);
+        assert_eq!(elf.symbols["_start"], 0x104);
+        assert_eq!(elf.symbols["counter"], 0x108);
+        assert_eq!(elf.symbols.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_errors_work() {
+        let good = build_elf(&[0x73, 0, 0, 0], 0, 0, 0);
+        assert!(parse(&good).is_ok());
+
+        let mut bad = good.clone();
+        bad[4] = 2;
+        assert_eq!(parse(&bad).err(), Some(ElfError::WrongClass(2)));
+        let mut bad = good.clone();
+        bad[5] = 2;
+        assert_eq!(parse(&bad).err(), Some(ElfError::WrongEndianness(2)));
+        let mut bad = good.clone();
+        put_u16(&mut bad, 0x12, 62);
+        assert_eq!(parse(&bad).err(), Some(ElfError::WrongMachine(62)));
+        assert_eq!(parse(b"\x7fELG\x01\x01").err(), Some(ElfError::BadMagic));
+        assert_eq!(parse(&good[0..0x40]).err(), Some(ElfError::Truncated));
+
+        // an oversized p_memsz is refused before it is allocated
+        let mut bad = good.clone();
+        put_u32(&mut bad, 0x48, 0xffff_ffff);
+        assert_eq!(parse(&bad).err(), Some(ElfError::SegmentOutOfRange{ addr: 0, size: 0xffff_ffff }));
+        let mut bad = good.clone();
+        put_u32(&mut bad, 0x40, 0xffff_fffe);
+        assert_eq!(parse(&bad).err(), Some(ElfError::SegmentOutOfRange{ addr: 0xffff_fffe, size: 4 }));
+        let mut bad = good.clone();
+        put_u32(&mut bad, 0x48, cpu::MAX_MEM_BYTES + 1);
+        assert!(parse(&bad).is_err());
+    }
+
+    #[test]
+    fn test_load_works() {
+        let src = "
+                li a0, 7
+                lw a1, 16(zero)
+                ecall
+        ";
+        let text = rv_asm::assemble(src).unwrap().bytes;
+        let mut cpu = cpu::Cpu::new();
+        let addr = |val: u32| utils::bytes_to_boollist32(&utils::split_u32(val));
+        cpu.write_mem(&addr(16), &addr(0xdeadbeef));
+        // a segment at 2 shares its first and last words with existing data
+        let mut data = vec![0u8; 2];
+        data.extend_from_slice(&text);
+        data.extend_from_slice(&[0x11, 0x22]);
+        let elf = parse(&build_elf(&data, 2, 0, 4)).unwrap();
+        load(&mut cpu, &elf).unwrap();
+        assert_eq!(cpu.pc(), addr(4));
+        cpu.run(10);
+        assert_eq!(utils::boollist_to_u32(&cpu.read_reg(10)), 7);
+        assert_eq!(utils::boollist_to_u32(&cpu.read_reg(11)), 0xdead2211);
+
+        let elf = Elf{ entry: 0, segments: vec![Segment{ paddr: cpu::MEM_BYTES - 4, data: vec![0; 8] }], symbols: HashMap::new() };
+        assert_eq!(load(&mut cpu, &elf).err(), Some(ElfError::SegmentOutOfRange{ addr: cpu::MEM_BYTES - 4, size: 8 }));
+        cpu.set_mem_size(2 * cpu::MEM_BYTES);
+        assert_eq!(load(&mut cpu, &elf), Ok(()));
+    }
+}
@@ -1,7 +1,6 @@
-use std::iter;
-use crate::*;
-
 pub fn nand(val1: bool, val2: bool) -> bool {
+    #[cfg(feature = "count")]
+    crate::count::tally_nand();
     !(val1 & val2)
 }
 
@@ -9,18 +8,24 @@ pub fn not(val: bool) -> bool {
     nand(val, val)
 }
 
+/// notN - Bitwise not of an N-bit bus
+pub fn not_n<const N: usize>(val: &[bool; N]) -> [bool; N] {
+    val.map(not)
+}
+
 pub fn not16(val: &[bool; 16]) -> [bool; 16] {
-    let mut res = [false; 16];
-    for i in 0..16 {
-        res[i] = not(val[i]);
-    }
-    res
+    not_n(val)
 }
 
 pub fn not32(val: &[bool; 32]) -> [bool; 32] {
-    let mut res = [false; 32];
-    for i in 0..32 {
-        res[i] = not(val[i]);
+    not_n(val)
+}
+
+// applies a two-input gate to every pair of bits of two buses
+fn bitwise<const N: usize>(val1: &[bool; N], val2: &[bool; N], gate: fn(bool, bool) -> bool) -> [bool; N] {
+    let mut res = [false; N];
+    for i in 0..N {
+        res[i] = gate(val1[i], val2[i]);
     }
     res
 }
@@ -29,42 +34,35 @@ pub fn and(val1: bool, val2: bool) -> bool {
     not(nand(val1, val2))
 }
 
-pub fn and16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] { 
-    let mut res = [false; 16];
-    for i in 0..16 {
-        res[i] = and(val1[i], val2[i]);
-    }
-    res
+pub fn and_n<const N: usize>(val1: &[bool; N], val2: &[bool; N]) -> [bool; N] {
+    bitwise(val1, val2, and)
 }
 
-pub fn and32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] { 
-    let mut res = [false; 32];
-    for i in 0..32 {
-        res[i] = and(val1[i], val2[i]);
-    }
-    res
+pub fn and16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] {
+    and_n(val1, val2)
+}
+
+pub fn and32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] {
+    and_n(val1, val2)
 }
 
 pub fn or(val1: bool, val2: bool) -> bool {
     nand(not(val1), not(val2))
 }
 
-pub fn or16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] { 
-    let mut res = [false; 16];
-    for i in 0..16 {
-        res[i] = or(val1[i], val2[i]);
-    }
-    res
+pub fn or_n<const N: usize>(val1: &[bool; N], val2: &[bool; N]) -> [bool; N] {
+    bitwise(val1, val2, or)
 }
 
-pub fn or32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] { 
-    let mut res = [false; 32];
-    for i in 0..32 {
-        res[i] = or(val1[i], val2[i]);
-    }
-    res
+pub fn or16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] {
+    or_n(val1, val2)
+}
+
+pub fn or32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] {
+    or_n(val1, val2)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn or8way(val1: bool, val2: bool, val3: bool, val4: bool, val5: bool, val6: bool, val7: bool, val8: bool) -> bool {
     or(or(or(val1, val2), or(val3, val4)),or(or(val5, val6), or(val7, val8)))
 }
@@ -77,64 +75,68 @@ pub fn xor(val1: bool, val2: bool) -> bool {
     and(or(val1, val2), nand(val1, val2))
 }
 
-pub fn xor16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] { 
-    let mut res = [false; 16];
-    for i in 0..16 {
-        res[i] = xor(val1[i], val2[i]);
-    }
-    res
+pub fn xor_n<const N: usize>(val1: &[bool; N], val2: &[bool; N]) -> [bool; N] {
+    bitwise(val1, val2, xor)
 }
 
-pub fn xor32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] { 
-    let mut res = [false; 32];
-    for i in 0..32 {
-        res[i] = xor(val1[i], val2[i]);
-    }
-    res
+pub fn xor16(val1: &[bool; 16], val2: &[bool; 16]) -> [bool; 16] {
+    xor_n(val1, val2)
+}
+
+pub fn xor32(val1: &[bool; 32], val2: &[bool; 32]) -> [bool; 32] {
+    xor_n(val1, val2)
 }
 
 pub fn mux(val1: bool, val2: bool, sel: bool) -> bool {
     or(and(val1, not(sel)), and(val2, sel))    
 }
 
-pub fn mux16(val1: &[bool; 16], val2: &[bool; 16], sel: bool) -> [bool; 16] { 
-    let mut res = [false; 16];
-    for i in 0..16 {
+pub fn mux_n<const N: usize>(val1: &[bool; N], val2: &[bool; N], sel: bool) -> [bool; N] {
+    let mut res = [false; N];
+    for i in 0..N {
         res[i] = mux(val1[i], val2[i], sel);
     }
     res
 }
 
-pub fn mux4way16(vals: &[[bool; 16]; 4], sel: (bool, bool)) -> [bool; 16] { 
-    mux16(&mux16(&vals[0], &vals[1], sel.1), 
-        &mux16(&vals[2], &vals[3], sel.1), 
+/// mux4wayN - Select one of four N-bit buses
+/// Function: sel=(s0, s1) picks vals[2*s0 + s1]
+pub fn mux4way_n<const N: usize>(vals: &[[bool; N]; 4], sel: (bool, bool)) -> [bool; N] {
+    mux_n(&mux_n(&vals[0], &vals[1], sel.1),
+        &mux_n(&vals[2], &vals[3], sel.1),
         sel.0)
 }
 
-pub fn mux8way16(vals: &[[bool; 16]; 8], sel: (bool, bool, bool)) -> [bool; 16] { 
-    mux16(&mux4way16(&[vals[0], vals[1], vals[2], vals[3]], (sel.1, sel.2)), 
-        &mux4way16(&[vals[4], vals[5], vals[6], vals[7]], (sel.1, sel.2)), 
+/// mux8wayN - Select one of eight N-bit buses
+/// Function: sel=(s0, s1, s2) picks vals[4*s0 + 2*s1 + s2]
+pub fn mux8way_n<const N: usize>(vals: &[[bool; N]; 8], sel: (bool, bool, bool)) -> [bool; N] {
+    mux_n(&mux4way_n(&[vals[0], vals[1], vals[2], vals[3]], (sel.1, sel.2)),
+        &mux4way_n(&[vals[4], vals[5], vals[6], vals[7]], (sel.1, sel.2)),
         sel.0)
 }
 
-pub fn mux32(val1: &[bool; 32], val2: &[bool; 32], sel: bool) -> [bool; 32] { 
-    let mut res = [false; 32];
-    for i in 0..32 {
-        res[i] = mux(val1[i], val2[i], sel);
-    }
-    res
+pub fn mux16(val1: &[bool; 16], val2: &[bool; 16], sel: bool) -> [bool; 16] {
+    mux_n(val1, val2, sel)
 }
 
-pub fn mux4way32(vals: &[[bool; 32]; 4], sel: (bool, bool)) -> [bool; 32] { 
-    mux32(&mux32(&vals[0], &vals[1], sel.1), 
-        &mux32(&vals[2], &vals[3], sel.1), 
-        sel.0)
+pub fn mux4way16(vals: &[[bool; 16]; 4], sel: (bool, bool)) -> [bool; 16] {
+    mux4way_n(vals, sel)
 }
 
-pub fn mux8way32(vals: &[[bool; 32]; 8], sel: (bool, bool, bool)) -> [bool; 32] { 
-    mux32(&mux4way32(&[vals[0], vals[1], vals[2], vals[3]], (sel.1, sel.2)), 
-        &mux4way32(&[vals[4], vals[5], vals[6], vals[7]], (sel.1, sel.2)), 
-        sel.0)
+pub fn mux8way16(vals: &[[bool; 16]; 8], sel: (bool, bool, bool)) -> [bool; 16] {
+    mux8way_n(vals, sel)
+}
+
+pub fn mux32(val1: &[bool; 32], val2: &[bool; 32], sel: bool) -> [bool; 32] {
+    mux_n(val1, val2, sel)
+}
+
+pub fn mux4way32(vals: &[[bool; 32]; 4], sel: (bool, bool)) -> [bool; 32] {
+    mux4way_n(vals, sel)
+}
+
+pub fn mux8way32(vals: &[[bool; 32]; 8], sel: (bool, bool, bool)) -> [bool; 32] {
+    mux8way_n(vals, sel)
 }
 
 pub fn dmux(val: bool, sel: bool) -> (bool, bool) {
@@ -161,9 +163,24 @@ pub fn dmux8way(val: bool, sel1: bool, sel2: bool, sel3: bool) -> [bool; 8] {
     and(val, and(and(sel1, sel2), sel3))] 
 }
 
+/// dmuxN - Route an N-bit bus to one of two outputs, the other reads zero
+pub fn dmux_n<const N: usize>(val: &[bool; N], sel: bool) -> ([bool; N], [bool; N]) {
+    (and_n(val, &[not(sel); N]), and_n(val, &[sel; N]))
+}
+
+/// dmux4wayN - Route an N-bit bus to output 2*sel1 + sel2 of four
+pub fn dmux4way_n<const N: usize>(val: &[bool; N], sel1: bool, sel2: bool) -> [[bool; N]; 4] {
+    dmux4way(true, sel1, sel2).map(|line| and_n(val, &[line; N]))
+}
+
+/// dmux8wayN - Route an N-bit bus to output 4*sel1 + 2*sel2 + sel3 of eight
+pub fn dmux8way_n<const N: usize>(val: &[bool; N], sel1: bool, sel2: bool, sel3: bool) -> [[bool; N]; 8] {
+    dmux8way(true, sel1, sel2, sel3).map(|line| and_n(val, &[line; N]))
+}
+
 #[cfg(test)]
 mod tests {
-    use utils::boollist_to_bytes;
+    use crate::utils;
 
     use super::*;
 
@@ -419,11 +436,11 @@ mod tests {
     fn test_or8way_works() {
         for byte in 0..=255 {
             let mut expected: bool = true;
-            if (byte == 0) { expected = false; }
+            if byte == 0 { expected = false; }
             
             let mut inputs: [bool; 8] = [false; 8];
             for i in 0..8 {
-                inputs[i] = (((byte >> i) & 1) == 1);
+                inputs[i] = ((byte >> i) & 1) == 1;
             }
             assert_eq!(or8way(inputs[0], inputs[1], inputs[2], inputs[3], inputs[4], inputs[5], inputs[6], inputs[7]), expected);
         }
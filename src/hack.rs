@@ -0,0 +1,235 @@
+use crate::*;
+use adder::HackCtrl;
+use memory::{Register, PC16, ROM32K, HackMemory, RamBackend, GateLevel};
+use bus::Device;
+
+/// HackCpuOut - Outputs of the Hack CPU for one clock cycle
+pub struct HackCpuOut {
+    pub out_m: [bool; 16],      // value to write to M
+    pub write_m: bool,          // write out_m to memory[address_m]
+    pub address_m: [bool; 15],  // address of M
+    pub pc: [bool; 15],         // address of the current instruction
+}
+
+/// HackCpu - The nand2tetris chapter 5 CPU
+/// State: A, D (Register), PC (PC16)
+/// Inputs: inM[16], instruction[16], reset
+/// Outputs: HackCpuOut
+/// Function: A-instruction 0vvv vvvv vvvv vvvv loads v into A.
+///           C-instruction 111a cccc ccdd djjj computes comp on D and A (a=0) or M (a=1)
+///           through hack_alu with c1..c6 as zx nx zy ny f no, stores it in the
+///           d1=A d2=D d3=M destinations and jumps to A if j1=out<0, j2=out=0 or j3=out>0.
+///           reset sets PC to 0.
+#[derive(Copy, Clone, Debug)]
+pub struct HackCpu {
+    a: Register,
+    d: Register,
+    pc: PC16,
+}
+
+impl HackCpu {
+    pub fn new() -> HackCpu {
+        HackCpu{ a: Register::new(), d: Register::new(), pc: PC16::new() }
+    }
+
+    pub fn read_a(&self) -> [bool; 16] {
+        self.a.read()
+    }
+
+    pub fn read_d(&self) -> [bool; 16] {
+        self.d.read()
+    }
+
+    pub fn pc(&self) -> [bool; 16] {
+        self.pc.read()
+    }
+
+    pub fn clk_cycle(&mut self, in_m: &[bool; 16], inst: &[bool; 16], reset: bool) -> HackCpuOut {
+        let is_c = inst[15];
+        let a = self.a.read();
+        let d = self.d.read();
+
+        let y = gates::mux16(&a, in_m, gates::and(is_c, inst[12]));
+        let ctrl = HackCtrl::new(inst[11], inst[10], inst[9], inst[8], inst[7], inst[6]);
+        let alu = trace_child!("ALU", adder::hack_alu(&d, &y, &ctrl));
+
+        let load_a = gates::or(gates::not(is_c), inst[5]);
+        let load_d = gates::and(is_c, inst[4]);
+        let write_m = gates::and(is_c, inst[3]);
+
+        let pos = gates::not(gates::or(alu.zr, alu.ng));
+        let cond = gates::or(gates::or(gates::and(inst[2], alu.ng), gates::and(inst[1], alu.zr)), gates::and(inst[0], pos));
+        let jump = gates::and(is_c, cond);
+
+        let pc = trace_child!("PC", self.pc.clk_cycle(&a, true, jump, reset));
+        trace_child!("ARegister", self.a.clk_cycle(&gates::mux16(inst, &alu.out, is_c), load_a));
+        trace_child!("DRegister", self.d.clk_cycle(&alu.out, load_d));
+
+        let mut address_m = [false; 15];
+        address_m.copy_from_slice(&a[0..15]);
+        let mut pc_out = [false; 15];
+        pc_out.copy_from_slice(&pc[0..15]);
+        trace_ports!(("inM", in_m), ("instruction", inst), ("reset", &[reset]),
+                     ("outM", &alu.out), ("writeM", &[write_m]), ("addressM", &address_m), ("pc", &pc_out));
+        HackCpuOut{ out_m: alu.out, write_m, address_m, pc: pc_out }
+    }
+}
+
+impl Default for HackCpu {
+    fn default() -> HackCpu { HackCpu::new() }
+}
+
+/// Computer - The nand2tetris Hack computer
+/// State: HackCpu, ROM32K instruction memory on backend B, data memory M
+///        (HackMemory on backend B unless with_memory puts e.g. a bus::Bus there)
+/// Function: each clk_cycle fetches ROM[pc], feeds M=memory[A] to the CPU
+///           and latches out_m into memory[A] when write_m is set
+#[derive(Clone, Debug)]
+pub struct Computer<B: RamBackend = GateLevel, M: Device = HackMemory<B>> {
+    cpu: HackCpu,
+    rom: ROM32K<B>,
+    mem: M,
+}
+
+impl Computer {
+    pub fn new() -> Computer {
+        Computer::with_backend()
+    }
+}
+
+impl<B: RamBackend> Computer<B> {
+    pub fn with_backend() -> Computer<B> {
+        Computer::with_memory(HackMemory::with_backend())
+    }
+
+    pub fn set_key(&mut self, key: &[bool; 16]) {
+        self.mem.set_key(key);
+    }
+}
+
+impl<B: RamBackend, M: Device> Computer<B, M> {
+    /// A computer whose data memory is mem, decoding the 15-bit addressM
+    pub fn with_memory(mem: M) -> Computer<B, M> {
+        Computer{ cpu: HackCpu::new(), rom: ROM32K::with_backend(), mem }
+    }
+
+    pub fn load_program(&mut self, program: &[[bool; 16]]) {
+        self.rom.flash(program);
+    }
+
+    pub fn cpu(&self) -> &HackCpu {
+        &self.cpu
+    }
+
+    /// Data memory, e.g. to reach the devices of a bus
+    pub fn mem(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Read a data memory word without writing it
+    pub fn read_mem(&mut self, addr: &[bool]) -> [bool; 16] {
+        self.mem.clk_cycle(&[false; 16], addr, true, false)
+    }
+
+    /// Write a data memory word from outside the machine, e.g. test inputs in R0..R15
+    pub fn write_mem(&mut self, addr: &[bool], val: &[bool; 16]) {
+        self.mem.clk_cycle(val, addr, true, true);
+    }
+
+    pub fn clk_cycle(&mut self, reset: bool) {
+        let inst = trace_child!("ROM32K", self.rom.clk_cycle(&self.cpu.pc()[0..15]));
+        let a = self.cpu.read_a();
+        // memory is only clocked when the instruction reads or writes M, so a device
+        // such as a UART sees exactly one access per D=M or M=D
+        let mut in_m = [false; 16];
+        if gates::and(inst[15], inst[12]) {
+            in_m = trace_child!("Memory", self.mem.clk_cycle(&[false; 16], &a[0..15], true, false));
+        }
+        let out = trace_child!("CPU", self.cpu.clk_cycle(&in_m, &inst, reset));
+        if out.write_m {
+            trace_child!("Memory", self.mem.clk_cycle(&out.out_m, &out.address_m, true, true));
+        }
+        trace_ports!(("reset", &[reset]));
+    }
+
+    pub fn run(&mut self, cycles: usize) {
+        for _ in 0..cycles {
+            self.clk_cycle(false);
+        }
+    }
+}
+
+impl<B: RamBackend> Default for Computer<B> {
+    fn default() -> Computer<B> { Computer::with_backend() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(val: u16) -> [bool; 16] {
+        utils::bytes_to_boollist(&utils::split_u16(val))
+    }
+
+    #[test]
+    fn test_hack_cpu_works() {
+        let mut cpu = HackCpu::new();
+        let zeros = word(0);
+
+        // @17, D=A, @5, AD=D-A, D;JGT, then M=M+1 with M=99
+        let out = cpu.clk_cycle(&zeros, &word(17), false);
+        assert_eq!((out.write_m, out.pc), (false, [false; 15]));
+        cpu.clk_cycle(&zeros, &word(0xec10), false);
+        assert_eq!(cpu.read_d(), word(17));
+        cpu.clk_cycle(&zeros, &word(5), false);
+        cpu.clk_cycle(&zeros, &word(0xe4f0), false);
+        assert_eq!((cpu.read_a(), cpu.read_d()), (word(12), word(12)));
+        cpu.clk_cycle(&zeros, &word(0xe301), false);
+        assert_eq!(cpu.pc(), word(12));
+
+        let out = cpu.clk_cycle(&word(99), &word(0xfdc8), false);
+        assert_eq!(out.out_m, word(100));
+        assert_eq!(out.write_m, true);
+        assert_eq!(utils::boollist_to_u32(&out.address_m), 12);
+        assert_eq!(utils::boollist_to_u32(&out.pc), 12);
+        assert_eq!(cpu.pc(), word(13));
+
+        // 0;JMP always jumps, D;JLT does not when D>0, reset wins
+        cpu.clk_cycle(&zeros, &word(0xea87), false);
+        assert_eq!(cpu.pc(), word(12));
+        cpu.clk_cycle(&zeros, &word(0xe304), false);
+        assert_eq!(cpu.pc(), word(13));
+        cpu.clk_cycle(&zeros, &word(0xea87), true);
+        assert_eq!(cpu.pc(), zeros);
+    }
+
+    #[test]
+    fn test_computer_works() {
+        // R2 = R0 + R1
+        let program = [
+            0x0000, // @R0
+            0xfc10, // D=M
+            0x0001, // @R1
+            0xf090, // D=D+M
+            0x0002, // @R2
+            0xe308, // M=D
+        ].map(word);
+        let mut computer = Computer::new();
+        computer.load_program(&program);
+        computer.write_mem(&word(0), &word(1234));
+        computer.write_mem(&word(1), &word(4321));
+        computer.run(6);
+        assert_eq!(computer.read_mem(&word(2)), word(5555));
+        assert_eq!(computer.cpu().pc(), word(6));
+
+        // screen and keyboard are mapped above the RAM
+        computer.write_mem(&word(0x4000), &word(0xffff));
+        computer.set_key(&word(75));
+        assert_eq!(computer.read_mem(&word(0x4000)), word(0xffff));
+        assert_eq!(computer.read_mem(&word(0x6000)), word(75));
+        assert_eq!(computer.read_mem(&word(0)), word(1234));
+
+        computer.clk_cycle(true);
+        assert_eq!(computer.cpu().pc(), word(0));
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::*;
+
+/// AsmError - Assembly failure at a 1-based source line
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+// a bit followed by c1..c6, which hack::HackCpu feeds to HackCtrl as zx nx zy ny f no
+fn comp_bits(comp: &str) -> Option<u16> {
+    let bits = match comp {
+        "0"   => 0b0101010,
+        "1"   => 0b0111111,
+        "-1"  => 0b0111010,
+        "D"   => 0b0001100,
+        "A"   => 0b0110000, "M"   => 0b1110000,
+        "!D"  => 0b0001101,
+        "!A"  => 0b0110001, "!M"  => 0b1110001,
+        "-D"  => 0b0001111,
+        "-A"  => 0b0110011, "-M"  => 0b1110011,
+        "D+1" => 0b0011111,
+        "A+1" => 0b0110111, "M+1" => 0b1110111,
+        "D-1" => 0b0001110,
+        "A-1" => 0b0110010, "M-1" => 0b1110010,
+        "D+A" | "A+D" => 0b0000010, "D+M" | "M+D" => 0b1000010,
+        "D-A" => 0b0010011, "D-M" => 0b1010011,
+        "A-D" => 0b0000111, "M-D" => 0b1000111,
+        "D&A" | "A&D" => 0b0000000, "D&M" | "M&D" => 0b1000000,
+        "D|A" | "A|D" => 0b0010101, "D|M" | "M|D" => 0b1010101,
+        _ => return None,
+    };
+    Some(bits)
+}
+
+// d1=A d2=D d3=M, letters in any order
+fn dest_bits(dest: &str) -> Option<u16> {
+    let mut bits = 0;
+    for c in dest.chars() {
+        let bit = match c {
+            'A' => 0b100,
+            'D' => 0b010,
+            'M' => 0b001,
+            _ => return None,
+        };
+        if bits & bit != 0 { return None; }
+        bits |= bit;
+    }
+    Some(bits)
+}
+
+fn jump_bits(jump: &str) -> Option<u16> {
+    let bits = match jump {
+        "JGT" => 0b001,
+        "JEQ" => 0b010,
+        "JGE" => 0b011,
+        "JLT" => 0b100,
+        "JNE" => 0b101,
+        "JLE" => 0b110,
+        "JMP" => 0b111,
+        _ => return None,
+    };
+    Some(bits)
+}
+
+fn predefined_symbols() -> HashMap<String, u16> {
+    let mut symbols = HashMap::new();
+    for (name, addr) in [("SP", 0), ("LCL", 1), ("ARG", 2), ("THIS", 3), ("THAT", 4),
+                         ("SCREEN", 0x4000), ("KBD", 0x6000)] {
+        symbols.insert(name.to_string(), addr);
+    }
+    for i in 0..16 {
+        symbols.insert(format!("R{}", i), i);
+    }
+    symbols
+}
+
+fn is_symbol(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if !c.is_ascii_digit() => {},
+        _ => return false,
+    }
+    name.chars().all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c))
+}
+
+fn c_instruction(inst: &str, line: usize) -> Result<u16, AsmError> {
+    let err = |msg: String| AsmError{ line, msg };
+    let (dest, rest) = match inst.split_once('=') {
+        Some((dest, rest)) => (dest, rest),
+        None => ("", inst),
+    };
+    let (comp, jump) = match rest.split_once(';') {
+        Some((comp, jump)) => (comp, jump),
+        None => (rest, ""),
+    };
+
+    let comp = comp_bits(comp).ok_or_else(|| err(format!("unknown comp '{}'", comp)))?;
+    let dest = dest_bits(dest).ok_or_else(|| err(format!("unknown dest '{}'", dest)))?;
+    let jump = match jump {
+        "" => 0,
+        _ => jump_bits(jump).ok_or_else(|| err(format!("unknown jump '{}'", jump)))?,
+    };
+    Ok(0b111 << 13 | comp << 6 | dest << 3 | jump)
+}
+
+/// Assemble Hack assembly into machine words
+/// Two passes: the first binds (LABEL)s to ROM addresses, the second encodes
+/// instructions and allocates unknown @symbols as variables from RAM[16] up.
+pub fn assemble_u16(src: &str) -> Result<Vec<u16>, AsmError> {
+    // strip comments and all whitespace, keep 1-based line numbers for errors
+    let lines: Vec<(usize, String)> = src.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let code = line.split("//").next().unwrap_or("");
+            (i + 1, code.chars().filter(|c| !c.is_whitespace()).collect::<String>())
+        })
+        .filter(|(_, code)| !code.is_empty())
+        .collect();
+
+    let mut symbols = predefined_symbols();
+    let mut rom_addr = 0;
+    for (line, code) in &lines {
+        if let Some(label) = code.strip_prefix('(') {
+            let label = label.strip_suffix(')')
+                .filter(|label| is_symbol(label))
+                .ok_or_else(|| AsmError{ line: *line, msg: format!("bad label '{}'", code) })?;
+            if symbols.insert(label.to_string(), rom_addr).is_some() {
+                return Err(AsmError{ line: *line, msg: format!("duplicate symbol '{}'", label) });
+            }
+        } else {
+            rom_addr += 1;
+        }
+    }
+
+    let mut words = Vec::new();
+    let mut next_var = 16;
+    for (line, code) in &lines {
+        if code.starts_with('(') { continue; }
+        let word = match code.strip_prefix('@') {
+            Some(value) if value.starts_with(|c: char| c.is_ascii_digit()) => {
+                match value.parse::<u16>() {
+                    Ok(val) if val < 0x8000 => val,
+                    _ => return Err(AsmError{ line: *line, msg: format!("constant '{}' out of range", value) }),
+                }
+            }
+            Some(name) if is_symbol(name) => {
+                *symbols.entry(name.to_string()).or_insert_with(|| {
+                    next_var += 1;
+                    next_var - 1
+                })
+            }
+            Some(name) => return Err(AsmError{ line: *line, msg: format!("bad symbol '{}'", name) }),
+            None => c_instruction(code, *line)?,
+        };
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Assemble Hack assembly into words ready to flash into ROM32K
+pub fn assemble(src: &str) -> Result<Vec<[bool; 16]>, AsmError> {
+    let words = assemble_u16(src)?;
+    Ok(words.iter().map(|word| utils::bytes_to_boollist(&utils::split_u16(*word))).collect())
+}
+
+/// Format machine words as a .hack file, one 16-digit binary word per line
+pub fn to_hack_text(words: &[[bool; 16]]) -> String {
+    let mut res = String::new();
+    for word in words {
+        for bit in word.iter().rev() {
+            res.push(if *bit { '1' } else { '0' });
+        }
+        res.push('\n');
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_ASM: &str = "
+// Computes R2 = max(R0, R1)
+   @R0
+   D=M              // D = first number
+   @R1
+   D=D-M            // D = first number - second number
+   @OUTPUT_FIRST
+   D;JGT            // if D>0 (first is greater) goto output_first
+   @R1
+   D=M              // D = second number
+   @OUTPUT_D
+   0;JMP            // goto output_d
+(OUTPUT_FIRST)
+   @R0
+   D=M              // D = first number
+(OUTPUT_D)
+   @R2
+   M=D              // M[2] = D (greatest number)
+(INFINITE_LOOP)
+   @INFINITE_LOOP
+   0;JMP            // infinite loop
+";
+
+    const MAX_HACK: &str = "\
+0000000000000000
+1111110000010000
+0000000000000001
+1111010011010000
+0000000000001010
+1110001100000001
+0000000000000001
+1111110000010000
+0000000000001100
+1110101010000111
+0000000000000000
+1111110000010000
+0000000000000010
+1110001100001000
+0000000000001110
+1110101010000111
+";
+
+    #[test]
+    fn test_assemble_max_works() {
+        let words = assemble(MAX_ASM).unwrap();
+        assert_eq!(to_hack_text(&words), MAX_HACK);
+    }
+
+    #[test]
+    fn test_c_instruction_works() {
+        assert_eq!(c_instruction("D=A", 1), Ok(0xec10));
+        assert_eq!(c_instruction("AD=D-A", 1), Ok(0xe4f0));
+        assert_eq!(c_instruction("M=M+1", 1), Ok(0xfdc8));
+        assert_eq!(c_instruction("DM=M+D", 1), Ok(0xf098));
+        assert_eq!(c_instruction("AMD=0;JMP", 1), Ok(0xeabf));
+        assert_eq!(c_instruction("D;JLE", 1), Ok(0xe306));
+        assert!(c_instruction("D=X", 1).is_err());
+        assert!(c_instruction("DD=A", 1).is_err());
+        assert!(c_instruction("D;JXX", 1).is_err());
+    }
+
+    #[test]
+    fn test_symbols_work() {
+        let src = "@SP\n@LCL\n@R15\n@SCREEN\n@KBD\n@i\n@sum\n@i\n(END)\n@END\n@32767";
+        assert_eq!(assemble_u16(src), Ok(vec![0, 1, 15, 0x4000, 0x6000, 16, 17, 16, 8, 32767]));
+    }
+
+    #[test]
+    fn test_assemble_errors_work() {
+        assert_eq!(assemble_u16("@1\n\n  @32768").unwrap_err().line, 3);
+        assert_eq!(assemble_u16("(X)\n(X)").unwrap_err().msg, "duplicate symbol 'X'");
+        assert!(assemble_u16("(1X)").is_err());
+        assert!(assemble_u16("@-1").is_err());
+        assert!(assemble_u16("D=Q").is_err());
+    }
+}
@@ -0,0 +1,1142 @@
+// nand2tetris HDL front end: parse .hdl chip definitions and simulate them
+// on top of the native chips. User chips are flattened into built-in parts
+// whose pins share nets, the parts are evaluated in dependency order, and
+// clocked parts (DFF, Bit, Register, PC, RAMn, Screen, Memory, CPU) latch on tick/tock.
+// ROM32K and Keyboard are filled from outside, with load_rom and set_key.
+use std::collections::HashMap;
+use std::{fmt, fs, path};
+use crate::*;
+use memory::{BitRegister, HackMemory, Register, WordMemory, DFF, PC16, ROM32K};
+
+/// HdlError - Parse or build failure, line is 0 when there is no source line
+#[derive(Debug, PartialEq)]
+pub struct HdlError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for HdlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.msg),
+            line => write!(f, "line {}: {}", line, self.msg),
+        }
+    }
+}
+
+fn err<T>(line: usize, msg: String) -> Result<T, HdlError> {
+    Err(HdlError{ line, msg })
+}
+
+/// Signal - Right-hand side of a connection
+#[derive(Clone, Debug, PartialEq)]
+pub enum Signal {
+    Const(bool),
+    Wire { name: String, range: Option<(usize, usize)> },
+}
+
+/// Conn - One pin=signal connection of a part, ranges are inclusive bit indices
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conn {
+    pub pin: String,
+    pub range: Option<(usize, usize)>,
+    pub signal: Signal,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartDef {
+    pub chip: String,
+    pub conns: Vec<Conn>,
+    pub line: usize,
+}
+
+/// ChipDef - A parsed CHIP, either PARTS or a BUILTIN implementation
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChipDef {
+    pub name: String,
+    pub inputs: Vec<(String, usize)>,
+    pub outputs: Vec<(String, usize)>,
+    pub parts: Vec<PartDef>,
+    pub builtin: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Ident(String),
+    Num(usize),
+    Sym(&'static str),
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Tok, usize)>, HdlError> {
+    const SYMS: [&str; 10] = ["..", "{", "}", "(", ")", "[", "]", ";", ",", "="];
+    let mut toks = Vec::new();
+    let mut line = 1;
+    let mut rest = src;
+    while let Some(c) = rest.chars().next() {
+        if c == '\n' { line += 1; }
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if rest.starts_with("/*") {
+            let end = rest.find("*/").ok_or_else(|| HdlError{ line, msg: "unterminated comment".to_string() })?;
+            line += rest[..end].matches('\n').count();
+            rest = &rest[end + 2..];
+        } else if c == ':' {
+            // PARTS: is the only use of a colon
+            rest = &rest[1..];
+        } else if let Some(sym) = SYMS.iter().find(|sym| rest.starts_with(**sym)) {
+            toks.push((Tok::Sym(sym), line));
+            rest = &rest[sym.len()..];
+        } else if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let num = rest[..end].parse().map_err(|_| HdlError{ line, msg: format!("bad number '{}'", &rest[..end]) })?;
+            toks.push((Tok::Num(num), line));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            toks.push((Tok::Ident(rest[..end].to_string()), line));
+            rest = &rest[end..];
+        } else {
+            return err(line, format!("unexpected character '{}'", c));
+        }
+    }
+    Ok(toks)
+}
+
+struct Parser {
+    toks: Vec<(Tok, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.toks.get(self.pos).or(self.toks.last()).map_or(1, |(_, line)| *line)
+    }
+
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.pos).map(|(tok, _)| tok)
+    }
+
+    fn next(&mut self, what: &str) -> Result<Tok, HdlError> {
+        let tok = self.peek().cloned().ok_or_else(|| HdlError{ line: self.line(), msg: format!("expected {}, found end of file", what) })?;
+        self.pos += 1;
+        Ok(tok)
+    }
+
+    fn is_sym(&self, sym: &str) -> bool {
+        matches!(self.peek(), Some(Tok::Sym(s)) if *s == sym)
+    }
+
+    fn sym(&mut self, sym: &str) -> Result<(), HdlError> {
+        let line = self.line();
+        match self.next(&format!("'{}'", sym))? {
+            Tok::Sym(s) if s == sym => Ok(()),
+            tok => err(line, format!("expected '{}', found {:?}", sym, tok)),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, HdlError> {
+        let line = self.line();
+        match self.next("a name")? {
+            Tok::Ident(name) => Ok(name),
+            tok => err(line, format!("expected a name, found {:?}", tok)),
+        }
+    }
+
+    fn num(&mut self) -> Result<usize, HdlError> {
+        let line = self.line();
+        match self.next("a number")? {
+            Tok::Num(num) => Ok(num),
+            tok => err(line, format!("expected a number, found {:?}", tok)),
+        }
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        if self.peek() == Some(&Tok::Ident(word.to_string())) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    // name or name[width]
+    fn pin_list(&mut self) -> Result<Vec<(String, usize)>, HdlError> {
+        let mut pins = Vec::new();
+        loop {
+            let name = self.ident()?;
+            let mut width = 1;
+            if self.is_sym("[") {
+                self.sym("[")?;
+                width = self.num()?;
+                self.sym("]")?;
+            }
+            pins.push((name, width));
+            if !self.is_sym(",") { break; }
+            self.sym(",")?;
+        }
+        self.sym(";")?;
+        Ok(pins)
+    }
+
+    // [i] or [i..j]
+    fn range(&mut self) -> Result<Option<(usize, usize)>, HdlError> {
+        if !self.is_sym("[") { return Ok(None); }
+        let line = self.line();
+        self.sym("[")?;
+        let lo = self.num()?;
+        let mut hi = lo;
+        if self.is_sym("..") {
+            self.sym("..")?;
+            hi = self.num()?;
+        }
+        self.sym("]")?;
+        if hi < lo {
+            return err(line, format!("bad range [{}..{}]", lo, hi));
+        }
+        Ok(Some((lo, hi)))
+    }
+
+    fn part(&mut self) -> Result<PartDef, HdlError> {
+        let line = self.line();
+        let chip = self.ident()?;
+        self.sym("(")?;
+        let mut conns = Vec::new();
+        while !self.is_sym(")") {
+            let pin = self.ident()?;
+            let range = self.range()?;
+            self.sym("=")?;
+            let signal = match self.ident()?.as_str() {
+                "true" => Signal::Const(true),
+                "false" => Signal::Const(false),
+                name => Signal::Wire{ name: name.to_string(), range: self.range()? },
+            };
+            conns.push(Conn{ pin, range, signal });
+            if !self.is_sym(",") { break; }
+            self.sym(",")?;
+        }
+        self.sym(")")?;
+        self.sym(";")?;
+        Ok(PartDef{ chip, conns, line })
+    }
+
+    fn chip(&mut self) -> Result<ChipDef, HdlError> {
+        let line = self.line();
+        if !self.keyword("CHIP") {
+            return err(line, "expected CHIP".to_string());
+        }
+        let name = self.ident()?;
+        self.sym("{")?;
+        let mut def = ChipDef{ name, inputs: Vec::new(), outputs: Vec::new(), parts: Vec::new(), builtin: None };
+        loop {
+            if self.keyword("IN") {
+                def.inputs.extend(self.pin_list()?);
+            } else if self.keyword("OUT") {
+                def.outputs.extend(self.pin_list()?);
+            } else if self.keyword("BUILTIN") {
+                def.builtin = Some(self.ident()?);
+                self.sym(";")?;
+            } else if self.keyword("CLOCKED") {
+                // the built-in library already knows which of its chips are clocked
+                self.pin_list()?;
+            } else if self.keyword("PARTS") {
+                while !self.is_sym("}") {
+                    def.parts.push(self.part()?);
+                }
+            } else {
+                break;
+            }
+        }
+        self.sym("}")?;
+        if self.peek().is_some() {
+            return err(self.line(), "unexpected text after the chip".to_string());
+        }
+        Ok(def)
+    }
+}
+
+/// Parse one CHIP definition
+pub fn parse(src: &str) -> Result<ChipDef, HdlError> {
+    Parser{ toks: tokenize(src)?, pos: 0 }.chip()
+}
+
+type CombFn = fn(&[Vec<bool>]) -> Vec<Vec<bool>>;
+
+// how a built-in part computes its outputs
+enum Prim {
+    Comb(CombFn),
+    Dff(DFF),
+    Bit(BitRegister),
+    Register(Register),
+    Pc(PC16),
+    Cpu(hack::HackCpu),
+    Ram(Box<dyn WordMemory>),
+    Rom(Box<ROM32K>),
+    Memory(Box<HackMemory>),
+    Keyboard(Register),
+}
+
+fn bus16(bits: &[bool]) -> [bool; 16] {
+    let mut res = [false; 16];
+    res.copy_from_slice(&bits[0..16]);
+    res
+}
+
+const AB: &[(&str, usize)] = &[("a", 1), ("b", 1)];
+const AB16: &[(&str, usize)] = &[("a", 16), ("b", 16)];
+const OUT: &[(&str, usize)] = &[("out", 1)];
+const OUT16: &[(&str, usize)] = &[("out", 16)];
+
+type Pins = Vec<(&'static str, usize)>;
+type PinSpec = &'static [(&'static str, usize)];
+
+// pins and behaviour of the built-in chips, backed by the native gates and chips
+fn builtin(name: &str) -> Option<(Pins, Pins, Prim)> {
+    let (ins, outs, prim): (PinSpec, PinSpec, Prim) = match name {
+        "Nand" => (AB, OUT, Prim::Comb(|i| vec![vec![gates::nand(i[0][0], i[1][0])]])),
+        "Not" => (&[("in", 1)], OUT, Prim::Comb(|i| vec![vec![gates::not(i[0][0])]])),
+        "And" => (AB, OUT, Prim::Comb(|i| vec![vec![gates::and(i[0][0], i[1][0])]])),
+        "Or" => (AB, OUT, Prim::Comb(|i| vec![vec![gates::or(i[0][0], i[1][0])]])),
+        "Xor" => (AB, OUT, Prim::Comb(|i| vec![vec![gates::xor(i[0][0], i[1][0])]])),
+        "Mux" => (&[("a", 1), ("b", 1), ("sel", 1)], OUT, Prim::Comb(|i| vec![vec![gates::mux(i[0][0], i[1][0], i[2][0])]])),
+        "DMux" => (&[("in", 1), ("sel", 1)], AB, Prim::Comb(|i| {
+            let (a, b) = gates::dmux(i[0][0], i[1][0]);
+            vec![vec![a], vec![b]]
+        })),
+        "Not16" => (&[("in", 16)], OUT16, Prim::Comb(|i| vec![gates::not16(&bus16(&i[0])).to_vec()])),
+        "And16" => (AB16, OUT16, Prim::Comb(|i| vec![gates::and16(&bus16(&i[0]), &bus16(&i[1])).to_vec()])),
+        "Or16" => (AB16, OUT16, Prim::Comb(|i| vec![gates::or16(&bus16(&i[0]), &bus16(&i[1])).to_vec()])),
+        "Mux16" => (&[("a", 16), ("b", 16), ("sel", 1)], OUT16,
+                    Prim::Comb(|i| vec![gates::mux16(&bus16(&i[0]), &bus16(&i[1]), i[2][0]).to_vec()])),
+        "Or8Way" => (&[("in", 8)], OUT, Prim::Comb(|i| {
+            let v = &i[0];
+            vec![vec![gates::or8way(v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7])]]
+        })),
+        "Mux4Way16" => (&[("a", 16), ("b", 16), ("c", 16), ("d", 16), ("sel", 2)], OUT16, Prim::Comb(|i| {
+            let vals = [bus16(&i[0]), bus16(&i[1]), bus16(&i[2]), bus16(&i[3])];
+            vec![gates::mux4way16(&vals, (i[4][1], i[4][0])).to_vec()]
+        })),
+        "Mux8Way16" => (&[("a", 16), ("b", 16), ("c", 16), ("d", 16), ("e", 16), ("f", 16), ("g", 16), ("h", 16), ("sel", 3)],
+                        OUT16, Prim::Comb(|i| {
+            let vals = [0, 1, 2, 3, 4, 5, 6, 7].map(|j| bus16(&i[j]));
+            vec![gates::mux8way16(&vals, (i[8][2], i[8][1], i[8][0])).to_vec()]
+        })),
+        "DMux4Way" => (&[("in", 1), ("sel", 2)], &[("a", 1), ("b", 1), ("c", 1), ("d", 1)], Prim::Comb(|i| {
+            gates::dmux4way(i[0][0], i[1][1], i[1][0]).iter().map(|bit| vec![*bit]).collect()
+        })),
+        "DMux8Way" => (&[("in", 1), ("sel", 3)],
+                       &[("a", 1), ("b", 1), ("c", 1), ("d", 1), ("e", 1), ("f", 1), ("g", 1), ("h", 1)], Prim::Comb(|i| {
+            gates::dmux8way(i[0][0], i[1][2], i[1][1], i[1][0]).iter().map(|bit| vec![*bit]).collect()
+        })),
+        "HalfAdder" => (AB, &[("sum", 1), ("carry", 1)], Prim::Comb(|i| {
+            let (sum, carry) = adder::half_adder(i[0][0], i[1][0]);
+            vec![vec![sum], vec![carry]]
+        })),
+        "FullAdder" => (&[("a", 1), ("b", 1), ("c", 1)], &[("sum", 1), ("carry", 1)], Prim::Comb(|i| {
+            let (sum, carry) = adder::full_adder(i[0][0], i[1][0], i[2][0]);
+            vec![vec![sum], vec![carry]]
+        })),
+        "Add16" => (AB16, OUT16, Prim::Comb(|i| vec![adder::add16(&bus16(&i[0]), &bus16(&i[1])).to_vec()])),
+        "Inc16" => (&[("in", 16)], OUT16, Prim::Comb(|i| vec![adder::inc16(&bus16(&i[0])).to_vec()])),
+        "ALU" => (&[("x", 16), ("y", 16), ("zx", 1), ("nx", 1), ("zy", 1), ("ny", 1), ("f", 1), ("no", 1)],
+                  &[("out", 16), ("zr", 1), ("ng", 1)], Prim::Comb(|i| {
+            let ctrl = adder::HackCtrl::new(i[2][0], i[3][0], i[4][0], i[5][0], i[6][0], i[7][0]);
+            let res = adder::hack_alu(&bus16(&i[0]), &bus16(&i[1]), &ctrl);
+            vec![res.out.to_vec(), vec![res.zr], vec![res.ng]]
+        })),
+        "DFF" => (&[("in", 1)], OUT, Prim::Dff(DFF::new())),
+        "Bit" => (&[("in", 1), ("load", 1)], OUT, Prim::Bit(BitRegister::new())),
+        "Register" | "ARegister" | "DRegister" => (&[("in", 16), ("load", 1)], OUT16, Prim::Register(Register::new())),
+        "PC" => (&[("in", 16), ("load", 1), ("inc", 1), ("reset", 1)], OUT16, Prim::Pc(PC16::new())),
+        "RAM8" => (&[("in", 16), ("load", 1), ("address", 3)], OUT16, Prim::Ram(Box::new(memory::RAM8::new()))),
+        "RAM64" => (&[("in", 16), ("load", 1), ("address", 6)], OUT16, Prim::Ram(Box::new(memory::RAM64::new()))),
+        "RAM512" => (&[("in", 16), ("load", 1), ("address", 9)], OUT16, Prim::Ram(Box::new(memory::RAM512::new()))),
+        "RAM4K" => (&[("in", 16), ("load", 1), ("address", 12)], OUT16, Prim::Ram(Box::new(memory::RAM4096::new()))),
+        "RAM16K" => (&[("in", 16), ("load", 1), ("address", 14)], OUT16, Prim::Ram(Box::new(memory::RAM16384::new()))),
+        "Screen" => (&[("in", 16), ("load", 1), ("address", 13)], OUT16,
+                     Prim::Ram(Box::new(memory::RAMBank::<memory::RAM4096, 2>::new()))),
+        "Keyboard" => (&[], OUT16, Prim::Keyboard(Register::new())),
+        "Memory" => (&[("in", 16), ("load", 1), ("address", 15)], OUT16, Prim::Memory(Box::new(HackMemory::new()))),
+        "ROM32K" => (&[("address", 15)], OUT16, Prim::Rom(Box::new(ROM32K::new()))),
+        "CPU" => (&[("inM", 16), ("instruction", 16), ("reset", 1)],
+                  &[("outM", 16), ("writeM", 1), ("addressM", 15), ("pc", 15)], Prim::Cpu(hack::HackCpu::new())),
+        _ => return None,
+    };
+    Some((ins.to_vec(), outs.to_vec(), prim))
+}
+
+/// Is name one of the built-in chips
+pub fn is_builtin(name: &str) -> bool {
+    builtin(name).is_some()
+}
+
+// a built-in part after flattening, pins hold net roots
+struct Part {
+    chip: String,
+    prim: Prim,
+    inputs: Vec<Vec<usize>>,
+    outputs: Vec<Vec<usize>>,
+    pending: Option<Vec<Vec<bool>>>,    // inputs sampled at tick, latched at tock
+}
+
+impl Part {
+    // outputs from the inputs and the current state, without clocking
+    fn eval(&mut self, ins: &[Vec<bool>]) -> Vec<Vec<bool>> {
+        match &mut self.prim {
+            Prim::Comb(f) => f(ins),
+            Prim::Dff(dff) => vec![vec![dff.read()]],
+            Prim::Bit(bit) => vec![vec![bit.read()]],
+            Prim::Register(reg) => vec![reg.read().to_vec()],
+            Prim::Pc(pc) => vec![pc.read().to_vec()],
+            Prim::Cpu(cpu) => {
+                // run the cycle on a copy, the outputs settle before the registers latch
+                let mut copy = *cpu;
+                let res = copy.clk_cycle(&bus16(&ins[0]), &bus16(&ins[1]), ins[2][0]);
+                vec![res.out_m.to_vec(), vec![res.write_m], res.address_m.to_vec(), res.pc.to_vec()]
+            }
+            Prim::Ram(ram) => vec![ram.read_word(utils::boollist_to_u32(&ins[2]) as usize).to_vec()],
+            Prim::Rom(rom) => vec![rom.clk_cycle(&ins[0]).to_vec()],
+            Prim::Memory(mem) => vec![mem.clk_cycle(&[false; 16], &ins[2], false).to_vec()],
+            Prim::Keyboard(key) => vec![key.read().to_vec()],
+        }
+    }
+
+    fn clock(&mut self, ins: &[Vec<bool>]) {
+        match &mut self.prim {
+            Prim::Comb(_) | Prim::Rom(_) | Prim::Keyboard(_) => {},
+            Prim::Dff(dff) => { dff.clk_cycle(ins[0][0]); }
+            Prim::Bit(bit) => { bit.clk_cycle(ins[0][0], ins[1][0]); }
+            Prim::Register(reg) => { reg.clk_cycle(&ins[0], ins[1][0]); }
+            Prim::Pc(pc) => { pc.clk_cycle(&bus16(&ins[0]), ins[2][0], ins[1][0], ins[3][0]); }
+            Prim::Cpu(cpu) => { cpu.clk_cycle(&bus16(&ins[0]), &bus16(&ins[1]), ins[2][0]); }
+            Prim::Ram(ram) => {
+                if ins[1][0] {
+                    ram.write_word(utils::boollist_to_u32(&ins[2]) as usize, &bus16(&ins[0]));
+                }
+            }
+            Prim::Memory(mem) => { mem.clk_cycle(&ins[0], &ins[2], ins[1][0]); }
+        }
+    }
+
+    // the ROM and keyboard change from outside the chip, never on a clock
+    fn is_clocked(&self) -> bool {
+        !matches!(self.prim, Prim::Comb(_) | Prim::Rom(_) | Prim::Keyboard(_))
+    }
+
+    // groups of output pins, each with the input pins that reach it within the same cycle
+    fn comb_groups(&self) -> Vec<(Vec<usize>, Vec<usize>)> {
+        let outs = (0..self.outputs.len()).collect();
+        match self.prim {
+            Prim::Comb(_) => vec![(outs, (0..self.inputs.len()).collect())],
+            // memory reads are combinational in address
+            Prim::Ram(_) | Prim::Memory(_) => vec![(outs, vec![2])],
+            Prim::Rom(_) => vec![(outs, vec![0])],
+            // outM and writeM settle through the ALU, addressM and pc come straight from registers,
+            // so a Memory part can feed inM from addressM without a loop
+            Prim::Cpu(_) => vec![(vec![0, 1], vec![0, 1]), (vec![2, 3], Vec::new())],
+            _ => vec![(outs, Vec::new())],
+        }
+    }
+}
+
+/// Library - Parsed chip definitions that parts resolve against before the built-ins
+#[derive(Clone, Debug, Default)]
+pub struct Library {
+    chips: HashMap<String, ChipDef>,
+}
+
+impl Library {
+    pub fn new() -> Library {
+        Library{ chips: HashMap::new() }
+    }
+
+    /// Parse and add a chip, returning its name
+    pub fn add(&mut self, src: &str) -> Result<String, HdlError> {
+        let def = parse(src)?;
+        let name = def.name.clone();
+        self.chips.insert(name.clone(), def);
+        Ok(name)
+    }
+
+    /// Add every .hdl file in dir, returning the chip names
+    pub fn load_dir(&mut self, dir: &path::Path) -> Result<Vec<String>, HdlError> {
+        let io_err = |e: std::io::Error| HdlError{ line: 0, msg: format!("{}: {}", dir.display(), e) };
+        let mut names = Vec::new();
+        let mut paths: Vec<path::PathBuf> = fs::read_dir(dir).map_err(io_err)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "hdl"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let src = fs::read_to_string(&path).map_err(io_err)?;
+            let name = self.add(&src).map_err(|e| HdlError{ line: 0, msg: format!("{}: {}", path.display(), e) })?;
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ChipDef> {
+        self.chips.get(name)
+    }
+
+    /// Build a simulator for the named chip, from this library or the built-ins
+    pub fn build(&self, name: &str) -> Result<Chip, HdlError> {
+        let def = match self.chips.get(name) {
+            Some(def) => def.clone(),
+            None => self.build_def(name).ok_or_else(|| HdlError{ line: 0, msg: format!("unknown chip '{}'", name) })?,
+        };
+        let mut builder = Builder{ lib: self, parent: vec![0, 1], parts: Vec::new(), active: Vec::new() };
+        let pins = builder.instantiate(&def)?;
+        builder.finish(&def, pins)
+    }
+}
+
+// flattens a chip into built-in parts, nets joined by union-find; nets 0 and 1 are false and true
+struct Builder<'a> {
+    lib: &'a Library,
+    parent: Vec<usize>,
+    parts: Vec<Part>,
+    active: Vec<String>,    // user chips being instantiated, to catch recursion
+}
+
+impl Builder<'_> {
+    fn new_nets(&mut self, width: usize) -> Vec<usize> {
+        let start = self.parent.len();
+        self.parent.extend(start..start + width);
+        (start..start + width).collect()
+    }
+
+    fn find(&mut self, net: usize) -> usize {
+        let mut root = net;
+        while self.parent[root] != root { root = self.parent[root]; }
+        let mut net = net;
+        while self.parent[net] != root {
+            let next = self.parent[net];
+            self.parent[net] = root;
+            net = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize, line: usize) -> Result<(), HdlError> {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b { return Ok(()); }
+        if a < 2 && b < 2 {
+            return err(line, "true and false are connected".to_string());
+        }
+        // constants stay roots so eval never overwrites them
+        if a < 2 { self.parent[b] = a; } else { self.parent[a] = b; }
+        Ok(())
+    }
+
+    // allocate nets for def's pins and everything inside it, returning the pin nets
+    fn instantiate(&mut self, def: &ChipDef) -> Result<HashMap<String, Vec<usize>>, HdlError> {
+        let mut scope: HashMap<String, Vec<usize>> = HashMap::new();
+        for (pin, width) in def.inputs.iter().chain(def.outputs.iter()) {
+            let nets = self.new_nets(*width);
+            scope.insert(pin.clone(), nets);
+        }
+
+        if let Some(name) = &def.builtin {
+            let (ins, outs, prim) = builtin(name).ok_or_else(|| HdlError{ line: 0, msg: format!("unknown built-in chip '{}'", name) })?;
+            let pin_nets = |pins: &Pins| -> Result<Vec<Vec<usize>>, HdlError> {
+                pins.iter().map(|(pin, width)| match scope.get(*pin) {
+                    Some(nets) if nets.len() == *width => Ok(nets.clone()),
+                    _ => err(0, format!("{} must declare pin {}[{}] to be built in", def.name, pin, width)),
+                }).collect()
+            };
+            let (inputs, outputs) = (pin_nets(&ins)?, pin_nets(&outs)?);
+            self.parts.push(Part{ chip: name.clone(), prim, inputs, outputs, pending: None });
+            return Ok(scope);
+        }
+
+        self.active.push(def.name.clone());
+        for part in &def.parts {
+            let line = part.line;
+            if self.active.contains(&part.chip) {
+                return err(line, format!("chip '{}' contains itself", part.chip));
+            }
+            let pins: HashMap<String, Vec<usize>> = match self.lib.get(&part.chip) {
+                Some(sub) => self.instantiate(&sub.clone()).map_err(|e| match e.line {
+                    0 => HdlError{ line, msg: format!("{}: {}", part.chip, e.msg) },
+                    _ => HdlError{ line, msg: format!("{}: {}", part.chip, e) },
+                })?,
+                None => {
+                    let sub = self.lib.build_def(&part.chip).ok_or_else(|| HdlError{ line, msg: format!("unknown chip '{}'", part.chip) })?;
+                    self.instantiate(&sub)?
+                }
+            };
+
+            for conn in &part.conns {
+                let pin_nets = pins.get(&conn.pin).ok_or_else(|| HdlError{ line, msg: format!("{} has no pin '{}'", part.chip, conn.pin) })?;
+                let pin_nets = slice(pin_nets, conn.range, &conn.pin, line)?;
+                let nets = match &conn.signal {
+                    Signal::Const(val) => vec![*val as usize; pin_nets.len()],
+                    Signal::Wire{ name, range } => match scope.get(name) {
+                        Some(nets) => slice(nets, *range, name, line)?,
+                        None if range.is_some() => return err(line, format!("sub-bus of undeclared pin '{}'", name)),
+                        None => {
+                            let nets = self.new_nets(pin_nets.len());
+                            scope.insert(name.clone(), nets.clone());
+                            nets
+                        }
+                    },
+                };
+                if nets.len() != pin_nets.len() {
+                    return err(line, format!("{}.{} is {} bits wide but is connected to {} bits", part.chip, conn.pin, pin_nets.len(), nets.len()));
+                }
+                for (a, b) in pin_nets.iter().zip(nets.iter()) {
+                    self.union(*a, *b, line)?;
+                }
+            }
+        }
+        self.active.pop();
+        Ok(scope)
+    }
+
+    fn finish(mut self, def: &ChipDef, pins: HashMap<String, Vec<usize>>) -> Result<Chip, HdlError> {
+        // resolve every pin to its root net
+        let mut parts = std::mem::take(&mut self.parts);
+        for part in parts.iter_mut() {
+            for pin in part.inputs.iter_mut().chain(part.outputs.iter_mut()) {
+                for net in pin.iter_mut() { *net = self.find(*net); }
+            }
+        }
+        let mut pins: HashMap<String, Vec<usize>> = pins.into_iter()
+            .map(|(pin, nets)| (pin, nets.iter().map(|net| self.find(*net)).collect()))
+            .collect();
+        pins.retain(|pin, _| def.inputs.iter().chain(def.outputs.iter()).any(|(name, _)| name == pin));
+
+        let mut driver: HashMap<usize, usize> = HashMap::new();
+        for (i, part) in parts.iter().enumerate() {
+            for net in part.outputs.iter().flatten() {
+                if *net < 2 || driver.insert(*net, i).is_some() {
+                    return err(0, format!("an output of {} is connected to a constant or another output", part.chip));
+                }
+            }
+        }
+        for (pin, _) in &def.inputs {
+            if pins[pin].iter().any(|net| driver.contains_key(net)) {
+                return err(0, format!("input pin '{}' is driven by a part", pin));
+            }
+        }
+
+        // order the output groups of every part so each combinational input is computed before it is read
+        let groups: Vec<(usize, Vec<usize>, Vec<usize>)> = parts.iter().enumerate()
+            .flat_map(|(i, part)| part.comb_groups().into_iter().map(move |(outs, ins)| (i, outs, ins)))
+            .collect();
+        let mut group_of: HashMap<usize, usize> = HashMap::new();
+        for (g, (i, outs, _)) in groups.iter().enumerate() {
+            for pin in outs {
+                for net in &parts[*i].outputs[*pin] { group_of.insert(*net, g); }
+            }
+        }
+        let mut deps: Vec<Vec<usize>> = vec![Vec::new(); groups.len()];
+        let mut waiting = vec![0; groups.len()];
+        for (g, (i, _, ins)) in groups.iter().enumerate() {
+            let mut srcs: Vec<usize> = ins.iter()
+                .flat_map(|pin| parts[*i].inputs[*pin].iter())
+                .filter_map(|net| group_of.get(net).copied())
+                .collect();
+            srcs.sort();
+            srcs.dedup();
+            waiting[g] = srcs.len();
+            for src in srcs { deps[src].push(g); }
+        }
+        let mut sorted: Vec<usize> = (0..groups.len()).filter(|g| waiting[*g] == 0).collect();
+        let mut next = 0;
+        while next < sorted.len() {
+            for dep in deps[sorted[next]].clone() {
+                waiting[dep] -= 1;
+                if waiting[dep] == 0 { sorted.push(dep); }
+            }
+            next += 1;
+        }
+        if sorted.len() < groups.len() {
+            let stuck = (0..groups.len()).find(|g| waiting[*g] > 0).unwrap();
+            return err(0, format!("combinational loop through {}", parts[groups[stuck].0].chip));
+        }
+        let order = sorted.into_iter().map(|g| (groups[g].0, groups[g].1.clone())).collect();
+
+        let mut nets = vec![false; self.parent.len()];
+        nets[1] = true;
+        let mut chip = Chip{ name: def.name.clone(), inputs: def.inputs.clone(), outputs: def.outputs.clone(), pins, parts, order, nets };
+        chip.eval();
+        Ok(chip)
+    }
+}
+
+impl Library {
+    // a library-less definition wrapping a built-in chip
+    fn build_def(&self, name: &str) -> Option<ChipDef> {
+        let (ins, outs, _) = builtin(name)?;
+        let pins = |pins: Pins| pins.iter().map(|(pin, width)| (pin.to_string(), *width)).collect();
+        Some(ChipDef{ name: name.to_string(), inputs: pins(ins), outputs: pins(outs), parts: Vec::new(), builtin: Some(name.to_string()) })
+    }
+}
+
+fn slice(nets: &[usize], range: Option<(usize, usize)>, name: &str, line: usize) -> Result<Vec<usize>, HdlError> {
+    match range {
+        None => Ok(nets.to_vec()),
+        Some((lo, hi)) if hi < nets.len() => Ok(nets[lo..=hi].to_vec()),
+        Some((lo, hi)) => err(line, format!("{}[{}..{}] is outside a {}-bit pin", name, lo, hi, nets.len())),
+    }
+}
+
+/// Chip - A simulated HDL chip
+/// Function: set drives input pins, eval propagates them to the outputs;
+///           tick samples the inputs of every clocked part and tock latches them.
+pub struct Chip {
+    name: String,
+    inputs: Vec<(String, usize)>,
+    outputs: Vec<(String, usize)>,
+    pins: HashMap<String, Vec<usize>>,
+    parts: Vec<Part>,
+    order: Vec<(usize, Vec<usize>)>,   // parts with the output pins each evaluation settles
+    nets: Vec<bool>,
+}
+
+impl Chip {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inputs(&self) -> &[(String, usize)] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[(String, usize)] {
+        &self.outputs
+    }
+
+    /// Drive an input pin, bit 0 first
+    pub fn set(&mut self, pin: &str, val: &[bool]) -> Result<(), HdlError> {
+        if !self.inputs.iter().any(|(name, _)| name == pin) {
+            return err(0, format!("{} has no input pin '{}'", self.name, pin));
+        }
+        let nets = &self.pins[pin];
+        if nets.len() != val.len() {
+            return err(0, format!("{} is {} bits wide, got {}", pin, nets.len(), val.len()));
+        }
+        for (net, bit) in nets.iter().zip(val) {
+            self.nets[*net] = *bit;
+        }
+        Ok(())
+    }
+
+    /// Current value of an input or output pin, bit 0 first
+    pub fn get(&self, pin: &str) -> Result<Vec<bool>, HdlError> {
+        let nets = self.pins.get(pin).ok_or_else(|| HdlError{ line: 0, msg: format!("{} has no pin '{}'", self.name, pin) })?;
+        Ok(nets.iter().map(|net| self.nets[*net]).collect())
+    }
+
+    fn read_inputs(&self, part: &Part) -> Vec<Vec<bool>> {
+        part.inputs.iter().map(|pin| pin.iter().map(|net| self.nets[*net]).collect()).collect()
+    }
+
+    /// Propagate the inputs and state through every part
+    pub fn eval(&mut self) {
+        for i in 0..self.order.len() {
+            let idx = self.order[i].0;
+            let ins = self.read_inputs(&self.parts[idx]);
+            let outs = self.parts[idx].eval(&ins);
+            for pin in &self.order[i].1 {
+                for (net, val) in self.parts[idx].outputs[*pin].iter().zip(&outs[*pin]) {
+                    self.nets[*net] = *val;
+                }
+            }
+        }
+    }
+
+    /// First half of a clock cycle: clocked parts sample their inputs
+    pub fn tick(&mut self) {
+        self.eval();
+        for i in 0..self.parts.len() {
+            if self.parts[i].is_clocked() {
+                self.parts[i].pending = Some(self.read_inputs(&self.parts[i]));
+            }
+        }
+    }
+
+    /// Second half of a clock cycle: clocked parts latch what they sampled
+    pub fn tock(&mut self) {
+        for part in self.parts.iter_mut() {
+            if let Some(ins) = part.pending.take() {
+                part.clock(&ins);
+            }
+        }
+        self.eval();
+    }
+
+    /// Whether the chip has clocked parts
+    pub fn is_clocked(&self) -> bool {
+        self.parts.iter().any(|part| part.is_clocked())
+    }
+
+    /// Flash a program into every ROM32K part
+    pub fn load_rom(&mut self, program: &[[bool; 16]]) {
+        for part in self.parts.iter_mut() {
+            if let Prim::Rom(rom) = &mut part.prim { rom.flash(program); }
+        }
+        self.eval();
+    }
+
+    /// Press a key on every Keyboard and Memory part, 0 releases it
+    pub fn set_key(&mut self, key: &[bool; 16]) {
+        for part in self.parts.iter_mut() {
+            match &mut part.prim {
+                Prim::Keyboard(kbd) => { kbd.clk_cycle(key, true); }
+                Prim::Memory(mem) => mem.set_key(key),
+                _ => {},
+            }
+        }
+        self.eval();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(val: u32, width: usize) -> Vec<bool> {
+        (0..width).map(|i| (val >> i) & 1 == 1).collect()
+    }
+
+    fn value(chip: &Chip, pin: &str) -> u32 {
+        utils::boollist_to_u32(&chip.get(pin).unwrap())
+    }
+
+    fn rand_words(seed: u32, count: usize) -> Vec<u32> {
+        utils::Rng::new(seed).take(count).map(|x| x >> 16).collect()
+    }
+
+    const XOR: &str = "
+        // Xor from the other elementary gates
+        CHIP MyXor {
+            IN a, b;
+            OUT out;
+            PARTS:
+            Not(in=a, out=nota);
+            Not(in=b, out=notb);
+            And(a=a, b=notb, out=w1);
+            And(a=nota, b=b, out=w2);
+            Or(a=w1, b=w2, out=out);
+        }";
+
+    const ADD16: &str = "
+        /* ripple carry, with a constant carry in */
+        CHIP MyAdd16 {
+            IN a[16], b[16];
+            OUT out[16];
+            PARTS:
+            FullAdder(a=a[0], b=b[0], c=false, sum=out[0], carry=c0);
+            FullAdder(a=a[1], b=b[1], c=c0, sum=out[1], carry=c1);
+            FullAdder(a=a[2], b=b[2], c=c1, sum=out[2], carry=c2);
+            FullAdder(a=a[3], b=b[3], c=c2, sum=out[3], carry=c3);
+            FullAdder(a=a[4], b=b[4], c=c3, sum=out[4], carry=c4);
+            FullAdder(a=a[5], b=b[5], c=c4, sum=out[5], carry=c5);
+            FullAdder(a=a[6], b=b[6], c=c5, sum=out[6], carry=c6);
+            FullAdder(a=a[7], b=b[7], c=c6, sum=out[7], carry=c7);
+            FullAdder(a=a[8], b=b[8], c=c7, sum=out[8], carry=c8);
+            FullAdder(a=a[9], b=b[9], c=c8, sum=out[9], carry=c9);
+            FullAdder(a=a[10], b=b[10], c=c9, sum=out[10], carry=c10);
+            FullAdder(a=a[11], b=b[11], c=c10, sum=out[11], carry=c11);
+            FullAdder(a=a[12], b=b[12], c=c11, sum=out[12], carry=c12);
+            FullAdder(a=a[13], b=b[13], c=c12, sum=out[13], carry=c13);
+            FullAdder(a=a[14], b=b[14], c=c13, sum=out[14], carry=c14);
+            FullAdder(a=a[15], b=b[15], c=c14, sum=out[15]);
+        }";
+
+    const ALU: &str = "
+        CHIP MyALU {
+            IN x[16], y[16], zx, nx, zy, ny, f, no;
+            OUT out[16], zr, ng;
+            PARTS:
+            Mux16(a=x, b=false, sel=zx, out=x1);
+            Not16(in=x1, out=notx1);
+            Mux16(a=x1, b=notx1, sel=nx, out=x2);
+            Mux16(a=y, b=false, sel=zy, out=y1);
+            Not16(in=y1, out=noty1);
+            Mux16(a=y1, b=noty1, sel=ny, out=y2);
+            And16(a=x2, b=y2, out=xandy);
+            MyAdd16(a=x2, b=y2, out=xplusy);
+            Mux16(a=xandy, b=xplusy, sel=f, out=res);
+            Not16(in=res, out=notres);
+            Mux16(a=res, b=notres, sel=no, out=out, out[0..7]=lo, out[8..15]=hi, out[15]=ng);
+            Or8Way(in=lo, out=orlo);
+            Or8Way(in=hi, out=orhi);
+            Or(a=orlo, b=orhi, out=nz);
+            Not(in=nz, out=zr);
+        }";
+
+    #[test]
+    fn test_parse_works() {
+        let def = parse(ALU).unwrap();
+        assert_eq!(def.name, "MyALU");
+        assert_eq!(def.inputs[0], ("x".to_string(), 16));
+        assert_eq!(def.outputs, vec![("out".to_string(), 16), ("zr".to_string(), 1), ("ng".to_string(), 1)]);
+        assert_eq!(def.parts.len(), 15);
+        assert_eq!(def.parts[0].line, 6);
+        assert_eq!(def.parts[0].conns[1], Conn{ pin: "b".to_string(), range: None, signal: Signal::Const(false) });
+        assert_eq!(def.parts[10].conns[6], Conn{
+            pin: "out".to_string(), range: Some((15, 15)), signal: Signal::Wire{ name: "ng".to_string(), range: None },
+        });
+
+        assert_eq!(parse("CHIP X { IN a; OUT b; PARTS: Not(in=a out=b); }").unwrap_err().line, 1);
+        assert!(parse("CHIP X { IN a[; }").is_err());
+        assert!(parse("CHIP X { IN a; /* never closed").is_err());
+        assert!(parse("CHIP X { } CHIP Y { }").is_err());
+    }
+
+    #[test]
+    fn test_combinational_chips_match_native() {
+        let mut lib = Library::new();
+        lib.add(XOR).unwrap();
+        lib.add(ADD16).unwrap();
+        lib.add(ALU).unwrap();
+
+        let mut xor = lib.build("MyXor").unwrap();
+        for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+            xor.set("a", &[a]).unwrap();
+            xor.set("b", &[b]).unwrap();
+            xor.eval();
+            assert_eq!(xor.get("out").unwrap(), vec![gates::xor(a, b)]);
+        }
+
+        let mut alu = lib.build("MyALU").unwrap();
+        let mut native = lib.build("ALU").unwrap();
+        let vals = rand_words(0x85ebca6b, 24);
+        for (i, pair) in vals.chunks(2).enumerate() {
+            for ctrl in [0b101010u32, 0b111111, 0b000010, 0b010011, 0b000111, 0b000000, 0b010101, 0b001101, (i as u32 * 37) & 0x3f] {
+                let ctrl_bits = bits(ctrl, 6);
+                for chip in [&mut alu, &mut native] {
+                    chip.set("x", &bits(pair[0], 16)).unwrap();
+                    chip.set("y", &bits(pair[1], 16)).unwrap();
+                    for (j, pin) in ["no", "f", "ny", "zy", "nx", "zx"].iter().enumerate() {
+                        chip.set(pin, &[ctrl_bits[j]]).unwrap();
+                    }
+                    chip.eval();
+                }
+                let x = utils::bytes_to_boollist(&utils::split_u16(pair[0] as u16));
+                let y = utils::bytes_to_boollist(&utils::split_u16(pair[1] as u16));
+                let c = |j: usize| ctrl_bits[j];
+                let res = adder::hack_alu(&x, &y, &adder::HackCtrl::new(c(5), c(4), c(3), c(2), c(1), c(0)));
+                assert_eq!(alu.get("out").unwrap(), res.out.to_vec(), "{:x} {:x} {:06b}", pair[0], pair[1], ctrl);
+                assert_eq!((alu.get("zr").unwrap()[0], alu.get("ng").unwrap()[0]), (res.zr, res.ng));
+                assert_eq!(native.get("out").unwrap(), alu.get("out").unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequential_chips_match_native() {
+        let mut lib = Library::new();
+        lib.add("
+            CHIP MyBit {
+                IN in, load;
+                OUT out;
+                PARTS:
+                Mux(a=dffout, b=in, sel=load, out=muxout);
+                DFF(in=muxout, out=dffout, out=out);
+            }").unwrap();
+        lib.add("
+            CHIP MyPC {
+                IN in[16], load, inc, reset;
+                OUT out[16];
+                PARTS:
+                Inc16(in=feedback, out=pc1);
+                Mux16(a=feedback, b=pc1, sel=inc, out=w0);
+                Mux16(a=w0, b=in, sel=load, out=w1);
+                Mux16(a=w1, b=false, sel=reset, out=w2);
+                Register(in=w2, load=true, out=out, out=feedback);
+            }").unwrap();
+        lib.add("
+            CHIP MyRAM64 {
+                IN in[16], load, address[6];
+                OUT out[16];
+                PARTS:
+                DMux8Way(in=load, sel=address[3..5], a=l0, b=l1, c=l2, d=l3, e=l4, f=l5, g=l6, h=l7);
+                RAM8(in=in, load=l0, address=address[0..2], out=r0);
+                RAM8(in=in, load=l1, address=address[0..2], out=r1);
+                RAM8(in=in, load=l2, address=address[0..2], out=r2);
+                RAM8(in=in, load=l3, address=address[0..2], out=r3);
+                RAM8(in=in, load=l4, address=address[0..2], out=r4);
+                RAM8(in=in, load=l5, address=address[0..2], out=r5);
+                RAM8(in=in, load=l6, address=address[0..2], out=r6);
+                RAM8(in=in, load=l7, address=address[0..2], out=r7);
+                Mux8Way16(a=r0, b=r1, c=r2, d=r3, e=r4, f=r5, g=r6, h=r7, sel=address[3..5], out=out);
+            }").unwrap();
+
+        let mut bit = lib.build("MyBit").unwrap();
+        let mut native_bit = BitRegister::new();
+        for (val, load) in [(true, false), (true, true), (false, false), (false, true), (true, false)] {
+            bit.set("in", &[val]).unwrap();
+            bit.set("load", &[load]).unwrap();
+            bit.tick();
+            bit.tock();
+            native_bit.clk_cycle(val, load);
+            assert_eq!(bit.get("out").unwrap(), vec![native_bit.read()]);
+        }
+
+        let mut pc = lib.build("MyPC").unwrap();
+        let mut native_pc = PC16::new();
+        let steps = rand_words(0xc2b2ae35, 40);
+        for step in steps {
+            let (inc, load, reset) = (step & 1 != 0, step & 6 == 6, step & 0x38 == 0x38);
+            let val = bits(step * 7, 16);
+            pc.set("in", &val).unwrap();
+            pc.set("inc", &[inc]).unwrap();
+            pc.set("load", &[load]).unwrap();
+            pc.set("reset", &[reset]).unwrap();
+            pc.tick();
+            pc.tock();
+            native_pc.clk_cycle(&bus16(&val), inc, load, reset);
+            assert_eq!(pc.get("out").unwrap(), native_pc.read().to_vec());
+        }
+
+        // outputs only change on tock
+        let mut ram = lib.build("MyRAM64").unwrap();
+        let mut native_ram = memory::RAM64::new();
+        for (i, step) in rand_words(0x85ebca6b, 60).into_iter().enumerate() {
+            let (addr, load) = (step % 8 * 7 % 64, step & 0x100 != 0);
+            ram.set("in", &bits(i as u32 * 1000, 16)).unwrap();
+            ram.set("address", &bits(addr, 6)).unwrap();
+            ram.set("load", &[load]).unwrap();
+            ram.tick();
+            let before = ram.get("out").unwrap();
+            ram.tock();
+            let expected = native_ram.clk_cycle(&bits(i as u32 * 1000, 16), &bits(addr, 6), load);
+            assert_eq!(before, expected.to_vec(), "step {}", i);
+            assert_eq!(ram.get("out").unwrap(), native_ram.clk_cycle(&[false; 16], &bits(addr, 6), false).to_vec());
+        }
+    }
+
+    #[test]
+    fn test_builtins_build_directly() {
+        let lib = Library::new();
+        let mut mux = lib.build("Mux8Way16").unwrap();
+        for (i, pin) in ["a", "b", "c", "d", "e", "f", "g", "h"].iter().enumerate() {
+            mux.set(pin, &bits(100 + i as u32, 16)).unwrap();
+        }
+        mux.set("sel", &bits(6, 3)).unwrap();
+        mux.eval();
+        assert_eq!(value(&mux, "out"), 106);
+
+        let mut dmux = lib.build("DMux4Way").unwrap();
+        dmux.set("in", &[true]).unwrap();
+        dmux.set("sel", &bits(2, 2)).unwrap();
+        dmux.eval();
+        assert_eq!(["a", "b", "c", "d"].map(|pin| value(&dmux, pin)), [0, 0, 1, 0]);
+
+        // @21, D=A, then M=D writes D to RAM[A]
+        let mut cpu = lib.build("CPU").unwrap();
+        for inst in [21, 0xec10] {
+            cpu.set("instruction", &bits(inst, 16)).unwrap();
+            cpu.tick();
+            cpu.tock();
+        }
+        cpu.set("instruction", &bits(0xe308, 16)).unwrap();
+        cpu.eval();
+        assert_eq!(["outM", "writeM", "addressM", "pc"].map(|pin| value(&cpu, pin)), [21, 1, 21, 2]);
+        assert!(lib.build("RAM16K").unwrap().is_clocked());
+        assert!(!lib.build("Nand").unwrap().is_clocked());
+    }
+
+    #[test]
+    fn test_memory_builtins_work() {
+        let lib = Library::new();
+        let mut mem = lib.build("Memory").unwrap();
+        // RAM, the first and last screen words, then the keyboard
+        for (addr, val) in [(7, 1234), (0x4000, 0xffff), (0x5fff, 42)] {
+            mem.set("in", &bits(val, 16)).unwrap();
+            mem.set("address", &bits(addr, 15)).unwrap();
+            mem.set("load", &[true]).unwrap();
+            mem.tick();
+            mem.tock();
+        }
+        mem.set("load", &[false]).unwrap();
+        mem.set_key(&utils::bytes_to_boollist(&[0, 75]));
+        for (addr, val) in [(7, 1234), (0x4000, 0xffff), (0x5fff, 42), (0x6000, 75), (8, 0)] {
+            mem.set("address", &bits(addr, 15)).unwrap();
+            mem.eval();
+            assert_eq!(value(&mem, "out"), val, "address {:x}", addr);
+        }
+
+        let mut screen = lib.build("Screen").unwrap();
+        screen.set("in", &bits(99, 16)).unwrap();
+        screen.set("address", &bits(8000, 13)).unwrap();
+        screen.set("load", &[true]).unwrap();
+        screen.tick();
+        screen.tock();
+        assert_eq!(value(&screen, "out"), 99);
+
+        let mut kbd = lib.build("Keyboard").unwrap();
+        assert_eq!(value(&kbd, "out"), 0);
+        kbd.set_key(&utils::bytes_to_boollist(&[0, 131]));
+        assert_eq!(value(&kbd, "out"), 131);
+        assert!(!kbd.is_clocked());
+
+        let mut rom = lib.build("ROM32K").unwrap();
+        rom.load_rom(&[utils::bytes_to_boollist(&[0, 5]), utils::bytes_to_boollist(&[0x12, 0x34])]);
+        rom.set("address", &bits(1, 15)).unwrap();
+        rom.eval();
+        assert_eq!(value(&rom, "out"), 0x1234);
+    }
+
+    #[test]
+    fn test_computer_chip_works() {
+        // Computer.hdl from chapter 5, with the CPU outputs brought out for checking
+        let mut lib = Library::new();
+        lib.add("CHIP Computer {
+            IN reset;
+            OUT pc[15], addressM[15], outM[16], writeM;
+            PARTS:
+            ROM32K(address=pc, out=instruction);
+            CPU(inM=inM, instruction=instruction, reset=reset, outM=outM, writeM=writeM, addressM=addressM, pc=pc);
+            Memory(in=outM, load=writeM, address=addressM, out=inM);
+        }").unwrap();
+        let mut chip = lib.build("Computer").unwrap();
+        // RAM[1] = RAM[0] + 1 through the data memory, then the keyboard into RAM[2]
+        let program = hack_asm::assemble("@5\nD=A\n@0\nM=D\n@0\nD=M\n@1\nM=D+1\n@24576\nD=M\n@2\nM=D\n(END)\n@END\n0;JMP").unwrap();
+        chip.load_rom(&program);
+        chip.set_key(&utils::bytes_to_boollist(&[0, 32]));
+        let mut native = hack::Computer::new();
+        native.load_program(&program);
+        native.set_key(&utils::bytes_to_boollist(&[0, 32]));
+
+        let mut writes = Vec::new();
+        for _ in 0..16 {
+            assert_eq!(value(&chip, "pc"), utils::boollist_to_u32(&native.cpu().pc()));
+            if value(&chip, "writeM") == 1 {
+                writes.push((value(&chip, "addressM"), value(&chip, "outM")));
+            }
+            chip.tick();
+            chip.tock();
+            native.clk_cycle(false);
+        }
+        assert_eq!(writes, [(0, 5), (1, 6), (2, 32)]);
+        for (addr, val) in [(0, 5), (1, 6), (2, 32)] {
+            assert_eq!(utils::boollist_to_u32(&native.read_mem(&utils::gen_memaddr(addr))), val);
+        }
+    }
+
+    #[test]
+    fn test_build_errors_work() {
+        let build = |src: &str| {
+            let mut lib = Library::new();
+            let name = lib.add(src).unwrap();
+            lib.build(&name).err().unwrap().to_string()
+        };
+        assert_eq!(build("CHIP X { IN a; OUT out; PARTS:\n Foo(in=a, out=out); }"), "line 2: unknown chip 'Foo'");
+        assert_eq!(build("CHIP X { IN a; OUT out; PARTS:\n Not(x=a, out=out); }"), "line 2: Not has no pin 'x'");
+        assert_eq!(build("CHIP X { IN a[16]; OUT out; PARTS:\n Not(in=a, out=out); }"),
+                   "line 2: Not.in is 1 bits wide but is connected to 16 bits");
+        assert_eq!(build("CHIP X { IN a[4]; OUT out; PARTS:\n Not(in=a[4], out=out); }"),
+                   "line 2: a[4..4] is outside a 4-bit pin");
+        assert_eq!(build("CHIP X { IN a; OUT out; PARTS:\n Not(in=w, out=v);\n Not(in=v, out=w); }"),
+                   "combinational loop through Not");
+        assert_eq!(build("CHIP X { IN a; OUT out; PARTS:\n Not(in=a, out=out);\n Not(in=a, out=out); }"),
+                   "an output of Not is connected to a constant or another output");
+        assert_eq!(build("CHIP X { IN a; OUT out; PARTS:\n X(a=a, out=out); }"), "line 2: chip 'X' contains itself");
+    }
+}
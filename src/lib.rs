@@ -0,0 +1,50 @@
+//! nandputer - RISC-V and Hack computers built from NAND gates and DFFs
+//!
+//! The modules follow the chip hierarchy, each level only using the one below it:
+//! - `gates`: nand and the combinational gates derived from it
+//! - `bits`: `Bits<N>`, a bus of any width built on those gates
+//! - `adder`: adders, incrementers, the barrel shifter and the Hack and RV32I ALUs
+//! - `memory`: DFF, registers, program counters, RAM chips and the `WordMemory` trait
+//! - `bus`: memory-mapped I/O, the `Device` trait and a few peripherals
+//! - `decoder`, `cpu`: the single-cycle RV32I processor
+//! - `hack`: the Hack CPU and computer from nand2tetris
+//! - `hdl`, `tst`: a simulator for nand2tetris .hdl chips built on the native chips above,
+//!   and a runner for the course's .tst/.cmp test scripts
+//!
+//! Tooling around the machines lives in `rv_asm`, `hack_asm`, `disasm`, `elf` and `memfile`,
+//! `vcd` dumps the signals of any clocked chip as a waveform (built with the `vcd` feature)
+//! and `count` tallies the nand and DFF evaluations behind any operation (with the `count` feature).
+//! Buses are `[bool; N]` or `Bits<N>` with bit 0 the LSB; `Bits` and the conversion functions
+//! re-exported at the crate root (`boollist_to_u32`, `bytes_to_boollist32`, ...) turn them into integers.
+
+// bit-indexed loops, chip names like DFF/RAM8 and truth-table asserts mirror the nand2tetris chip specs
+#![allow(clippy::needless_range_loop, clippy::upper_case_acronyms, clippy::bool_assert_comparison)]
+
+#[macro_use]
+pub mod vcd;
+pub mod count;
+pub mod gates;
+pub mod bits;
+pub mod adder;
+pub mod memory;
+pub mod bus;
+pub mod memfile;
+pub mod cpu;
+pub mod decoder;
+pub mod disasm;
+pub mod elf;
+pub mod hack;
+pub mod hdl;
+pub mod tst;
+pub mod hack_asm;
+pub mod rv_asm;
+mod utils;
+
+pub use bits::Bits;
+pub use bus::Bus;
+pub use cpu::Cpu;
+pub use hack::{Computer, HackCpu};
+pub use memory::{WordMemory, RamBackend, GateLevel, Behavioral};
+pub use hdl::{Chip, Library};
+// bus conversions, big endian bytes and integers to and from [bool; N] with bit 0 the LSB
+pub use utils::{bytes_to_bus, bytes_to_boollist, bytes_to_boollist32, boollist_to_u32, split_u16, split_u32, gen_memaddr};
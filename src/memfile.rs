@@ -0,0 +1,316 @@
+use std::fmt;
+use crate::*;
+use memory::WordMemory;
+
+// Image formats for 16-bit word memories. Words are little endian in byte
+// formats (Intel HEX, raw binary), so byte address 2n is the low byte of word n.
+
+/// MemFileError - Why an image can't be loaded into or dumped from a memory
+#[derive(Debug, PartialEq)]
+pub enum MemFileError {
+    Parse { line: usize, msg: String },
+    OutOfRange { addr: usize, size: usize },
+}
+
+impl fmt::Display for MemFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemFileError::Parse{ line, msg } => write!(f, "line {}: {}", line, msg),
+            MemFileError::OutOfRange{ addr, size } =>
+                write!(f, "word address 0x{:x} is outside a memory of {} words", addr, size),
+        }
+    }
+}
+
+fn to_bits(word: u16) -> [bool; 16] {
+    utils::bytes_to_boollist(&utils::split_u16(word))
+}
+
+fn to_u16(bits: &[bool; 16]) -> u16 {
+    utils::boollist_to_u32(bits) as u16
+}
+
+// write (word address, value) pairs at offset, checking every address before touching memory
+fn store<M: WordMemory>(mem: &mut M, words: &[(usize, u16)], offset: usize) -> Result<usize, MemFileError> {
+    let size = mem.size();
+    // a huge @ address must not wrap around to a small one
+    if let Some((addr, _)) = words.iter().find(|(addr, _)| addr.checked_add(offset).is_none_or(|end| end >= size)) {
+        return Err(MemFileError::OutOfRange{ addr: addr.saturating_add(offset), size });
+    }
+    for (addr, val) in words {
+        mem.write_word(addr + offset, &to_bits(*val));
+    }
+    Ok(words.len())
+}
+
+fn check_range<M: WordMemory>(mem: &M, start: usize, count: usize) -> Result<(), MemFileError> {
+    if start.checked_add(count).is_none_or(|end| end > mem.size()) {
+        return Err(MemFileError::OutOfRange{ addr: start.saturating_add(count.saturating_sub(1)), size: mem.size() });
+    }
+    Ok(())
+}
+
+// pair up bytes at byte addresses into words, a lone byte keeps the other half zero
+fn bytes_to_words(bytes: &[(usize, u8)]) -> Vec<(usize, u16)> {
+    let mut words: Vec<(usize, u16)> = Vec::new();
+    for (addr, byte) in bytes {
+        let val = (*byte as u16) << (8 * (addr % 2));
+        match words.last_mut() {
+            Some((word_addr, word)) if *word_addr == addr / 2 => *word |= val,
+            _ => words.push((addr / 2, val)),
+        }
+    }
+    words
+}
+
+fn parse_hex_byte(text: &str, pos: usize, line: usize) -> Result<u8, MemFileError> {
+    text.get(pos..pos + 2)
+        .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        .ok_or_else(|| MemFileError::Parse{ line, msg: format!("bad hex at column {}", pos + 1) })
+}
+
+/// Load Intel HEX records (data, EOF, extended segment and linear address)
+/// Returns the number of words written.
+pub fn load_ihex<M: WordMemory>(mem: &mut M, text: &str, offset: usize) -> Result<usize, MemFileError> {
+    let mut bytes = Vec::new();
+    let mut base = 0;
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let err = |msg: &str| MemFileError::Parse{ line: line_no, msg: msg.to_string() };
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        let record = line.strip_prefix(':').ok_or_else(|| err("record does not start with ':'"))?;
+        if record.len() % 2 != 0 || record.len() < 10 {
+            return Err(err("record has a bad length"));
+        }
+
+        let mut raw = Vec::new();
+        for pos in (0..record.len()).step_by(2) {
+            raw.push(parse_hex_byte(record, pos, line_no)?);
+        }
+        if raw.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(err("bad checksum"));
+        }
+        let len = raw[0] as usize;
+        if raw.len() != len + 5 {
+            return Err(err("byte count does not match the record"));
+        }
+        let addr = (raw[1] as usize) << 8 | raw[2] as usize;
+        let data = &raw[4..4 + len];
+        match raw[3] {
+            0x00 => bytes.extend(data.iter().enumerate().map(|(j, b)| (base + addr + j, *b))),
+            0x01 => break,
+            0x02 if len == 2 => base = ((data[0] as usize) << 8 | data[1] as usize) << 4,
+            0x04 if len == 2 => base = ((data[0] as usize) << 8 | data[1] as usize) << 16,
+            0x03 | 0x05 => {},  // start address records don't place data
+            kind => return Err(err(&format!("unsupported record type {:02x}", kind))),
+        }
+    }
+    store(mem, &bytes_to_words(&bytes), offset)
+}
+
+/// Dump count words from start as Intel HEX, 16 data bytes per record
+/// Addresses past 64KB get an extended linear address record whenever the upper 16 bits change.
+pub fn dump_ihex<M: WordMemory>(mem: &mut M, start: usize, count: usize) -> Result<String, MemFileError> {
+    let bytes = dump_bin(mem, start, count)?;
+    let mut res = String::new();
+    let mut push_record = |kind: u8, addr: usize, data: &[u8]| {
+        let mut record = vec![data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+        record.extend_from_slice(data);
+        let checksum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+        record.push(checksum);
+        res.push(':');
+        for b in record { res.push_str(&format!("{:02X}", b)); }
+        res.push('\n');
+    };
+
+    let mut upper = 0;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let addr = 2 * start + pos;
+        if addr >> 16 != upper {
+            upper = addr >> 16;
+            push_record(0x04, 0, &[(upper >> 8) as u8, upper as u8]);
+        }
+        // a record never crosses a 64KB boundary
+        let len = 16.min(bytes.len() - pos).min(0x10000 - (addr & 0xffff));
+        push_record(0x00, addr & 0xffff, &bytes[pos..pos + len]);
+        pos += len;
+    }
+    push_record(0x01, 0, &[]);
+    Ok(res)
+}
+
+/// Load a flat little endian binary, an odd trailing byte fills the low half of the last word
+pub fn load_bin<M: WordMemory>(mem: &mut M, bytes: &[u8], offset: usize) -> Result<usize, MemFileError> {
+    let bytes: Vec<(usize, u8)> = bytes.iter().copied().enumerate().collect();
+    store(mem, &bytes_to_words(&bytes), offset)
+}
+
+/// Dump count words from start as a flat little endian binary
+pub fn dump_bin<M: WordMemory>(mem: &mut M, start: usize, count: usize) -> Result<Vec<u8>, MemFileError> {
+    check_range(mem, start, count)?;
+    let mut res = Vec::new();
+    for addr in start..start + count {
+        res.extend_from_slice(&to_u16(&mem.read_word(addr)).to_le_bytes());
+    }
+    Ok(res)
+}
+
+/// Load Verilog $readmemh text: one hex word per token, @ADDR jumps to a word address
+pub fn load_readmemh<M: WordMemory>(mem: &mut M, text: &str, offset: usize) -> Result<usize, MemFileError> {
+    let mut words = Vec::new();
+    let mut addr = 0;
+    let mut in_block = false;
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let mut code = line;
+        // /* */ comments may span lines, // comments end at the line
+        let mut stripped = String::new();
+        loop {
+            if in_block {
+                match code.find("*/") {
+                    Some(end) => { code = &code[end + 2..]; in_block = false; }
+                    None => break,
+                }
+            } else {
+                match (code.find("/*"), code.find("//")) {
+                    (Some(open), line_comment) if line_comment.is_none_or(|lc| open < lc) => {
+                        stripped.push_str(&code[..open]);
+                        stripped.push(' ');
+                        code = &code[open + 2..];
+                        in_block = true;
+                    }
+                    (_, Some(lc)) => { stripped.push_str(&code[..lc]); break; }
+                    (_, None) => { stripped.push_str(code); break; }
+                }
+            }
+        }
+
+        for token in stripped.split_whitespace() {
+            let err = || MemFileError::Parse{ line: line_no, msg: format!("bad token '{}'", token) };
+            match token.strip_prefix('@') {
+                Some(target) => addr = usize::from_str_radix(target, 16).map_err(|_| err())?,
+                None => {
+                    let val = u16::from_str_radix(&token.replace('_', ""), 16).map_err(|_| err())?;
+                    words.push((addr, val));
+                    addr = addr.saturating_add(1);
+                }
+            }
+        }
+    }
+    store(mem, &words, offset)
+}
+
+/// Dump count words from start as $readmemh text with an @start header
+pub fn dump_readmemh<M: WordMemory>(mem: &mut M, start: usize, count: usize) -> Result<String, MemFileError> {
+    check_range(mem, start, count)?;
+    let mut res = format!("@{:04x}\n", start);
+    for addr in start..start + count {
+        res.push_str(&format!("{:04x}\n", to_u16(&mem.read_word(addr))));
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::{Behavioral, ByteRAM, RAM8, RAM64, ROM32K};
+
+    #[test]
+    fn test_ihex_roundtrip_works() {
+        let mut ram = RAM64::new();
+        // words 0x1234, 0xbeef at byte 0, then byte 0x42 at byte address 0x21 (high half of word 16)
+        let text = ":040000003412EFBE09\n:01002100429C\n:00000001FF\n";
+        assert_eq!(load_ihex(&mut ram, text, 2), Ok(3));
+        assert_eq!(to_u16(&ram.read_word(2)), 0x1234);
+        assert_eq!(to_u16(&ram.read_word(3)), 0xbeef);
+        assert_eq!(to_u16(&ram.read_word(18)), 0x4200);
+
+        let dump = dump_ihex(&mut ram, 2, 2).unwrap();
+        assert_eq!(dump, ":040004003412EFBE05\n:00000001FF\n");
+        let mut copy = RAM64::new();
+        load_ihex(&mut copy, &dump, 0).unwrap();
+        assert_eq!(to_u16(&copy.read_word(3)), 0xbeef);
+    }
+
+    #[test]
+    fn test_ihex_past_64k_works() {
+        let mut ram = ByteRAM::<Behavioral>::with_backend();
+        ram.set_size(0x40000);
+        for i in 0..8 {
+            ram.write_word(0x7ffc + i, &to_bits(0x1100 * i as u16 + 1));
+        }
+        ram.write_word(0x10000, &to_bits(0xbeef));
+
+        // words 0x7ffc..0x8004 straddle byte address 0x10000
+        let dump = dump_ihex(&mut ram, 0x7ffc, 8).unwrap();
+        assert_eq!(dump, ":08FFF800010001110122013397\n:020000040001F9\n:0800000001440155016601777E\n:00000001FF\n");
+        assert_eq!(dump_ihex(&mut ram, 0x10000, 1).unwrap(), ":020000040002F8\n:02000000EFBE51\n:00000001FF\n");
+
+        let mut copy = ByteRAM::<Behavioral>::with_backend();
+        copy.set_size(0x40000);
+        assert_eq!(load_ihex(&mut copy, &dump, 0), Ok(8));
+        assert_eq!(load_ihex(&mut copy, &dump_ihex(&mut ram, 0x10000, 1).unwrap(), 0), Ok(1));
+        for addr in [0, 0x7ffb, 0x8004] {
+            assert_eq!(to_u16(&copy.read_word(addr)), 0);
+        }
+        for addr in (0x7ffc..0x8004).chain([0x10000]) {
+            assert_eq!(copy.read_word(addr), ram.read_word(addr), "word 0x{:x}", addr);
+        }
+    }
+
+    #[test]
+    fn test_ihex_errors_work() {
+        let mut ram = RAM8::new();
+        assert_eq!(load_ihex(&mut ram, ":040000003412EFBE0A", 0),
+                   Err(MemFileError::Parse{ line: 1, msg: "bad checksum".to_string() }));
+        assert!(load_ihex(&mut ram, "040000003412EFBE09", 0).is_err());
+        assert!(load_ihex(&mut ram, ":04000000ZZ12EFBE09", 0).is_err());
+        // extended linear address 0x0001 pushes the data far past RAM8
+        assert_eq!(load_ihex(&mut ram, ":020000040001F9\n:040000003412EFBE09", 0),
+                   Err(MemFileError::OutOfRange{ addr: 0x8000, size: 8 }));
+    }
+
+    #[test]
+    fn test_bin_works() {
+        let mut ram = RAM8::new();
+        assert_eq!(load_bin(&mut ram, &[0x34, 0x12, 0xef, 0xbe, 0x7f], 5), Ok(3));
+        assert_eq!(to_u16(&ram.read_word(5)), 0x1234);
+        assert_eq!(to_u16(&ram.read_word(7)), 0x007f);
+        assert_eq!(dump_bin(&mut ram, 5, 3), Ok(vec![0x34, 0x12, 0xef, 0xbe, 0x7f, 0x00]));
+
+        // nothing is written when the image doesn't fit
+        assert_eq!(load_bin(&mut ram, &[1, 0, 2, 0], 7), Err(MemFileError::OutOfRange{ addr: 8, size: 8 }));
+        assert_eq!(to_u16(&ram.read_word(7)), 0x007f);
+        assert!(dump_bin(&mut ram, 4, 5).is_err());
+    }
+
+    #[test]
+    fn test_readmemh_works() {
+        let mut ram = RAM64::new();
+        let text = "// header\n1234 beef /* skip\n ffff */ 00_01\n@20 dead\n";
+        assert_eq!(load_readmemh(&mut ram, text, 0), Ok(4));
+        assert_eq!(to_u16(&ram.read_word(1)), 0xbeef);
+        assert_eq!(to_u16(&ram.read_word(2)), 0x0001);
+        assert_eq!(to_u16(&ram.read_word(0x20)), 0xdead);
+        assert_eq!(dump_readmemh(&mut ram, 0, 3), Ok("@0000\n1234\nbeef\n0001\n".to_string()));
+
+        assert_eq!(load_readmemh(&mut ram, "12\nxyz", 0).err().unwrap(),
+                   MemFileError::Parse{ line: 2, msg: "bad token 'xyz'".to_string() });
+        assert!(load_readmemh(&mut ram, "@40 1", 0).is_err());
+        assert_eq!(load_readmemh(&mut ram, "@ffffffffffffffff 1 2", 1),
+                   Err(MemFileError::OutOfRange{ addr: usize::MAX, size: 64 }));
+        assert_eq!(load_readmemh(&mut ram, "@ffffffffffffffff 1 2", 0),
+                   Err(MemFileError::OutOfRange{ addr: usize::MAX, size: 64 }));
+        assert_eq!(dump_readmemh(&mut ram, usize::MAX, 2), Err(MemFileError::OutOfRange{ addr: usize::MAX, size: 64 }));
+    }
+
+    #[test]
+    fn test_rom_load_works() {
+        let mut rom = ROM32K::new();
+        assert_eq!(load_readmemh(&mut rom, "@7fff ec10", 0), Ok(1));
+        assert_eq!(to_u16(&rom.read_word(0x7fff)), 0xec10);
+        assert!(load_readmemh(&mut rom, "ec10", 0x8000).is_err());
+    }
+}
@@ -1,25 +1,27 @@
-use std::{iter,fmt};
+use std::fmt;
 use crate::*;
 
 // like nand2tetris we will use the D Flip-Flop as the atomic unit of sequential logic
 // in physics DFFs are implemented via feedback between NAND gates
 // simulating that would be much more difficult at this stage, instead implement DFF in Rust
 #[derive(Copy, Clone, Debug)]
-struct DFF {
+pub struct DFF {
     bit: bool
 }
 
 // TODO is there a kind of iterator that can be passed values?
 impl DFF {
-    fn new() -> DFF {
+    pub fn new() -> DFF {
         DFF { bit: false }
     }
 
-    fn read(&self) -> bool {
+    pub fn read(&self) -> bool {
         self.bit
     }
 
-    fn clk_cycle(&mut self, bit: bool) -> bool {
+    pub fn clk_cycle(&mut self, bit: bool) -> bool {
+        #[cfg(feature = "count")]
+        count::tally_dff();
         let res = self.bit;
         self.bit = bit;
         res
@@ -27,22 +29,24 @@ impl DFF {
 }
 
 #[derive(Copy, Clone, Debug)]
-struct BitRegister {
+pub struct BitRegister {
     dff: DFF
 }
 
 impl BitRegister {
 
-    fn new() -> BitRegister {
+    pub fn new() -> BitRegister {
         BitRegister{ dff: DFF::new() }
     }
 
-    fn read(&self) -> bool {
+    pub fn read(&self) -> bool {
         self.dff.read()
     }
 
-    fn clk_cycle(&mut self, val: bool, load: bool) -> bool {
-        self.dff.clk_cycle(gates::mux(self.dff.read(), val, load))
+    pub fn clk_cycle(&mut self, val: bool, load: bool) -> bool {
+        let res = self.dff.clk_cycle(gates::mux(self.dff.read(), val, load));
+        trace_ports!(("in", &[val]), ("load", &[load]), ("out", &[res]));
+        res
     }
 }
 
@@ -75,15 +79,20 @@ pub struct Register {
 }
 
 impl Register {
-    fn new() -> Register {
+    pub fn new() -> Register {
         Register { bits: [BitRegister::new(); 16] }
     }
 
-    fn clk_cycle(&mut self, val: &[bool], load: bool) -> [bool; 16] {
+    pub fn read(&self) -> [bool; 16] {
+        self.bits.map(|x| x.read())
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool], load: bool) -> [bool; 16] {
         let mut res = [false; 16];
         for i in 0..16 {
-            res[i] = self.bits[i].clk_cycle(val[i], load);
+            res[i] = trace_child!("BitRegister", i, self.bits[i].clk_cycle(val[i], load));
         }
+        trace_ports!(("in", &val[0..16]), ("load", &[load]), ("out", &res));
         res
     }
 }
@@ -102,23 +111,147 @@ pub struct Register32 {
 }
 
 impl Register32 {
-    fn new() -> Register32 {
+    pub fn new() -> Register32 {
         Register32 { bits: [BitRegister::new(); 32] }
     }
 
-    fn clk_cycle(&mut self, val: &[bool], load: bool) -> [bool; 32] {
+    pub fn read(&self) -> [bool; 32] {
+        self.bits.map(|x| x.read())
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool], load: bool) -> [bool; 32] {
         let mut res = [false; 32];
         for i in 0..32 {
-            res[i] = self.bits[i].clk_cycle(val[i], load);
+            res[i] = trace_child!("BitRegister", i, self.bits[i].clk_cycle(val[i], load));
         }
+        trace_ports!(("in", &val[0..32]), ("load", &[load]), ("out", &res));
         res
     }
 }
 
 impl fmt::Display for Register32 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let reading = self.bits.map(|x| x.read());
-        for byte in utils::boollist_to_bytes(&reading) { write!(f, "{:02x}", byte)?; }
+        for byte in utils::boollist_to_bytes32(&self.read()) { write!(f, "{:02x}", byte)?; }
+        Ok(())
+    }
+}
+
+// Chip name: PC // 16-bit counter
+// Inputs: in[16], inc, load, reset
+// Outputs: out[16]
+// Function: If reset(t-1) then out(t)=0
+// else if load(t-1) then out(t)=in(t-1)
+// else if inc(t-1) then out(t)=out(t-1)+1
+// else out(t)=out(t-1)
+#[derive(Copy, Clone, Debug)]
+pub struct PC16 {
+    reg: Register,
+}
+
+impl PC16 {
+    pub fn new() -> PC16 {
+        PC16{ reg: Register::new() }
+    }
+
+    pub fn read(&self) -> [bool; 16] {
+        self.reg.read()
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool; 16], inc: bool, load: bool, reset: bool) -> [bool; 16] {
+        let out = self.reg.read();
+        let mut next = gates::mux16(&out, &adder::inc16(&out), inc);
+        next = gates::mux16(&next, val, load);
+        next = gates::mux16(&next, &[false; 16], reset);
+        let res = trace_child!("Register", self.reg.clk_cycle(&next, true));
+        trace_ports!(("in", val), ("inc", &[inc]), ("load", &[load]), ("reset", &[reset]), ("out", &res));
+        res
+    }
+}
+
+// Chip name: PC32 // 32-bit counter with a RISC-V step
+// Inputs: in[32], inc, step4, load, reset
+// Outputs: out[32]
+// Function: as PC, but inc adds 4 instead of 1 when step4 is set
+#[derive(Copy, Clone, Debug)]
+pub struct PC32 {
+    reg: Register32,
+}
+
+impl PC32 {
+    pub fn new() -> PC32 {
+        PC32{ reg: Register32::new() }
+    }
+
+    pub fn read(&self) -> [bool; 32] {
+        self.reg.read()
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool; 32], inc: bool, step4: bool, load: bool, reset: bool) -> [bool; 32] {
+        let out = self.reg.read();
+        let four = utils::bytes_to_boollist32(&[0, 0, 0, 4]);
+        let step = gates::mux32(&adder::inc32(&out), &adder::add32(&out, &four), step4);
+        let mut next = gates::mux32(&out, &step, inc);
+        next = gates::mux32(&next, val, load);
+        next = gates::mux32(&next, &[false; 32], reset);
+        let res = trace_child!("Register32", self.reg.clk_cycle(&next, true));
+        trace_ports!(("in", val), ("inc", &[inc]), ("step4", &[step4]), ("load", &[load]), ("reset", &[reset]), ("out", &res));
+        res
+    }
+}
+
+// Chip name: RegisterFile32
+// Inputs: rs1[5], rs2[5], rd[5], in[32], load
+// Outputs: out1[32], out2[32]
+// Function: out1(t)=x[rs1(t)](t), out2(t)=x[rs2(t)](t)
+// If load(t-1) and rd(t-1)!=0 then
+// x[rd(t-1)](t)=in(t-1)
+// Comment: x0 is hardwired to zero, writes to it are dropped.
+#[derive(Copy, Clone, Debug)]
+pub struct RegisterFile32 {
+    regs: [Register32; 32],
+}
+
+impl RegisterFile32 {
+    pub fn new() -> RegisterFile32 {
+        RegisterFile32{ regs: [Register32::new(); 32] }
+    }
+
+    pub fn read(&self, idx: usize) -> [bool; 32] {
+        self.regs[idx].read()
+    }
+
+    pub fn clk_cycle(&mut self, rs1: &[bool], rs2: &[bool], rd: &[bool], val: &[bool], load: bool) -> ([bool; 32], [bool; 32]) {
+        let group_bits = gates::dmux4way(load, rd[4], rd[3]);
+        let mut load_bits = [false; 32];
+        for i in 0..4 {
+            let bits = gates::dmux8way(group_bits[i], rd[2], rd[1], rd[0]);
+            load_bits[8*i..8*i+8].copy_from_slice(&bits);
+        }
+        load_bits[0] = false; // x0 never latches
+
+        let mut res = [[false; 32]; 32];
+        for i in 0..32 {
+            res[i] = trace_child!("Register32", i, self.regs[i].clk_cycle(val, load_bits[i]));
+        }
+        let (out1, out2) = (RegisterFile32::select(&res, rs1), RegisterFile32::select(&res, rs2));
+        trace_ports!(("rs1", &rs1[0..5]), ("rs2", &rs2[0..5]), ("rd", &rd[0..5]), ("in", &val[0..32]), ("load", &[load]),
+                     ("out1", &out1), ("out2", &out2));
+        (out1, out2)
+    }
+
+    fn select(res: &[[bool; 32]; 32], idx: &[bool]) -> [bool; 32] {
+        let mut groups = [[false; 32]; 4];
+        for i in 0..4 {
+            let words = [res[8*i], res[8*i+1], res[8*i+2], res[8*i+3], res[8*i+4], res[8*i+5], res[8*i+6], res[8*i+7]];
+            groups[i] = gates::mux8way32(&words, (idx[2], idx[1], idx[0]));
+        }
+        gates::mux4way32(&groups, (idx[4], idx[3]))
+    }
+}
+
+impl fmt::Display for RegisterFile32 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for i in 0..32 { writeln!(f, "x{}: {}", i, self.regs[i])?; }
         Ok(())
     }
 }
@@ -138,9 +271,11 @@ impl RAM8 {
         let load_bits = gates::dmux8way(load, addr[2], addr[1], addr[0]);
         let mut res = [[false; 16]; 8];
         for i in 0..8 {
-            res[i] = self.words[i].clk_cycle(val, load_bits[i]);
+            res[i] = trace_child!("Register", i, self.words[i].clk_cycle(val, load_bits[i]));
         }
-        gates::mux8way16(&res, (addr[2], addr[1], addr[0]))
+        let out = gates::mux8way16(&res, (addr[2], addr[1], addr[0]));
+        trace_ports!(("in", &val[0..16]), ("address", &addr[0..3]), ("load", &[load]), ("out", &out));
+        out
     }
 }
 
@@ -157,7 +292,7 @@ impl Iterator for RAM8 {
 
 impl fmt::Display for RAM8 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for i in 0..8 { write!(f, "{}: {}\n", i, self.words[i])?; }
+        for i in 0..8 { writeln!(f, "{}: {}", i, self.words[i])?; }
         Ok(())
     }
 }
@@ -177,9 +312,11 @@ impl RAM64 {
         let load_bits = gates::dmux8way(load, addr[5], addr[4], addr[3]);
         let mut res = [[false; 16]; 8];
         for i in 0..8 {
-            res[i] = self.blocks[i].clk_cycle(val, &addr, load_bits[i]);
+            res[i] = trace_child!("RAM8", i, self.blocks[i].clk_cycle(val, addr, load_bits[i]));
         }
-        gates::mux8way16(&res, (addr[5], addr[4], addr[3]))
+        let out = gates::mux8way16(&res, (addr[5], addr[4], addr[3]));
+        trace_ports!(("in", &val[0..16]), ("address", &addr[0..6]), ("load", &[load]), ("out", &out));
+        out
     }
 }
 
@@ -188,81 +325,537 @@ impl Iterator for RAM64 {
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.index == 8 { return None };
-        let res = match self.blocks[self.index].next() {
+        
+        match self.blocks[self.index].next() {
             Some(val) => Some(val),
             None => {
                 self.index += 1;
                 self.next()
             }
-        };
-        res
+        }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+// from RAM512 up the blocks live in a Vec, so a big tree never sits on the stack
+#[derive(Clone, Debug)]
 pub struct RAM512 {
-    blocks: [RAM64; 8],
+    blocks: Vec<RAM64>,
 }
 
 impl RAM512 {
     pub fn new() -> RAM512 {
-        RAM512{ blocks: [RAM64::new();8] }
+        RAM512{ blocks: vec![RAM64::new(); 8] }
     }
 
     pub fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
         let load_bits = gates::dmux8way(load, addr[8], addr[7], addr[6]);
         let mut res = [[false; 16]; 8];
         for i in 0..8 {
-            res[i] = self.blocks[i].clk_cycle(val, &addr, load_bits[i]);
+            res[i] = trace_child!("RAM64", i, self.blocks[i].clk_cycle(val, addr, load_bits[i]));
         }
-        gates::mux8way16(&res, (addr[8], addr[7], addr[6]))
+        let out = gates::mux8way16(&res, (addr[8], addr[7], addr[6]));
+        trace_ports!(("in", &val[0..16]), ("address", &addr[0..9]), ("load", &[load]), ("out", &out));
+        out
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct RAM4096 {
-    blocks: [RAM512; 8],
+    blocks: Vec<RAM512>,
 }
 
 impl RAM4096 {
     pub fn new() -> RAM4096 {
-        RAM4096{ blocks: [RAM512::new();8] }
+        RAM4096{ blocks: vec![RAM512::new(); 8] }
     }
 
     pub fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
         let load_bits = gates::dmux8way(load, addr[11], addr[10], addr[9]);
         let mut res = [[false; 16]; 8];
         for i in 0..8 {
-            res[i] = self.blocks[i].clk_cycle(val, &addr, load_bits[i]);
+            res[i] = trace_child!("RAM512", i, self.blocks[i].clk_cycle(val, addr, load_bits[i]));
         }
-        gates::mux8way16(&res, (addr[11], addr[10], addr[9]))
+        let out = gates::mux8way16(&res, (addr[11], addr[10], addr[9]));
+        trace_ports!(("in", &val[0..16]), ("address", &addr[0..12]), ("load", &[load]), ("out", &out));
+        out
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub struct RAM16384 {
-    blocks: [RAM4096; 4],
+    blocks: Vec<RAM4096>,
 }
 
 impl RAM16384 {
     pub fn new() -> RAM16384 {
-        RAM16384{ blocks: [RAM4096::new();4] }
+        RAM16384{ blocks: vec![RAM4096::new(); 4] }
     }
 
     pub fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
         let load_bits = gates::dmux4way(load, addr[13], addr[12]);
         let mut res = [[false; 16]; 4];
         for i in 0..4 {
-            res[i] = self.blocks[i].clk_cycle(val, &addr, load_bits[i]);
+            res[i] = trace_child!("RAM4096", i, self.blocks[i].clk_cycle(val, addr, load_bits[i]));
         }
-        gates::mux4way16(&res, (addr[13], addr[12]))
+        let out = gates::mux4way16(&res, (addr[13], addr[12]));
+        trace_ports!(("in", &val[0..16]), ("address", &addr[0..14]), ("load", &[load]), ("out", &out));
+        out
+    }
+}
+
+/// RamChip - The RAMn contract, implemented by the gate-level tree and by FastRAM
+/// Function: clk_cycle returns RAM[address] from before the tick and
+///           latches val into it when load is set
+pub trait RamChip: Clone + fmt::Debug + Send {
+    /// Number of 16-bit words, addressed by the low log2(WORDS) address bits
+    const WORDS: usize;
+
+    fn new() -> Self;
+    fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16];
+
+    /// Words actually backed by storage, less than WORDS for sparse chips
+    fn allocated_words(&self) -> usize {
+        Self::WORDS
+    }
+}
+
+macro_rules! impl_ram_chip {
+    ($($chip:ident: $size:expr),*) => {$(
+        impl RamChip for $chip {
+            const WORDS: usize = $size;
+
+            fn new() -> $chip { $chip::new() }
+
+            fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
+                $chip::clk_cycle(self, val, addr, load)
+            }
+        }
+    )*};
+}
+
+impl_ram_chip!(RAM8: 8, RAM64: 64, RAM512: 512, RAM4096: 4096, RAM16384: 16384);
+
+/// FastRAM - Behavioral RAM of WORDS words, one u16 per word on the heap
+/// Same clk_cycle timing as the RAMn tree without simulating its gates,
+/// for programs that run for millions of cycles. WORDS must be a power of two.
+#[derive(Clone, Debug)]
+pub struct FastRAM<const WORDS: usize> {
+    words: Vec<u16>,
+}
+
+impl<const WORDS: usize> FastRAM<WORDS> {
+    pub fn new() -> FastRAM<WORDS> {
+        assert!(WORDS.is_power_of_two(), "FastRAM size {} is not a power of two", WORDS);
+        FastRAM{ words: vec![0; WORDS] }
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
+        let addr = &addr[0..WORDS.trailing_zeros() as usize];
+        let idx = utils::boollist_to_u32(addr) as usize;
+        let res = self.words[idx];
+        if load {
+            self.words[idx] = utils::boollist_to_u32(&val[0..16]) as u16;
+        }
+        let out = utils::bytes_to_boollist(&utils::split_u16(res));
+        trace_ports!(("in", &val[0..16]), ("address", addr), ("load", &[load]), ("out", &out));
+        out
+    }
+}
+
+impl<const WORDS: usize> Default for FastRAM<WORDS> {
+    fn default() -> FastRAM<WORDS> { FastRAM::new() }
+}
+
+impl<const WORDS: usize> RamChip for FastRAM<WORDS> {
+    const WORDS: usize = WORDS;
+
+    fn new() -> FastRAM<WORDS> { FastRAM::new() }
+
+    fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
+        FastRAM::clk_cycle(self, val, addr, load)
+    }
+}
+
+/// RAMBank - N chips of type C side by side, for memories larger than RAM16K
+/// Function: address bits above C's select a bank with a decoder; out is the
+///           selected bank's out, with the RAMn timing.
+/// Comment: banks are allocated on the heap the first time they are written and
+/// read as zero until then, so untouched pages cost nothing. Unselected banks hold
+/// their contents and contribute zero to the output, so only the selected one is clocked.
+/// N must be a power of two.
+#[derive(Clone, Debug)]
+pub struct RAMBank<C: RamChip, const N: usize> {
+    banks: Vec<Option<Box<C>>>,
+}
+
+impl<C: RamChip, const N: usize> RAMBank<C, N> {
+    pub fn new() -> RAMBank<C, N> {
+        assert!(N.is_power_of_two(), "RAMBank of {} banks is not a power of two", N);
+        RAMBank{ banks: (0..N).map(|_| None).collect() }
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
+        let low = C::WORDS.trailing_zeros() as usize;
+        let sel = &addr[low..low + N.trailing_zeros() as usize];
+        let mut out = [false; 16];
+        for (i, bank) in self.banks.iter_mut().enumerate() {
+            // select line i is high when the bank bits spell i
+            let line = sel.iter().enumerate()
+                .fold(true, |acc, (j, bit)| gates::and(acc, if (i >> j) & 1 == 1 { *bit } else { gates::not(*bit) }));
+            if !line { continue; }
+            if bank.is_none() && load {
+                *bank = Some(Box::new(C::new()));
+            }
+            if let Some(chip) = bank {
+                out = trace_child!("bank", i, chip.clk_cycle(val, addr, load));
+            }
+        }
+        trace_ports!(("in", &val[0..16]), ("address", &addr[0..low + sel.len()]), ("load", &[load]), ("out", &out));
+        out
+    }
+
+    /// Number of banks allocated so far
+    pub fn allocated_banks(&self) -> usize {
+        self.banks.iter().filter(|bank| bank.is_some()).count()
+    }
+}
+
+impl<C: RamChip, const N: usize> Default for RAMBank<C, N> {
+    fn default() -> RAMBank<C, N> { RAMBank::new() }
+}
+
+impl<C: RamChip, const N: usize> RamChip for RAMBank<C, N> {
+    const WORDS: usize = N * C::WORDS;
+
+    fn new() -> RAMBank<C, N> { RAMBank::new() }
+
+    fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
+        RAMBank::clk_cycle(self, val, addr, load)
+    }
+
+    fn allocated_words(&self) -> usize {
+        self.banks.iter().flatten().map(|chip| chip.allocated_words()).sum()
+    }
+}
+
+/// RAM64K - 64K words (128KB), four RAM16K banks
+pub type RAM64K = RAMBank<RAM16384, 4>;
+/// RAM512K - 512K words (1MB), eight RAM64K banks
+pub type RAM512K = RAMBank<RAM64K, 8>;
+/// RAM2M - 2M words (4MB), four RAM512K banks
+pub type RAM2M = RAMBank<RAM512K, 4>;
+
+/// RamBackend - The RAM chips the larger memories are built from
+/// GateLevel is the RAMn tree of registers, Behavioral swaps in FastRAM.
+pub trait RamBackend: Copy + Clone + fmt::Debug + Send + 'static {
+    type Ram4K: RamChip;
+    type Ram16K: RamChip;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct GateLevel;
+
+impl RamBackend for GateLevel {
+    type Ram4K = RAM4096;
+    type Ram16K = RAM16384;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Behavioral;
+
+impl RamBackend for Behavioral {
+    type Ram4K = FastRAM<4096>;
+    type Ram16K = FastRAM<16384>;
+}
+
+// Chip name: ROM32K
+// Inputs: address[15]
+// Outputs: out[16]
+// Function: out(t)=ROM[address(t)](t)
+// Comment: read-only from the CPU side, flash writes a program in
+// from outside the machine. Built from two RAM16K banks.
+#[derive(Clone, Debug)]
+pub struct ROM32K<B: RamBackend = GateLevel> {
+    banks: [B::Ram16K; 2],
+}
+
+impl ROM32K {
+    pub fn new() -> ROM32K {
+        ROM32K::with_backend()
+    }
+}
+
+impl<B: RamBackend> ROM32K<B> {
+    pub fn with_backend() -> ROM32K<B> {
+        ROM32K{ banks: [B::Ram16K::new(), B::Ram16K::new()] }
+    }
+
+    /// Write program words starting at address 0
+    pub fn flash(&mut self, program: &[[bool; 16]]) {
+        for (i, word) in program.iter().enumerate() {
+            self.clk(word, &utils::gen_memaddr(i as u16), true);
+        }
+    }
+
+    pub fn clk_cycle(&mut self, addr: &[bool]) -> [bool; 16] {
+        self.clk(&[false; 16], addr, false)
+    }
+
+    fn clk(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
+        let (load_lo, load_hi) = gates::dmux(load, addr[14]);
+        let lo = trace_child!("RAM16K", 0, self.banks[0].clk_cycle(val, addr, load_lo));
+        let hi = trace_child!("RAM16K", 1, self.banks[1].clk_cycle(val, addr, load_hi));
+        let out = gates::mux16(&lo, &hi, addr[14]);
+        trace_ports!(("in", &val[0..16]), ("address", &addr[0..15]), ("load", &[load]), ("out", &out));
+        out
+    }
+}
+
+// Chip name: Memory // Hack data memory
+// Inputs: in[16], address[15], load
+// Outputs: out[16]
+// Function: the address space is RAM16K at 0-16383, the screen at
+// 16384-24575 and the keyboard at 24576, with the RAMn contract.
+// Comment: the keyboard is read-only from the CPU side, set_key stands
+// in for the physical keyboard.
+#[derive(Clone, Debug)]
+pub struct HackMemory<B: RamBackend = GateLevel> {
+    ram: B::Ram16K,
+    screen: [B::Ram4K; 2],
+    kbd: Register,
+}
+
+impl HackMemory {
+    pub fn new() -> HackMemory {
+        HackMemory::with_backend()
+    }
+}
+
+impl<B: RamBackend> HackMemory<B> {
+    pub fn with_backend() -> HackMemory<B> {
+        HackMemory{ ram: B::Ram16K::new(), screen: [B::Ram4K::new(), B::Ram4K::new()], kbd: Register::new() }
+    }
+
+    pub fn set_key(&mut self, key: &[bool; 16]) {
+        self.kbd.clk_cycle(key, true);
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool], addr: &[bool], load: bool) -> [bool; 16] {
+        let load_bits = gates::dmux4way(load, addr[14], addr[13]);
+        let (screen_lo_load, screen_hi_load) = gates::dmux(load_bits[2], addr[12]);
+
+        let ram = trace_child!("RAM16K", self.ram.clk_cycle(val, addr, gates::or(load_bits[0], load_bits[1])));
+        let screen_lo = trace_child!("Screen", 0, self.screen[0].clk_cycle(val, addr, screen_lo_load));
+        let screen_hi = trace_child!("Screen", 1, self.screen[1].clk_cycle(val, addr, screen_hi_load));
+        let screen = gates::mux16(&screen_lo, &screen_hi, addr[12]);
+        let kbd = self.kbd.read();
+
+        let out = gates::mux4way16(&[ram, ram, screen, kbd], (addr[14], addr[13]));
+        trace_ports!(("in", &val[0..16]), ("address", &addr[0..15]), ("load", &[load]), ("out", &out));
+        out
+    }
+}
+
+/// WordMemory - A 16-bit word-addressed chip seen from outside the machine
+/// Lets loaders and dumpers fill or read any RAMn or ROM32K word by word.
+pub trait WordMemory {
+    /// Number of 16-bit words
+    fn size(&self) -> usize;
+    fn read_word(&mut self, addr: usize) -> [bool; 16];
+    fn write_word(&mut self, addr: usize, val: &[bool; 16]);
+}
+
+impl<R: RamChip> WordMemory for R {
+    fn size(&self) -> usize { R::WORDS }
+
+    fn read_word(&mut self, addr: usize) -> [bool; 16] {
+        self.clk_cycle(&[false; 16], &utils::bytes_to_boollist32(&utils::split_u32(addr as u32)), false)
+    }
+
+    fn write_word(&mut self, addr: usize, val: &[bool; 16]) {
+        self.clk_cycle(val, &utils::bytes_to_boollist32(&utils::split_u32(addr as u32)), true);
+    }
+}
+
+// every chip powers up with all of its DFFs cleared
+macro_rules! impl_default {
+    ($($chip:ident),*) => {$(
+        impl Default for $chip {
+            fn default() -> $chip { $chip::new() }
+        }
+    )*};
+}
+
+impl_default!(DFF, BitRegister, Register, Register32, PC16, PC32, RegisterFile32,
+              RAM8, RAM64, RAM512, RAM4096, RAM16384);
+
+impl<B: RamBackend> Default for ROM32K<B> {
+    fn default() -> ROM32K<B> { ROM32K::with_backend() }
+}
+
+impl<B: RamBackend> Default for HackMemory<B> {
+    fn default() -> HackMemory<B> { HackMemory::with_backend() }
+}
+
+impl<B: RamBackend> Default for ByteRAM<B> {
+    fn default() -> ByteRAM<B> { ByteRAM::with_backend() }
+}
+
+impl<B: RamBackend> WordMemory for ROM32K<B> {
+    fn size(&self) -> usize { 32768 }
+
+    fn read_word(&mut self, addr: usize) -> [bool; 16] {
+        self.clk_cycle(&utils::gen_memaddr(addr as u16))
+    }
+
+    fn write_word(&mut self, addr: usize, val: &[bool; 16]) {
+        self.clk(val, &utils::gen_memaddr(addr as u16), true);
+    }
+}
+
+// whole-byte lane moves are pure wiring, no gates involved
+fn shift_lanes_right(val: &[bool; 32], lanes: usize) -> [bool; 32] {
+    let mut res = [false; 32];
+    res[0..32-8*lanes].copy_from_slice(&val[8*lanes..32]);
+    res
+}
+
+fn shift_lanes_left(val: &[bool; 32], lanes: usize) -> [bool; 32] {
+    let mut res = [false; 32];
+    res[8*lanes..32].copy_from_slice(&val[0..32-8*lanes]);
+    res
+}
+
+/// Largest ByteRAM, in bytes
+pub const BYTE_RAM_MAX: u32 = 1 << 21;
+
+// 512K halfwords of sparse RAM4K pages
+type ByteLane<R> = RAMBank<RAMBank<R, 16>, 8>;
+
+// Chip name: ByteRAM // byte-addressed main memory of the RV32I CPU
+// Inputs: in[32], address[32] (byte address), width[2], signed, load
+// Outputs: out[32], fault
+// Function: width 00=byte, 01=half, 10=word (funct3[1..0] of LB/LH/LW/SB/SH/SW)
+// out(t)=the width-sized little endian value at address(t), sign extended
+// if signed else zero extended
+// If load(t-1) and not fault(t-1) then
+// the addressed lanes of the word at address(t-1)[20..2] are replaced by in(t-1)
+// Comment: fault is set for halfwords at odd addresses, words at addresses
+// not divisible by 4 and addresses at or past the size; such stores are dropped.
+// The size is a multiple of 4 up to 2MB (16KB unless set_size changes it),
+// compared against the address with an Add32.
+// Each 32-bit word is split across a low and a high lane of sparse RAM4K pages,
+// so memory that is never written costs nothing.
+#[derive(Clone, Debug)]
+pub struct ByteRAM<B: RamBackend = GateLevel> {
+    lo: ByteLane<B::Ram4K>,
+    hi: ByteLane<B::Ram4K>,
+    size: [bool; 32],
+}
+
+impl ByteRAM {
+    pub fn new() -> ByteRAM {
+        ByteRAM::with_backend()
+    }
+}
+
+impl<B: RamBackend> ByteRAM<B> {
+    pub fn with_backend() -> ByteRAM<B> {
+        ByteRAM{ lo: RAMBank::new(), hi: RAMBank::new(), size: utils::bytes_to_boollist32(&utils::split_u32(16384)) }
+    }
+
+    /// Size in bytes
+    pub fn bytes(&self) -> u32 {
+        utils::boollist_to_u32(&self.size)
+    }
+
+    /// Set the size in bytes, a multiple of 4 no larger than BYTE_RAM_MAX
+    pub fn set_size(&mut self, bytes: u32) {
+        assert!(bytes.is_multiple_of(4) && bytes <= BYTE_RAM_MAX, "ByteRAM of {} bytes", bytes);
+        self.size = utils::bytes_to_boollist32(&utils::split_u32(bytes));
+    }
+
+    pub fn clk_cycle(&mut self, val: &[bool], addr: &[bool], width: (bool, bool), signed: bool, load: bool) -> ([bool; 32], bool) {
+        let (word, half) = width;
+        let misaligned = gates::or(gates::and(half, addr[0]), gates::and(word, gates::or(addr[0], addr[1])));
+        // address - size borrows unless address >= size
+        let mut address = [false; 32];
+        address.copy_from_slice(&addr[0..32]);
+        let (_, past_end, _) = adder::add32_carry(&address, &gates::not32(&self.size), true);
+        let fault = gates::or(misaligned, past_end);
+
+        let mut old = [false; 32];
+        old[0..16].copy_from_slice(&trace_child!("lo", self.lo.clk_cycle(&[false; 16], &addr[2..21], false)));
+        old[16..32].copy_from_slice(&trace_child!("hi", self.hi.clk_cycle(&[false; 16], &addr[2..21], false)));
+
+        let mut data = [false; 32];
+        data.copy_from_slice(&val[0..32]);
+        let merged = Self::store_merge(&old, &data, &addr[0..2], width);
+        let write = gates::and(load, gates::not(fault));
+        trace_child!("lo", self.lo.clk_cycle(&merged[0..16], &addr[2..21], write));
+        trace_child!("hi", self.hi.clk_cycle(&merged[16..32], &addr[2..21], write));
+
+        let out = Self::load_extend(&old, &addr[0..2], width, signed);
+        trace_ports!(("in", &val[0..32]), ("address", &addr[0..32]), ("width", &[width.1, width.0]), ("signed", &[signed]),
+                     ("load", &[load]), ("out", &out), ("fault", &[fault]));
+        (out, fault)
+    }
+
+    // Select the addressed lane of a word and extend it to 32 bits
+    fn load_extend(word: &[bool; 32], offset: &[bool], width: (bool, bool), signed: bool) -> [bool; 32] {
+        let lanes = [*word, shift_lanes_right(word, 1), shift_lanes_right(word, 2), shift_lanes_right(word, 3)];
+        let aligned = gates::mux4way32(&lanes, (offset[1], offset[0]));
+
+        let mut byte = [gates::and(signed, aligned[7]); 32];
+        byte[0..8].copy_from_slice(&aligned[0..8]);
+        let mut half = [gates::and(signed, aligned[15]); 32];
+        half[0..16].copy_from_slice(&aligned[0..16]);
+
+        gates::mux4way32(&[byte, half, aligned, aligned], width)
+    }
+
+    // Replace the addressed lanes of a word with data
+    fn store_merge(old: &[bool; 32], data: &[bool; 32], offset: &[bool], width: (bool, bool)) -> [bool; 32] {
+        let lanes = [*data, shift_lanes_left(data, 1), shift_lanes_left(data, 2), shift_lanes_left(data, 3)];
+        let shifted = gates::mux4way32(&lanes, (offset[1], offset[0]));
+
+        let byte_en = gates::dmux4way(true, offset[1], offset[0]);
+        let half_en = [byte_en[0], byte_en[0], byte_en[2], byte_en[2]];
+        let mut lane_en = [false; 4];
+        for i in 0..4 {
+            lane_en[i] = gates::or(width.0, gates::mux(byte_en[i], half_en[i], width.1));
+        }
+
+        let mut res = [false; 32];
+        for i in 0..32 {
+            res[i] = gates::mux(old[i], shifted[i], lane_en[i / 8]);
+        }
+        res
+    }
+}
+
+// halfword view, so the 16-bit image formats can fill the CPU's memory
+impl<B: RamBackend> WordMemory for ByteRAM<B> {
+    fn size(&self) -> usize { self.bytes() as usize / 2 }
+
+    fn read_word(&mut self, addr: usize) -> [bool; 16] {
+        let addr = utils::bytes_to_boollist32(&utils::split_u32(2 * addr as u32));
+        let (out, _) = self.clk_cycle(&[false; 32], &addr, (false, true), false, false);
+        let mut res = [false; 16];
+        res.copy_from_slice(&out[0..16]);
+        res
+    }
+
+    fn write_word(&mut self, addr: usize, val: &[bool; 16]) {
+        let addr = utils::bytes_to_boollist32(&utils::split_u32(2 * addr as u32));
+        let mut data = [false; 32];
+        data[0..16].copy_from_slice(val);
+        self.clk_cycle(&data, &addr, (false, true), false, true);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use utils::bytes_to_boollist;
-
     use super::*;
 
     #[test]
@@ -305,6 +898,71 @@ mod tests {
         assert_eq!(register.clk_cycle(&zeros,false),zeros);
     }
 
+    #[test]
+    fn test_pc16_works() {
+        let mut pc = PC16::new();
+        let val = |x: u16| utils::bytes_to_boollist(&utils::split_u16(x));
+        let input = val(0x1234);
+
+        // out is the old value, reset beats load beats inc
+        assert_eq!(pc.clk_cycle(&input, false, false, false), val(0));
+        assert_eq!(pc.clk_cycle(&input, true, false, false), val(0));
+        assert_eq!(pc.clk_cycle(&input, true, false, false), val(1));
+        assert_eq!(pc.clk_cycle(&input, true, true, false), val(2));
+        assert_eq!(pc.clk_cycle(&input, false, false, false), input);
+        assert_eq!(pc.clk_cycle(&input, true, true, true), input);
+        assert_eq!(pc.read(), val(0));
+        pc.clk_cycle(&val(0xffff), false, true, false);
+        pc.clk_cycle(&input, true, false, false);
+        assert_eq!(pc.read(), val(0));
+    }
+
+    #[test]
+    fn test_pc32_works() {
+        let mut pc = PC32::new();
+        let val = |x: u32| utils::bytes_to_boollist32(&utils::split_u32(x));
+        let input = val(0xdeadbeef);
+
+        assert_eq!(pc.clk_cycle(&input, true, false, false, false), val(0));
+        assert_eq!(pc.clk_cycle(&input, true, true, false, false), val(1));
+        assert_eq!(pc.clk_cycle(&input, false, true, false, false), val(5));
+        assert_eq!(pc.clk_cycle(&input, true, true, true, false), val(5));
+        assert_eq!(pc.clk_cycle(&input, true, true, false, false), input);
+        assert_eq!(pc.clk_cycle(&input, true, true, true, true), val(0xdeadbef3));
+        assert_eq!(pc.read(), val(0));
+    }
+
+    #[test]
+    fn test_register_file32_works() {
+        let mut regs = RegisterFile32::new();
+        let input = utils::bytes_to_boollist32(&[0xde, 0xad, 0xbe, 0xef]);
+        let zeros = [false; 32];
+        let idx = utils::gen_memaddr;
+
+        // write every register with load set, reads return the old contents
+        for i in 0..32 {
+            let (out1, out2) = regs.clk_cycle(&idx(i), &idx(i), &idx(i), &input, true);
+            assert_eq!(out1, zeros);
+            assert_eq!(out2, zeros);
+        }
+
+        // x0 ignored the write, every other register latched it
+        for i in 0..32 {
+            let expected = if i == 0 { zeros } else { input };
+            let (out1, out2) = regs.clk_cycle(&idx(i), &idx(31 - i), &idx(0), &zeros, false);
+            assert_eq!(out1, expected);
+            assert_eq!(out2, if i == 31 { zeros } else { input });
+        }
+
+        // load not set leaves rd untouched
+        regs.clk_cycle(&idx(0), &idx(0), &idx(5), &zeros, false);
+        assert_eq!(regs.clk_cycle(&idx(5), &idx(0), &idx(0), &zeros, false).0, input);
+
+        // load set only touches rd
+        regs.clk_cycle(&idx(0), &idx(0), &idx(5), &zeros, true);
+        assert_eq!(regs.clk_cycle(&idx(5), &idx(6), &idx(0), &zeros, false), (zeros, input));
+    }
+
     #[test]
     fn test_ram8_works() {
         let mut ram = RAM8::new();
@@ -450,4 +1108,123 @@ mod tests {
             assert_eq!(reading, zeros);
         }
     }
+
+    #[test]
+    fn test_byte_ram_works() {
+        let mut ram = ByteRAM::new();
+        let word = |val: u32| utils::bytes_to_boollist32(&utils::split_u32(val));
+        let (byte, half, full) = ((false, false), (false, true), (true, false));
+        let zeros = [false; 32];
+
+        // stores return the old contents and land little endian
+        assert_eq!(ram.clk_cycle(&word(0xdeadbeef), &word(0x100), full, false, true), (zeros, false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x100), byte, true, false), (word(0xffffffef), false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x101), byte, false, false), (word(0xbe), false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x103), byte, true, false), (word(0xffffffde), false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x102), half, true, false), (word(0xffffdead), false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x100), half, false, false), (word(0xbeef), false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x100), full, true, false), (word(0xdeadbeef), false));
+
+        // sub-word stores only touch their lanes
+        ram.clk_cycle(&word(0x12345678), &word(0x101), byte, false, true);
+        ram.clk_cycle(&word(0x12345678), &word(0x102), half, false, true);
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x100), full, false, false).0, word(0x567878ef));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x104), full, false, false).0, zeros);
+
+        // misaligned accesses are reported and stores are dropped
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x101), half, false, true).1, true);
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x102), full, false, true).1, true);
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x103), byte, false, false).1, false);
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x100), full, false, false), (word(0x567878ef), false));
+
+        // past the 16KB default nothing aliases low memory
+        assert_eq!(ram.clk_cycle(&word(0x11), &word(0x4100), full, false, true).1, true);
+        assert_eq!(ram.clk_cycle(&zeros, &word(0xffff_fffc), full, false, false).1, true);
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x100), full, false, false), (word(0x567878ef), false));
+
+        ram.set_size(BYTE_RAM_MAX);
+        assert_eq!(ram.size(), 1 << 20);
+        assert_eq!(ram.clk_cycle(&word(0x11), &word(0x4100), full, false, true), (zeros, false));
+        assert_eq!(ram.clk_cycle(&word(0x22), &word(BYTE_RAM_MAX - 4), full, false, true), (zeros, false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x4100), full, false, false), (word(0x11), false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(BYTE_RAM_MAX - 4), full, false, false), (word(0x22), false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(0x100), full, false, false), (word(0x567878ef), false));
+        assert_eq!(ram.clk_cycle(&zeros, &word(BYTE_RAM_MAX), byte, false, false).1, true);
+    }
+
+    // random values, loads and addresses from a small pool so reads land on earlier writes;
+    // the upper address bits are random too and must be ignored by both chips
+    fn ram_matches_fast<R: RamChip, F: RamChip>(steps: usize) {
+        assert_eq!(R::WORDS, F::WORDS);
+        let mut gate = Box::new(R::new());
+        let mut fast = F::new();
+        let mut rng = utils::Rng::new(0x9e3779b9);
+        let mut rand = || rng.next_u32();
+        let pool: Vec<u16> = (0..8).map(|_| (rand() >> 16) as u16).collect();
+        for step in 0..steps {
+            let addr = utils::gen_memaddr(pool[rand() as usize % 8]);
+            let val = utils::bytes_to_boollist(&utils::split_u16(rand() as u16));
+            let load = rand() & 0x100 != 0;
+            assert_eq!(gate.clk_cycle(&val, &addr, load), fast.clk_cycle(&val, &addr, load), "step {}", step);
+        }
+    }
+
+    #[test]
+    fn test_fast_ram_matches_gates() {
+        ram_matches_fast::<RAM8, FastRAM<8>>(200);
+        ram_matches_fast::<RAM64, FastRAM<64>>(200);
+        ram_matches_fast::<RAM512, FastRAM<512>>(100);
+        ram_matches_fast::<RAM4096, FastRAM<4096>>(50);
+        ram_matches_fast::<RAM16384, FastRAM<16384>>(20);
+    }
+
+    #[test]
+    fn test_ram_bank_matches_fast() {
+        ram_matches_fast::<RAMBank<RAM8, 4>, FastRAM<32>>(200);
+        ram_matches_fast::<RAMBank<RAMBank<RAM8, 2>, 8>, FastRAM<128>>(200);
+    }
+
+    #[test]
+    fn test_ram_bank_is_sparse() {
+        let mut ram = RAM512K::new();
+        assert_eq!(ram.size(), 512 * 1024);
+        assert_eq!(ram.allocated_words(), 0);
+
+        // reads of untouched pages allocate nothing
+        assert_eq!(ram.read_word(300_000), [false; 16]);
+        assert_eq!(ram.allocated_words(), 0);
+
+        let top = utils::bytes_to_boollist(&[0xbe, 0xef]);
+        ram.write_word(0, &top);
+        ram.write_word(512 * 1024 - 1, &top);
+        assert_eq!(ram.allocated_banks(), 2);
+        assert_eq!(ram.allocated_words(), 2 * 16384);
+        assert_eq!(ram.read_word(512 * 1024 - 1), top);
+        assert_eq!(ram.read_word(512 * 1024 - 2), [false; 16]);
+        assert_eq!(ram.read_word(65536), [false; 16]);
+
+        // 4MB of address space, one RAM16K behind a single write
+        let mut big = RAM2M::new();
+        big.write_word(0x1abcde, &top);
+        assert_eq!(big.read_word(0x1abcde), top);
+        assert_eq!(big.allocated_words(), 16384);
+    }
+
+    #[test]
+    fn test_byte_ram_backends_match() {
+        let w = |x: u32| utils::bytes_to_boollist32(&utils::split_u32(x));
+        let mut gate = Box::new(ByteRAM::new());
+        let mut fast = ByteRAM::<Behavioral>::with_backend();
+        let mut rng = utils::Rng::new(0x1b873593);
+        let mut rand = || rng.next_u32();
+        for step in 0..60 {
+            let addr = w(0x200 + (rand() >> 24) % 16);
+            let val = w(rand());
+            let r = rand();
+            let width = [(false, false), (false, true), (true, false)][(r % 3) as usize];
+            let (signed, load) = (r & 0x100 != 0, r & 0x200 != 0);
+            assert_eq!(gate.clk_cycle(&val, &addr, width, signed, load),
+                       fast.clk_cycle(&val, &addr, width, signed, load), "step {}", step);
+        }
+    }
 }
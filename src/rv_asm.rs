@@ -0,0 +1,610 @@
+use std::collections::HashMap;
+use crate::*;
+use hack_asm::AsmError;
+
+/// Image - Assembled program, .text at address 0 followed by word-aligned .data
+pub struct Image {
+    pub bytes: Vec<u8>,
+    pub symbols: HashMap<String, u32>,
+}
+
+impl Image {
+    /// Little endian words of the image, ready for cpu::Cpu::load_program
+    pub fn words(&self) -> Vec<u32> {
+        self.bytes.chunks(4)
+            .map(|chunk| {
+                let mut word = [0u8; 4];
+                word[0..chunk.len()].copy_from_slice(chunk);
+                u32::from_le_bytes(word)
+            })
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Section {
+    Text,
+    Data,
+}
+
+enum Item {
+    Inst(String, Vec<String>),
+    Word(Vec<String>),
+    Byte(Vec<String>),
+    Ascii(Vec<u8>),
+}
+
+struct Stmt {
+    line: usize,
+    section: Section,
+    offset: u32,
+    item: Item,
+}
+
+/// ABI names of x0..x31
+pub const ABI: [&str; 32] = ["zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+                             "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+                             "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+                             "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6"];
+
+pub fn parse_reg(name: &str) -> Option<u32> {
+    if name == "fp" { return Some(8); }
+    if let Some(idx) = ABI.iter().position(|abi| *abi == name) {
+        return Some(idx as u32);
+    }
+    match name.strip_prefix('x')?.parse::<u32>() {
+        Ok(idx) if idx < 32 => Some(idx),
+        _ => None,
+    }
+}
+
+fn parse_imm(text: &str) -> Option<i64> {
+    let (neg, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let val = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i64>().ok()?
+    };
+    Some(if neg { -val } else { val })
+}
+
+fn split_operands(text: &str) -> Vec<String> {
+    if text.trim().is_empty() { return Vec::new(); }
+    text.split(',').map(|op| op.trim().to_string()).collect()
+}
+
+fn parse_string(text: &str) -> Option<Vec<u8>> {
+    let inner = text.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut res = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                '\\' => '\\',
+                '"' => '"',
+                _ => return None,
+            },
+            '"' => return None,
+            c => c,
+        };
+        let mut buf = [0u8; 4];
+        res.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    Some(res)
+}
+
+// drop a # comment unless it is inside a string literal
+fn strip_comment(line: &str) -> &str {
+    let mut in_str = false;
+    let mut prev = ' ';
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' if prev != '\\' => in_str = !in_str,
+            '#' if !in_str => return &line[..i],
+            _ => {},
+        }
+        prev = c;
+    }
+    line
+}
+
+fn is_label(name: &str) -> bool {
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {},
+        _ => return false,
+    }
+    name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+// lui/addi split of a 32-bit constant, addi sign extends so the upper part is rounded
+fn split_hi_lo(val: i64) -> (u32, i64) {
+    let val = val as u32;
+    let hi = (val.wrapping_add(0x800) >> 12) & 0xfffff;
+    let lo = (val.wrapping_sub(hi << 12)) as i32 as i64;
+    (hi, lo)
+}
+
+fn inst_size(mnemonic: &str, ops: &[String], line: usize) -> Result<u32, AsmError> {
+    if mnemonic != "li" { return Ok(4); }
+    let val = ops.get(1)
+        .and_then(|op| parse_imm(op))
+        .filter(|val| (-(1 << 31)..(1 << 32)).contains(val))
+        .ok_or_else(|| AsmError{ line, msg: "li needs a register and a 32-bit constant".to_string() })?;
+    let (hi, lo) = split_hi_lo(val);
+    Ok(if hi != 0 && lo != 0 { 8 } else { 4 })
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn i_type(imm: i64, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    ((imm as u32) & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn s_type(imm: i64, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 5) & 0x7f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | (imm & 0x1f) << 7 | 0b0100011
+}
+
+fn b_type(imm: i64, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 12) & 1) << 31 | ((imm >> 5) & 0x3f) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12
+        | ((imm >> 1) & 0xf) << 8 | ((imm >> 11) & 1) << 7 | 0b1100011
+}
+
+fn u_type(imm: u32, rd: u32, opcode: u32) -> u32 {
+    (imm & 0xfffff) << 12 | rd << 7 | opcode
+}
+
+fn j_type(imm: i64, rd: u32) -> u32 {
+    let imm = imm as u32;
+    ((imm >> 20) & 1) << 31 | ((imm >> 1) & 0x3ff) << 21 | ((imm >> 11) & 1) << 20
+        | ((imm >> 12) & 0xff) << 12 | rd << 7 | 0b1101111
+}
+
+/// Encoder for one statement, resolving registers, immediates and labels
+struct Encoder<'a> {
+    line: usize,
+    pc: u32,
+    symbols: &'a HashMap<String, u32>,
+}
+
+impl Encoder<'_> {
+    fn err(&self, msg: String) -> AsmError {
+        AsmError{ line: self.line, msg }
+    }
+
+    fn reg(&self, op: &str) -> Result<u32, AsmError> {
+        parse_reg(op).ok_or_else(|| self.err(format!("unknown register '{}'", op)))
+    }
+
+    fn imm(&self, op: &str, min: i64, max: i64) -> Result<i64, AsmError> {
+        match parse_imm(op) {
+            Some(val) if (min..=max).contains(&val) => Ok(val),
+            Some(_) => Err(self.err(format!("immediate '{}' out of range", op))),
+            None => Err(self.err(format!("bad immediate '{}'", op))),
+        }
+    }
+
+    // labels and plain numbers are both absolute addresses, encoded relative to pc
+    fn target(&self, op: &str, bits: u32) -> Result<i64, AsmError> {
+        let addr = match self.symbols.get(op) {
+            Some(addr) => *addr as i64,
+            None if is_label(op) => return Err(self.err(format!("undefined label '{}'", op))),
+            None => self.imm(op, 0, u32::MAX as i64)?,
+        };
+        let offset = addr - self.pc as i64;
+        let range = 1i64 << (bits - 1);
+        if offset % 2 != 0 || !(-range..range).contains(&offset) {
+            return Err(self.err(format!("branch target '{}' out of range", op)));
+        }
+        Ok(offset)
+    }
+
+    // off(reg), with an empty offset meaning 0
+    fn mem(&self, op: &str) -> Result<(i64, u32), AsmError> {
+        let (off, rest) = op.split_once('(')
+            .ok_or_else(|| self.err(format!("expected offset(register), got '{}'", op)))?;
+        let reg = rest.strip_suffix(')')
+            .ok_or_else(|| self.err(format!("expected offset(register), got '{}'", op)))?;
+        let off = match off.trim() {
+            "" => 0,
+            off => self.imm(off, -2048, 2047)?,
+        };
+        Ok((off, self.reg(reg.trim())?))
+    }
+
+    fn expect(&self, mnemonic: &str, ops: &[String], count: usize) -> Result<(), AsmError> {
+        if ops.len() != count {
+            return Err(self.err(format!("{} takes {} operands, got {}", mnemonic, count, ops.len())));
+        }
+        Ok(())
+    }
+
+    fn encode(&self, mnemonic: &str, ops: &[String]) -> Result<Vec<u32>, AsmError> {
+        let r_ops = [
+            ("add", 0, 0b000), ("sub", 0x20, 0b000), ("sll", 0, 0b001), ("slt", 0, 0b010),
+            ("sltu", 0, 0b011), ("xor", 0, 0b100), ("srl", 0, 0b101), ("sra", 0x20, 0b101),
+            ("or", 0, 0b110), ("and", 0, 0b111),
+        ];
+        let i_ops = [("addi", 0b000), ("slti", 0b010), ("sltiu", 0b011), ("xori", 0b100), ("ori", 0b110), ("andi", 0b111)];
+        let shift_ops = [("slli", 0, 0b001), ("srli", 0, 0b101), ("srai", 0x20, 0b101)];
+        let load_ops = [("lb", 0b000), ("lh", 0b001), ("lw", 0b010), ("lbu", 0b100), ("lhu", 0b101)];
+        let store_ops = [("sb", 0b000), ("sh", 0b001), ("sw", 0b010)];
+        let branch_ops = [("beq", 0b000), ("bne", 0b001), ("blt", 0b100), ("bge", 0b101), ("bltu", 0b110), ("bgeu", 0b111)];
+
+        if let Some((_, funct7, funct3)) = r_ops.iter().find(|op| op.0 == mnemonic) {
+            self.expect(mnemonic, ops, 3)?;
+            return Ok(vec![r_type(*funct7, self.reg(&ops[2])?, self.reg(&ops[1])?, *funct3, self.reg(&ops[0])?, 0b0110011)]);
+        }
+        if let Some((_, funct3)) = i_ops.iter().find(|op| op.0 == mnemonic) {
+            self.expect(mnemonic, ops, 3)?;
+            return Ok(vec![i_type(self.imm(&ops[2], -2048, 2047)?, self.reg(&ops[1])?, *funct3, self.reg(&ops[0])?, 0b0010011)]);
+        }
+        if let Some((_, funct7, funct3)) = shift_ops.iter().find(|op| op.0 == mnemonic) {
+            self.expect(mnemonic, ops, 3)?;
+            let shamt = self.imm(&ops[2], 0, 31)? as u32;
+            return Ok(vec![r_type(*funct7, shamt, self.reg(&ops[1])?, *funct3, self.reg(&ops[0])?, 0b0010011)]);
+        }
+        if let Some((_, funct3)) = load_ops.iter().find(|op| op.0 == mnemonic) {
+            self.expect(mnemonic, ops, 2)?;
+            let (off, rs1) = self.mem(&ops[1])?;
+            return Ok(vec![i_type(off, rs1, *funct3, self.reg(&ops[0])?, 0b0000011)]);
+        }
+        if let Some((_, funct3)) = store_ops.iter().find(|op| op.0 == mnemonic) {
+            self.expect(mnemonic, ops, 2)?;
+            let (off, rs1) = self.mem(&ops[1])?;
+            return Ok(vec![s_type(off, self.reg(&ops[0])?, rs1, *funct3)]);
+        }
+        if let Some((_, funct3)) = branch_ops.iter().find(|op| op.0 == mnemonic) {
+            self.expect(mnemonic, ops, 3)?;
+            return Ok(vec![b_type(self.target(&ops[2], 13)?, self.reg(&ops[1])?, self.reg(&ops[0])?, *funct3)]);
+        }
+
+        let inst = match mnemonic {
+            "lui" | "auipc" => {
+                self.expect(mnemonic, ops, 2)?;
+                let imm = self.imm(&ops[1], -(1 << 19), (1 << 20) - 1)? as u32;
+                let opcode = if mnemonic == "lui" { 0b0110111 } else { 0b0010111 };
+                u_type(imm, self.reg(&ops[0])?, opcode)
+            }
+            "jal" => match ops.len() {
+                1 => j_type(self.target(&ops[0], 21)?, 1),
+                _ => {
+                    self.expect(mnemonic, ops, 2)?;
+                    j_type(self.target(&ops[1], 21)?, self.reg(&ops[0])?)
+                }
+            },
+            "jalr" => match ops.len() {
+                1 => i_type(0, self.reg(&ops[0])?, 0, 1, 0b1100111),
+                2 if ops[1].contains('(') => {
+                    let (off, rs1) = self.mem(&ops[1])?;
+                    i_type(off, rs1, 0, self.reg(&ops[0])?, 0b1100111)
+                }
+                2 => i_type(0, self.reg(&ops[1])?, 0, self.reg(&ops[0])?, 0b1100111),
+                _ => {
+                    self.expect(mnemonic, ops, 3)?;
+                    i_type(self.imm(&ops[2], -2048, 2047)?, self.reg(&ops[1])?, 0, self.reg(&ops[0])?, 0b1100111)
+                }
+            },
+            "fence" => 0x0ff0000f,
+            "ecall" => {
+                self.expect(mnemonic, ops, 0)?;
+                0x00000073
+            }
+            "ebreak" => {
+                self.expect(mnemonic, ops, 0)?;
+                0x00100073
+            }
+
+            // pseudo-ops
+            "nop" => {
+                self.expect(mnemonic, ops, 0)?;
+                i_type(0, 0, 0, 0, 0b0010011)
+            }
+            "mv" => {
+                self.expect(mnemonic, ops, 2)?;
+                i_type(0, self.reg(&ops[1])?, 0, self.reg(&ops[0])?, 0b0010011)
+            }
+            "j" => {
+                self.expect(mnemonic, ops, 1)?;
+                j_type(self.target(&ops[0], 21)?, 0)
+            }
+            "ret" => {
+                self.expect(mnemonic, ops, 0)?;
+                i_type(0, 1, 0, 0, 0b1100111)
+            }
+            "li" => {
+                self.expect(mnemonic, ops, 2)?;
+                let rd = self.reg(&ops[0])?;
+                let (hi, lo) = split_hi_lo(self.imm(&ops[1], -(1 << 31), (1 << 32) - 1)?);
+                return Ok(match (hi, lo) {
+                    (0, lo) => vec![i_type(lo, 0, 0, rd, 0b0010011)],
+                    (hi, 0) => vec![u_type(hi, rd, 0b0110111)],
+                    (hi, lo) => vec![u_type(hi, rd, 0b0110111), i_type(lo, rd, 0, rd, 0b0010011)],
+                });
+            }
+            _ => return Err(self.err(format!("unknown instruction '{}'", mnemonic))),
+        };
+        Ok(vec![inst])
+    }
+}
+
+/// Assemble GNU-style RV32I assembly into a loadable image
+/// Two passes: the first sizes every statement and binds labels, the second encodes.
+/// .text is placed at 0 and .data right after it, aligned to a word.
+/// Branch and jump targets are labels or plain numbers, both absolute addresses as disasm prints them.
+pub fn assemble(src: &str) -> Result<Image, AsmError> {
+    let mut stmts = Vec::new();
+    let mut labels: Vec<(String, Section, u32, usize)> = Vec::new();
+    let mut section = Section::Text;
+    let mut sizes = [0u32; 2];
+
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let mut code = strip_comment(line).trim();
+
+        // any number of labels may prefix a statement
+        while let Some((label, rest)) = code.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) { break; }
+            labels.push((label.to_string(), section, sizes[section as usize], line_no));
+            code = rest.trim();
+        }
+        if code.is_empty() { continue; }
+
+        let (head, rest) = match code.split_once(char::is_whitespace) {
+            Some((head, rest)) => (head, rest.trim()),
+            None => (code, ""),
+        };
+        let err = |msg: String| AsmError{ line: line_no, msg };
+        let (item, size) = match head {
+            ".text" => { section = Section::Text; continue; }
+            ".data" => { section = Section::Data; continue; }
+            ".globl" | ".global" => continue,
+            ".word" => {
+                let ops = split_operands(rest);
+                let size = 4 * ops.len() as u32;
+                (Item::Word(ops), size)
+            }
+            ".byte" => {
+                let ops = split_operands(rest);
+                let size = ops.len() as u32;
+                (Item::Byte(ops), size)
+            }
+            ".ascii" => {
+                let bytes = parse_string(rest).ok_or_else(|| err(format!("bad string {}", rest)))?;
+                let size = bytes.len() as u32;
+                (Item::Ascii(bytes), size)
+            }
+            _ if head.starts_with('.') => return Err(err(format!("unknown directive '{}'", head))),
+            _ => {
+                let ops = split_operands(rest);
+                let size = inst_size(head, &ops, line_no)?;
+                (Item::Inst(head.to_string(), ops), size)
+            }
+        };
+        stmts.push(Stmt{ line: line_no, section, offset: sizes[section as usize], item });
+        sizes[section as usize] += size;
+    }
+
+    let data_base = (sizes[0] + 3) & !3;
+    let base = |section: Section| if section == Section::Text { 0 } else { data_base };
+    let mut symbols = HashMap::new();
+    for (label, section, offset, line) in labels {
+        if symbols.insert(label.clone(), base(section) + offset).is_some() {
+            return Err(AsmError{ line, msg: format!("duplicate label '{}'", label) });
+        }
+    }
+
+    let mut bytes = vec![0u8; (data_base + sizes[1]) as usize];
+    for stmt in &stmts {
+        let addr = base(stmt.section) + stmt.offset;
+        let enc = Encoder{ line: stmt.line, pc: addr, symbols: &symbols };
+        let out: Vec<u8> = match &stmt.item {
+            Item::Inst(mnemonic, ops) => enc.encode(mnemonic, ops)?
+                .iter().flat_map(|inst| inst.to_le_bytes()).collect(),
+            Item::Word(ops) => {
+                let mut out = Vec::new();
+                for op in ops {
+                    let val = match symbols.get(op) {
+                        Some(addr) => *addr,
+                        None => enc.imm(op, -(1 << 31), (1 << 32) - 1)? as u32,
+                    };
+                    out.extend_from_slice(&val.to_le_bytes());
+                }
+                out
+            }
+            Item::Byte(ops) => {
+                let mut out = Vec::new();
+                for op in ops {
+                    out.push(enc.imm(op, -128, 255)? as u8);
+                }
+                out
+            }
+            Item::Ascii(str_bytes) => str_bytes.clone(),
+        };
+        bytes[addr as usize..addr as usize + out.len()].copy_from_slice(&out);
+    }
+
+    Ok(Image{ bytes, symbols })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(src: &str) -> Vec<u32> {
+        assemble(src).unwrap().words()
+    }
+
+    #[test]
+    fn test_parse_reg_works() {
+        assert_eq!(parse_reg("x0"), Some(0));
+        assert_eq!(parse_reg("x31"), Some(31));
+        assert_eq!(parse_reg("zero"), Some(0));
+        assert_eq!(parse_reg("ra"), Some(1));
+        assert_eq!(parse_reg("fp"), Some(8));
+        assert_eq!(parse_reg("s0"), Some(8));
+        assert_eq!(parse_reg("a7"), Some(17));
+        assert_eq!(parse_reg("s11"), Some(27));
+        assert_eq!(parse_reg("t6"), Some(31));
+        assert_eq!(parse_reg("x32"), None);
+        assert_eq!(parse_reg("q1"), None);
+    }
+
+    #[test]
+    fn test_encode_works() {
+        let src = "
+            add x3, x1, x2
+            sub x4, x1, x2
+            sra a3, tp, a0
+            addi x1, x0, 12
+            sltiu s2, ra, -1
+            srai a5, tp, 31
+            lui s7, 0xdeadc
+            auipc s8, 1
+            lb tp, 0(sp)
+            lhu t2, (sp)
+            sb zero, 1(sp)
+            sw ra, 4(sp)
+            jalr zero, 0(t0)
+            fence
+            ecall
+            ebreak
+        ";
+        assert_eq!(words(src), vec![
+            0x002081b3, 0x40208233, 0x40a256b3, 0x00c00093, 0xfff0b913, 0x41f25793,
+            0xdeadcbb7, 0x00001c17, 0x00010203, 0x00015383, 0x000100a3, 0x00112223,
+            0x00028067, 0x0ff0000f, 0x00000073, 0x00100073,
+        ]);
+    }
+
+    #[test]
+    fn test_labels_work() {
+        let src = "
+                addi x1, x0, 0
+                addi x2, x0, 5
+        loop:   add x1, x1, x2      # sum 5..1
+                addi x2, x2, -1
+                bne x2, x0, loop
+                jal x5, func
+                j end
+        func:   ret
+        end:    ecall
+        ";
+        let image = assemble(src).unwrap();
+        assert_eq!(image.words(), vec![0x00000093, 0x00500113, 0x002080b3, 0xfff10113, 0xfe011ce3,
+                                       0x008002ef, 0x0080006f, 0x00008067, 0x00000073]);
+        assert_eq!(image.symbols["loop"], 8);
+        assert_eq!(image.symbols["end"], 32);
+    }
+
+    #[test]
+    fn test_numeric_targets_work() {
+        // beq at 4 to 8 is an offset of 4, like GNU as and unlike the raw encoding
+        assert_eq!(words("nop\nbeq zero, zero, 8\nj 0\njal ra, 0x100"), vec![0x00000013, 0x00000263, 0xff9ff06f, 0x0f4000ef]);
+        assert!(assemble("beq zero, zero, 7").is_err());
+        assert!(assemble("beq zero, zero, -4").is_err());
+        assert!(assemble("beq zero, zero, 0x1000").is_err());
+    }
+
+    #[test]
+    fn test_disasm_roundtrip_works() {
+        let src = "
+                li a0, 5
+        loop:   addi a0, a0, -1
+                lhu t2, (sp)
+                sw ra, 4(sp)
+                bne a0, zero, loop
+                bgeu a0, a1, end
+                jal t0, func
+                j loop
+        func:   jalr zero, 0(t0)
+        end:    ecall
+                .word 0xffffffff
+        ";
+        let image = assemble(src).unwrap().words();
+        let listing: Vec<String> = image.iter().enumerate()
+            .map(|(i, inst)| disasm::disassemble(*inst, 4 * i as u32))
+            .collect();
+        assert_eq!(listing[4], "bne a0, zero, 0x4");
+        assert_eq!(words(&listing.join("\n")), image);
+    }
+
+    #[test]
+    fn test_pseudo_ops_work() {
+        let src = "
+            nop
+            mv a0, a1
+            li t0, -5
+            li t1, 0x12345000
+            li t2, 0xdeadbeef
+            li t3, 2047
+            ret
+        ";
+        assert_eq!(words(src), vec![0x00000013, 0x00058513, 0xffb00293, 0x12345337,
+                                    0xdeadc3b7, 0xeef38393, 0x7ff00e13, 0x00008067]);
+    }
+
+    #[test]
+    fn test_data_directives_work() {
+        let src = "
+            .text
+            lw a0, 0(zero)
+            .data
+        msg:  .ascii \"hi\\n#\"
+        val:  .word 0xdeadbeef, msg
+        bytes: .byte 1, -1, 255
+        ";
+        let image = assemble(src).unwrap();
+        assert_eq!(image.symbols["msg"], 4);
+        assert_eq!(image.symbols["val"], 8);
+        assert_eq!(image.bytes, vec![0x03, 0x25, 0x00, 0x00, b'h', b'i', b'\n', b'#',
+                                     0xef, 0xbe, 0xad, 0xde, 4, 0, 0, 0, 1, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_assemble_errors_work() {
+        assert_eq!(assemble("nop\n  bogus x1").err().unwrap().line, 2);
+        assert!(assemble("addi x1, x0, 4096").is_err());
+        assert!(assemble("add x1, x2").is_err());
+        assert!(assemble("add x1, x2, x99").is_err());
+        assert!(assemble("j nowhere").is_err());
+        assert!(assemble("a:\na:").is_err());
+        assert!(assemble("slli x1, x1, 32").is_err());
+        assert!(assemble(".bogus").is_err());
+    }
+
+    #[test]
+    fn test_image_runs_on_cpu() {
+        let src = "
+                li a0, 5
+                li a1, 0
+        loop:   add a1, a1, a0
+                addi a0, a0, -1
+                bne a0, zero, loop
+                lw a2, 28(zero)
+                ecall
+            .data
+        val:    .word 0x1234
+        ";
+        let image = assemble(src).unwrap();
+        assert_eq!(image.symbols["val"], 28);
+        let mut cpu = cpu::Cpu::new();
+        cpu.load_program(&image.words());
+        cpu.run(100);
+        assert_eq!(utils::boollist_to_u32(&cpu.read_reg(11)), 15);
+        assert_eq!(utils::boollist_to_u32(&cpu.read_reg(12)), 0x1234);
+    }
+}
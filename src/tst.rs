@@ -0,0 +1,456 @@
+// nand2tetris test scripts: a .tst script loads a chip, drives its inputs with
+// set/eval/tick/tock and prints rows of an output-list. The rows go to the
+// output-file (.out) and are checked line by line against the compare-to file (.cmp).
+// Chips come from the .hdl files next to the script, or the hdl built-ins.
+use std::{fmt, fs, path};
+use crate::*;
+use hdl::{Chip, Library};
+
+/// TstError - Script failure, line is the script line or 0 for file errors
+#[derive(Debug, PartialEq)]
+pub struct TstError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for TstError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.msg),
+            line => write!(f, "line {}: {}", line, self.msg),
+        }
+    }
+}
+
+fn err<T>(line: usize, msg: String) -> Result<T, TstError> {
+    Err(TstError{ line, msg })
+}
+
+/// Column - One output-list entry, name%FMTleft.len.right
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub format: char,   // B binary, D decimal, X hex, S string
+    pub left: usize,
+    pub len: usize,
+    pub right: usize,
+}
+
+impl Column {
+    fn parse(text: &str) -> Option<Column> {
+        let (name, spec) = text.split_once('%')?;
+        let mut chars = spec.chars();
+        let format = chars.next().filter(|fmt| "BDXS".contains(*fmt))?;
+        let widths: Vec<usize> = chars.as_str().split('.').map(|num| num.parse().ok()).collect::<Option<_>>()?;
+        match widths.as_slice() {
+            [left, len, right] if !name.is_empty() => Some(Column{ name: name.to_string(), format, left: *left, len: *len, right: *right }),
+            _ => None,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.left + self.len + self.right
+    }
+
+    // the name centred in the column, cut to fit
+    fn header(&self) -> String {
+        let name: String = self.name.chars().take(self.width()).collect();
+        let left = (self.width() - name.len()) / 2;
+        format!("{}{}{}", " ".repeat(left), name, " ".repeat(self.width() - left - name.len()))
+    }
+
+    fn cell(&self, bits: &[bool]) -> String {
+        let val = utils::boollist_to_u32(bits) as u64;
+        let text = match self.format {
+            'B' => (0..self.len).rev().map(|i| if bits.get(i) == Some(&true) { '1' } else { '0' }).collect(),
+            'X' => format!("{:0len$X}", val, len = self.len),
+            'D' => {
+                // full words are two's complement, narrower pins like address are unsigned
+                let signed = match bits.len() {
+                    width if width >= 16 => (val as i64) - ((val as i64 >> (width - 1)) << width),
+                    _ => val as i64,
+                };
+                format!("{:>len$}", signed, len = self.len)
+            }
+            _ => format!("{:<len$}", val, len = self.len),
+        };
+        self.pad(&text)
+    }
+
+    fn pad(&self, text: &str) -> String {
+        format!("{}{}{}", " ".repeat(self.left), text, " ".repeat(self.right))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Cmd {
+    Load(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(String, String),
+    Eval,
+    Tick,
+    Tock,
+    Output,
+    Echo,
+    Repeat(usize, Vec<(Cmd, usize)>),
+}
+
+// words with their line numbers, ending each command at , ; ! or a brace
+fn tokenize(src: &str) -> Result<Vec<(String, usize)>, TstError> {
+    let mut toks = Vec::new();
+    let mut rest = src;
+    let mut line = 1;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if rest.starts_with("/*") {
+            let end = rest.find("*/").ok_or_else(|| TstError{ line, msg: "unterminated comment".to_string() })?;
+            line += rest[..end].matches('\n').count();
+            rest = &rest[end + 2..];
+        } else if c == '"' {
+            let end = rest[1..].find('"').ok_or_else(|| TstError{ line, msg: "unterminated string".to_string() })?;
+            toks.push((rest[..end + 2].to_string(), line));
+            rest = &rest[end + 2..];
+        } else if ",;!{}".contains(c) {
+            toks.push((c.to_string(), line));
+            rest = &rest[1..];
+        } else if c.is_whitespace() {
+            if c == '\n' { line += 1; }
+            rest = &rest[c.len_utf8()..];
+        } else {
+            let end = rest.find(|c: char| c.is_whitespace() || ",;!{}\"".contains(c)).unwrap_or(rest.len());
+            toks.push((rest[..end].to_string(), line));
+            rest = &rest[end..];
+        }
+    }
+    Ok(toks)
+}
+
+fn parse_cmds(toks: &[(String, usize)], pos: &mut usize, nested: bool) -> Result<Vec<(Cmd, usize)>, TstError> {
+    let mut cmds = Vec::new();
+    while *pos < toks.len() {
+        let start = *pos;
+        let line = toks[start].1;
+        if toks[start].0 == "}" {
+            if !nested {
+                return err(line, "unmatched '}'".to_string());
+            }
+            *pos += 1;
+            return Ok(cmds);
+        }
+        if toks[start].0 == "repeat" {
+            let count = toks.get(start + 1).and_then(|(num, _)| num.parse().ok());
+            let (Some(count), Some("{")) = (count, toks.get(start + 2).map(|(tok, _)| tok.as_str())) else {
+                return err(line, "expected repeat N {".to_string());
+            };
+            *pos = start + 3;
+            let body = parse_cmds(toks, pos, true)?;
+            cmds.push((Cmd::Repeat(count, body), line));
+            continue;
+        }
+
+        while *pos < toks.len() && !",;!{}".contains(toks[*pos].0.as_str()) {
+            *pos += 1;
+        }
+        let words: Vec<&str> = toks[start..*pos].iter().map(|(word, _)| word.as_str()).collect();
+        if *pos < toks.len() && ",;!".contains(toks[*pos].0.as_str()) {
+            *pos += 1;
+        } else {
+            return err(line, format!("expected ',' or ';' after '{}'", words.join(" ")));
+        }
+        let cmd = match words.as_slice() {
+            [] => continue,
+            ["load", file] => Cmd::Load(file.to_string()),
+            ["output-file", file] => Cmd::OutputFile(file.to_string()),
+            ["compare-to", file] => Cmd::CompareTo(file.to_string()),
+            ["output-list", cols @ ..] => Cmd::OutputList(cols.iter()
+                .map(|col| Column::parse(col).ok_or_else(|| TstError{ line, msg: format!("bad output-list entry '{}'", col) }))
+                .collect::<Result<_, _>>()?),
+            ["set", pin, val] => Cmd::Set(pin.to_string(), val.to_string()),
+            ["eval"] => Cmd::Eval,
+            ["tick"] => Cmd::Tick,
+            ["tock"] => Cmd::Tock,
+            ["output"] => Cmd::Output,
+            ["echo", ..] | ["clear-echo"] => Cmd::Echo,
+            _ => return err(line, format!("unknown command '{}'", words.join(" "))),
+        };
+        cmds.push((cmd, line));
+    }
+    if nested {
+        return err(toks.last().map_or(0, |(_, line)| *line), "expected '}'".to_string());
+    }
+    Ok(cmds)
+}
+
+// decimal, or %B %X %D prefixed, negative values wrap to the pin width
+fn parse_value(text: &str, width: usize) -> Option<Vec<bool>> {
+    let val = match text.get(..2) {
+        Some("%B") => i64::from_str_radix(&text[2..], 2).ok()?,
+        Some("%X") => i64::from_str_radix(&text[2..], 16).ok()?,
+        Some("%D") => text[2..].parse().ok()?,
+        _ => text.parse().ok()?,
+    };
+    Some((0..width).map(|i| i < 64 && (val >> i) & 1 == 1).collect())
+}
+
+/// Report - What a script printed, and the compare file it matched if any
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub lines: Vec<String>,
+    pub compared: Option<String>,
+}
+
+struct Runner<'a> {
+    dir: &'a path::Path,
+    chip: Option<Chip>,
+    columns: Vec<Column>,
+    lines: Vec<String>,
+    out_file: Option<path::PathBuf>,
+    cmp_file: Option<String>,
+    cmp: Option<Vec<String>>,
+    time: usize,
+    ticked: bool,   // between tick and tock, time prints as N+
+}
+
+impl Runner<'_> {
+    fn chip(&mut self, line: usize) -> Result<&mut Chip, TstError> {
+        self.chip.as_mut().ok_or_else(|| TstError{ line, msg: "no chip loaded".to_string() })
+    }
+
+    fn load(&mut self, file: &str, line: usize) -> Result<(), TstError> {
+        let name = file.strip_suffix(".hdl").unwrap_or(file);
+        let mut lib = Library::new();
+        if self.dir.join(format!("{}.hdl", name)).exists() {
+            lib.load_dir(self.dir).map_err(|e| TstError{ line, msg: e.to_string() })?;
+        }
+        self.chip = Some(lib.build(name).map_err(|e| TstError{ line, msg: format!("{}: {}", name, e) })?);
+        Ok(())
+    }
+
+    fn print(&mut self, row: String, line: usize) -> Result<(), TstError> {
+        let idx = self.lines.len();
+        self.lines.push(row);
+        let Some(cmp) = &self.cmp else { return Ok(()) };
+        let expected = cmp.get(idx).map_or("", |text| text.trim_end());
+        if expected != self.lines[idx] {
+            return err(line, format!("comparison failure at line {} of {}: expected '{}', got '{}'",
+                                     idx + 1, self.cmp_file.as_deref().unwrap_or(""), expected, self.lines[idx]));
+        }
+        Ok(())
+    }
+
+    fn output(&mut self, line: usize) -> Result<(), TstError> {
+        let time = format!("{}{}", self.time, if self.ticked { "+" } else { "" });
+        let mut row = String::from("|");
+        for col in &self.columns {
+            let cell = match col.name.as_str() {
+                "time" => col.pad(&format!("{:<len$}", time, len = col.len)),
+                pin => col.cell(&self.chip.as_ref().unwrap().get(pin).map_err(|e| TstError{ line, msg: e.to_string() })?),
+            };
+            row.push_str(&cell);
+            row.push('|');
+        }
+        self.print(row, line)
+    }
+
+    fn exec(&mut self, cmds: &[(Cmd, usize)]) -> Result<(), TstError> {
+        for (cmd, line) in cmds {
+            let line = *line;
+            match cmd {
+                Cmd::Load(file) => self.load(file, line)?,
+                Cmd::OutputFile(file) => self.out_file = Some(self.dir.join(file)),
+                Cmd::CompareTo(file) => {
+                    let text = fs::read_to_string(self.dir.join(file)).map_err(|e| TstError{ line, msg: format!("{}: {}", file, e) })?;
+                    self.cmp = Some(text.lines().map(String::from).collect());
+                    self.cmp_file = Some(file.clone());
+                }
+                Cmd::OutputList(cols) => {
+                    self.columns = cols.clone();
+                    let header = format!("|{}|", cols.iter().map(Column::header).collect::<Vec<_>>().join("|"));
+                    self.print(header, line)?;
+                }
+                Cmd::Set(pin, val) => {
+                    let chip = self.chip(line)?;
+                    let width = chip.inputs().iter().find(|(name, _)| name == pin).map(|(_, width)| *width)
+                        .ok_or_else(|| TstError{ line, msg: format!("{} has no input pin '{}'", chip.name(), pin) })?;
+                    let bits = parse_value(val, width).ok_or_else(|| TstError{ line, msg: format!("bad value '{}'", val) })?;
+                    chip.set(pin, &bits).map_err(|e| TstError{ line, msg: e.to_string() })?;
+                }
+                Cmd::Eval => self.chip(line)?.eval(),
+                Cmd::Tick => {
+                    self.chip(line)?.tick();
+                    self.ticked = true;
+                }
+                Cmd::Tock => {
+                    self.chip(line)?.tock();
+                    self.ticked = false;
+                    self.time += 1;
+                }
+                Cmd::Output => {
+                    self.chip(line)?;
+                    self.output(line)?;
+                }
+                Cmd::Echo => {}
+                Cmd::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.exec(body)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run a test script, files it names are relative to dir
+/// The output file is written even when the script fails part way.
+pub fn run(src: &str, dir: &path::Path) -> Result<Report, TstError> {
+    let cmds = parse_cmds(&tokenize(src)?, &mut 0, false)?;
+    let mut runner = Runner{
+        dir, chip: None, columns: Vec::new(), lines: Vec::new(),
+        out_file: None, cmp_file: None, cmp: None, time: 0, ticked: false,
+    };
+    let res = runner.exec(&cmds);
+    if let Some(file) = &runner.out_file {
+        let text: String = runner.lines.iter().map(|row| format!("{}\n", row)).collect();
+        fs::write(file, text).map_err(|e| TstError{ line: 0, msg: format!("{}: {}", file.display(), e) })?;
+    }
+    res?;
+    if let (Some(cmp), Some(file)) = (&runner.cmp, &runner.cmp_file) {
+        if cmp.iter().filter(|text| !text.trim().is_empty()).count() > runner.lines.len() {
+            return err(0, format!("comparison failure at line {} of {}: the script ended early", runner.lines.len() + 1, file));
+        }
+    }
+    Ok(Report{ lines: runner.lines, compared: runner.cmp_file })
+}
+
+/// Run the script at path
+pub fn run_file(path: &path::Path) -> Result<Report, TstError> {
+    let src = fs::read_to_string(path).map_err(|e| TstError{ line: 0, msg: format!("{}: {}", path.display(), e) })?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(path::Path::new("."));
+    run(&src, dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("nandputer-tst-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_column_format_works() {
+        let col = Column::parse("x%B1.16.1").unwrap();
+        assert_eq!(col.header(), "        x         ");
+        assert_eq!(col.cell(&utils::bytes_to_boollist(&[0x80, 0x01])), " 1000000000000001 ");
+        let col = Column::parse("out%D2.6.2").unwrap();
+        assert_eq!(col.cell(&utils::bytes_to_boollist(&[0xff, 0xfe])), "      -2  ");
+        assert_eq!(Column::parse("f%B1.1.1").unwrap().header(), " f ");
+        assert_eq!(Column::parse("addr%X0.4.0").unwrap().cell(&[true, true, false, true]), "000B");
+        assert_eq!(Column::parse("x%Q1.1.1"), None);
+        assert_eq!(parse_value("-1", 4), Some(vec![true; 4]));
+        assert_eq!(parse_value("%B0110", 4), Some(vec![false, true, true, false]));
+        assert_eq!(parse_value("%X1f", 3), Some(vec![true; 3]));
+    }
+
+    #[test]
+    fn test_hdl_chip_matches_cmp() {
+        let dir = temp_dir("xor");
+        fs::write(dir.join("Xor.hdl"), "
+            CHIP Xor {
+                IN a, b;
+                OUT out;
+                PARTS:
+                Nand(a=a, b=b, out=n);
+                Nand(a=a, b=n, out=x);
+                Nand(a=n, b=b, out=y);
+                Nand(a=x, b=y, out=out);
+            }").unwrap();
+        let script = "
+            load Xor.hdl,
+            output-file Xor.out,
+            compare-to Xor.cmp,
+            output-list a%B3.1.3 b%B3.1.3 out%B3.1.3;
+            set a 0, set b 0, eval, output;
+            set a 0, set b 1, eval, output;
+            set a 1, set b 0, eval, output;
+            set a 1, set b 1, eval, output;";
+        let cmp = "|   a   |   b   |  out  |
+                   |   0   |   0   |   0   |
+                   |   0   |   1   |   1   |
+                   |   1   |   0   |   1   |
+                   |   1   |   1   |   0   |\n".replace("                   ", "");
+        fs::write(dir.join("Xor.cmp"), &cmp).unwrap();
+        let report = run(script, &dir).unwrap();
+        assert_eq!(report.compared, Some("Xor.cmp".to_string()));
+        assert_eq!(fs::read_to_string(dir.join("Xor.out")).unwrap(), cmp);
+
+        // the first differing line is reported, the .out still has what ran
+        fs::write(dir.join("Xor.cmp"), cmp.replace("|   1   |   1   |   0   |", "|   1   |   1   |   1   |")).unwrap();
+        assert_eq!(run(script, &dir).unwrap_err().to_string(),
+                   "line 9: comparison failure at line 5 of Xor.cmp: expected '|   1   |   1   |   1   |', got '|   1   |   1   |   0   |'");
+        assert_eq!(fs::read_to_string(dir.join("Xor.out")).unwrap(), cmp);
+
+        fs::write(dir.join("Xor.cmp"), format!("{}|   0   |   0   |   0   |\n", cmp)).unwrap();
+        assert!(run(script, &dir).unwrap_err().msg.contains("ended early"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_builtin_clocked_chip_works() {
+        let dir = temp_dir("ram8");
+        let report = run("
+            load RAM8,
+            output-list time%S1.4.1 in%D1.6.1 load%B2.1.2 address%D3.1.3 out%D1.6.1;
+            set in 11, set load 1, set address 3, tick, output; tock, output;
+            set in -7, set address 5;
+            repeat 2 { tick, tock, }
+            set load 0, set address 3, eval, output;
+            set address 5, eval, output;", &dir).unwrap();
+        assert_eq!(report.compared, None);
+        assert_eq!(report.lines, [
+            "| time |   in   |load |address|  out   |",
+            "| 0+   |     11 |  1  |   3   |      0 |",
+            "| 1    |     11 |  1  |   3   |     11 |",
+            "| 3    |     -7 |  0  |   3   |     11 |",
+            "| 3    |     -7 |  0  |   5   |     -7 |",
+        ]);
+
+        assert_eq!(run("set a 1;", &dir).unwrap_err().to_string(), "line 1: no chip loaded");
+        assert_eq!(run("load Nand, set c 1;", &dir).unwrap_err().to_string(), "line 1: Nand has no input pin 'c'");
+        assert_eq!(run("load Nand, output-list out%B1.1.1; repeat 2 { output;", &dir).unwrap_err().to_string(),
+                   "line 1: expected '}'");
+        assert!(run("load Nand, frobnicate;", &dir).is_err());
+        assert!(run("load NoSuchChip;", &dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_alu_script_matches_native() {
+        let dir = temp_dir("alu");
+        let mut script = String::from("load ALU, output-list x%B1.16.1 y%B1.16.1 zx%B1.1.1 nx%B1.1.1 zy%B1.1.1 ny%B1.1.1 f%B1.1.1 no%B1.1.1 out%B1.16.1 zr%B1.1.1 ng%B1.1.1;\n");
+        let mut expected = vec!["|        x         |        y         |zx |nx |zy |ny | f |no |       out        |zr |ng |".to_string()];
+        let (x, y) = (0b0000_0000_0001_0001u16, 0b0000_0000_0000_0011u16);
+        for ctrl in [0b101010u8, 0b111111, 0b001100, 0b000010, 0b010011, 0b000111, 0b000000, 0b010101] {
+            let c = |i: u8| (ctrl >> i) & 1 == 1;
+            script.push_str(&format!("set x %B{:016b}, set y %B{:016b}, ", x, y));
+            for (i, pin) in ["zx", "nx", "zy", "ny", "f", "no"].iter().enumerate() {
+                script.push_str(&format!("set {} {}, ", pin, c(5 - i as u8) as u8));
+            }
+            script.push_str("eval, output;\n");
+
+            let res = adder::hack_alu(&utils::bytes_to_boollist(&utils::split_u16(x)), &utils::bytes_to_boollist(&utils::split_u16(y)),
+                                      &adder::HackCtrl::new(c(5), c(4), c(3), c(2), c(1), c(0)));
+            let bits: String = (0..6).map(|i| format!(" {} |", c(5 - i) as u8)).collect();
+            expected.push(format!("| {:016b} | {:016b} |{} {:016b} | {} | {} |",
+                                  x, y, bits, utils::boollist_to_u32(&res.out), res.zr as u8, res.ng as u8));
+        }
+        assert_eq!(run(&script, &dir).unwrap().lines, expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
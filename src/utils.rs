@@ -1,31 +1,24 @@
-pub fn bytes_to_boollist(bytes: &[u8]) -> [bool; 16] {
-    let mut boollist = [false; 16];
+// big endian bytes to a little endian bus (bit 0 first), bytes past N bits are ignored
+pub fn bytes_to_bus<const N: usize>(bytes: &[u8]) -> [bool; N] {
+    let mut boollist = [false; N];
     let mut total = 0;
     for byte in bytes {
         for i in 0..8 {
+            if total == N { return boollist; }
             let val = (byte >> (7-i)) & 1;
-            if val == 1 { boollist[15 - total] = true; }
-            else { boollist[15 - total] = false; }
+            boollist[N - 1 - total] = val == 1;
             total += 1;
         }
-        if total == 16 { break } // cap at 16 bits for now
     }
     boollist
 }
 
+pub fn bytes_to_boollist(bytes: &[u8]) -> [bool; 16] {
+    bytes_to_bus(bytes)
+}
+
 pub fn bytes_to_boollist32(bytes: &[u8]) -> [bool; 32] {
-    let mut boollist = [false; 32];
-    let mut total = 0;
-    for byte in bytes {
-        for i in 0..8 {
-            let val = (byte >> (7-i)) & 1;
-            if val == 1 { boollist[31 - total] = true; }
-            else { boollist[31 - total] = false; }
-            total += 1;
-        }
-        if total == 32 { break } // cap at 32 bits for now
-    }
-    boollist
+    bytes_to_bus(bytes)
 }
 
 pub fn boollist_to_bytes(boollist: &[bool]) -> [u8; 2] {
@@ -65,16 +58,30 @@ pub fn boollist_to_bytes32(boollist: &[bool]) -> [u8; 4] {
 pub fn gen_memaddr(val: u16) -> [bool; 16] {
     let byte1: u8 = (val & 0xFF) as u8;
     let byte2: u8 = ((val >> 8) & 0xFF) as u8;
-    let mut addr_bits = bytes_to_boollist(&[byte2,byte1]);
+    let addr_bits = bytes_to_boollist(&[byte2,byte1]);
     //addr_bits.reverse();
     addr_bits
 }
 
 pub fn split_u16(input: u16) -> [u8; 2] {
-    return [(input >> 8) as u8, (input & 0xFF) as u8]
+    [(input >> 8) as u8, (input & 0xFF) as u8]
+}
+
+pub fn split_u32(input: u32) -> [u8; 4] {
+    [(input >> 24) as u8, ((input >> 16) & 0xFF) as u8, ((input >> 8) & 0xFF) as u8, (input & 0xFF) as u8]
+}
+
+// reads a little endian bus (bit 0 first) of up to 32 bits back into an integer
+pub fn boollist_to_u32(boollist: &[bool]) -> u32 {
+    let mut val: u32 = 0;
+    for bit in boollist.iter().rev() {
+        val = (val << 1) | (*bit as u32);
+    }
+    val
 }
 
 // TODO this should be factored out
+#[allow(dead_code)]
 pub fn bytes_to_boolvec(bytes: &[u8]) -> Vec<bool> {
     let mut boolvec = Vec::new();
     for byte in bytes {
@@ -87,6 +94,31 @@ pub fn bytes_to_boolvec(bytes: &[u8]) -> Vec<bool> {
     boolvec
 }
 
+/// Rng - Linear congruential generator (Numerical Recipes constants) for repeatable randomized tests
+#[cfg(test)]
+pub struct Rng(u32);
+
+#[cfg(test)]
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+        self.0
+    }
+}
+
+#[cfg(test)]
+impl Iterator for Rng {
+    type Item = u32;
+
+    fn next(&mut self) -> Option<u32> {
+        Some(self.next_u32())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -141,4 +173,15 @@ mod tests {
     fn test_split_u16_works() {
         assert_eq!(split_u16(0xdead), [0xde, 0xad]);
     }
+
+    #[test]
+    fn test_split_u32_works() {
+        assert_eq!(split_u32(0xdeadbeef), [0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn test_boollist_to_u32_works() {
+        assert_eq!(boollist_to_u32(&bytes_to_boollist32(&[0x12, 0x34, 0x5d, 0x1b])), 0x12345d1b);
+        assert_eq!(boollist_to_u32(&[true, false, true]), 5);
+    }
 }
\ No newline at end of file
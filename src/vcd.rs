@@ -0,0 +1,380 @@
+// Value Change Dump tracing for GTKWave. Parents clock their children through
+// trace_child! (with an index for the k-th of an array) and every chip reports
+// its ports with trace_ports!, so the dump nests like the chips do:
+// RAM16384 -> RAM4096_k -> ... -> Register_k -> BitRegister_k.
+// A clk_cycle of the outermost chip is one timestep. The hooks are only compiled
+// in with the vcd feature, otherwise the macros leave the chips untouched;
+// with it, tracing is per thread and off until vcd::start.
+#![cfg_attr(not(feature = "vcd"), allow(dead_code))]
+
+// trace_child!(name, chip.clk_cycle(..)) or trace_child!(name, idx, ..)
+#[cfg(feature = "vcd")]
+macro_rules! trace_child {
+    ($name:expr, $idx:expr, $chip:expr) => { $crate::vcd::child_at($name, $idx, || $chip) };
+    ($name:expr, $chip:expr) => { $crate::vcd::child($name, || $chip) };
+}
+
+#[cfg(not(feature = "vcd"))]
+macro_rules! trace_child {
+    ($name:expr, $idx:expr, $chip:expr) => { $chip };
+    ($name:expr, $chip:expr) => { $chip };
+}
+
+// trace_ports!(("in", &val), ("load", &[load]), ..)
+#[cfg(feature = "vcd")]
+macro_rules! trace_ports {
+    ($($port:expr),* $(,)?) => { $crate::vcd::ports(&[$($port),*]) };
+}
+
+#[cfg(not(feature = "vcd"))]
+macro_rules! trace_ports {
+    ($($port:expr),* $(,)?) => { () };
+}
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// threads currently tracing
+static TRACING: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static TRACER: RefCell<Option<Tracer>> = const { RefCell::new(None) };
+}
+
+// the signals that changed in one timestep, as (var, value)
+type Step = (u64, Vec<(usize, Vec<bool>)>);
+
+struct Tracer {
+    top: String,
+    watch: Vec<Vec<String>>,
+    scope: Vec<String>,     // scopes open below top
+    muted: usize,           // scopes open below one no watch path reaches
+    ids: HashMap<Vec<String>, usize>,
+    vars: Vec<Vec<String>>,
+    widths: Vec<usize>,
+    pending: Vec<Option<Vec<bool>>>,
+    last: Vec<Option<Vec<bool>>>,
+    changes: Vec<Step>,
+    time: u64,
+}
+
+// a is a prefix of b or b of a
+fn overlaps(a: &[String], b: &[String]) -> bool {
+    a.iter().zip(b.iter()).all(|(a, b)| a == b)
+}
+
+impl Tracer {
+    fn scope_watched(&self) -> bool {
+        self.watch.is_empty() || self.watch.iter().any(|path| overlaps(path, &self.scope))
+    }
+
+    fn signal_watched(&self, path: &[String]) -> bool {
+        self.watch.is_empty() || self.watch.iter().any(|watch| watch.len() <= path.len() && overlaps(watch, path))
+    }
+
+    fn signal(&mut self, name: &str, val: &[bool]) {
+        let mut path = self.scope.clone();
+        path.push(name.to_string());
+        if !self.signal_watched(&path) { return; }
+        let id = match self.ids.get(&path) {
+            Some(id) => *id,
+            None => {
+                let id = self.vars.len();
+                self.ids.insert(path.clone(), id);
+                self.vars.push(path);
+                self.widths.push(val.len());
+                self.pending.push(None);
+                self.last.push(None);
+                id
+            }
+        };
+        self.pending[id] = Some(val.to_vec());
+    }
+
+    // scopes no watch path reaches are only counted, so their children cost nothing to name
+    fn enter(&mut self, name: &str, idx: Option<usize>) {
+        if self.muted > 0 {
+            self.muted += 1;
+            return;
+        }
+        self.scope.push(match idx {
+            Some(idx) => format!("{}_{}", name, idx),
+            None => name.to_string(),
+        });
+        if !self.scope_watched() {
+            self.scope.pop();
+            self.muted = 1;
+        }
+    }
+
+    fn leave(&mut self) {
+        match self.muted {
+            0 => { self.scope.pop(); }
+            _ => self.muted -= 1,
+        }
+    }
+
+    // close the timestep, keeping the last value each signal had in it
+    fn flush(&mut self) {
+        let mut step = Vec::new();
+        for id in 0..self.pending.len() {
+            if let Some(val) = self.pending[id].take() {
+                if self.last[id].as_ref() != Some(&val) {
+                    self.last[id] = Some(val.clone());
+                    step.push((id, val));
+                }
+            }
+        }
+        if !step.is_empty() {
+            self.changes.push((self.time, step));
+        }
+        self.time += 1;
+    }
+}
+
+/// Start tracing on this thread, replacing any trace in progress
+/// top names the outermost scope; watch lists dotted paths below it
+/// ("load", "RAM64_2.RAM8_1") whose signals are recorded, empty records everything.
+pub fn start(top: &str, watch: &[&str]) {
+    let tracer = Tracer{
+        top: top.to_string(),
+        watch: watch.iter().map(|path| path.split('.').map(String::from).collect()).collect(),
+        scope: Vec::new(), muted: 0,
+        ids: HashMap::new(), vars: Vec::new(), widths: Vec::new(),
+        pending: Vec::new(), last: Vec::new(), changes: Vec::new(), time: 0,
+    };
+    if TRACER.with(|cell| cell.borrow_mut().replace(tracer)).is_none() {
+        TRACING.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Stop tracing on this thread and return what was recorded
+pub fn stop() -> Option<Vcd> {
+    let mut tracer = TRACER.with(|cell| cell.borrow_mut().take())?;
+    TRACING.fetch_sub(1, Ordering::Relaxed);
+    if tracer.pending.iter().any(Option::is_some) {
+        tracer.flush();
+    }
+    Some(Vcd{ top: tracer.top, vars: tracer.vars, widths: tracer.widths, changes: tracer.changes, end: tracer.time })
+}
+
+/// Whether the chips were built with the trace hooks
+pub const ENABLED: bool = cfg!(feature = "vcd");
+
+fn with_tracer(f: impl FnOnce(&mut Tracer)) {
+    // skip the thread-local lookup while nobody traces
+    if TRACING.load(Ordering::Relaxed) == 0 { return; }
+    TRACER.with(|cell| {
+        if let Some(tracer) = cell.borrow_mut().as_mut() {
+            f(tracer);
+        }
+    });
+}
+
+/// Record the ports of the chip in the current scope, bit 0 first
+/// Called at the end of clk_cycle; when the chip is the outermost one this ends the timestep.
+pub(crate) fn ports(signals: &[(&str, &[bool])]) {
+    with_tracer(|tracer| {
+        if tracer.muted == 0 {
+            for (name, val) in signals {
+                tracer.signal(name, val);
+            }
+        }
+        if tracer.muted == 0 && tracer.scope.is_empty() {
+            tracer.flush();
+        }
+    });
+}
+
+/// Run a child chip inside its own scope
+pub(crate) fn child<R>(name: &str, f: impl FnOnce() -> R) -> R {
+    scoped(name, None, f)
+}
+
+/// Run child idx of an array of chips inside a scope named name_idx
+pub(crate) fn child_at<R>(name: &str, idx: usize, f: impl FnOnce() -> R) -> R {
+    scoped(name, Some(idx), f)
+}
+
+fn scoped<R>(name: &str, idx: Option<usize>, f: impl FnOnce() -> R) -> R {
+    with_tracer(|tracer| tracer.enter(name, idx));
+    let res = f();
+    with_tracer(Tracer::leave);
+    res
+}
+
+/// Vcd - A finished trace, Display writes it as a .vcd file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vcd {
+    top: String,
+    vars: Vec<Vec<String>>,
+    widths: Vec<usize>,
+    changes: Vec<Step>,
+    end: u64,
+}
+
+impl Vcd {
+    /// Dotted paths of the recorded signals, below the top scope
+    pub fn signals(&self) -> Vec<String> {
+        self.vars.iter().map(|path| path.join(".")).collect()
+    }
+
+    /// Value of a signal after timestep time, None before its first change
+    pub fn value_at(&self, signal: &str, time: u64) -> Option<Vec<bool>> {
+        let id = self.vars.iter().position(|path| path.join(".") == signal)?;
+        self.changes.iter()
+            .take_while(|(at, _)| *at <= time)
+            .flat_map(|(_, step)| step.iter())
+            .filter(|(var, _)| *var == id)
+            .map(|(_, val)| val.clone())
+            .last()
+    }
+
+    /// Number of timesteps
+    pub fn len(&self) -> u64 {
+        self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end == 0
+    }
+}
+
+// short printable identifiers: !, ", ... ~, !!, "!, ...
+fn var_id(mut idx: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (idx % 94) as u8) as char);
+        idx /= 94;
+        if idx == 0 { return id; }
+        idx -= 1;
+    }
+}
+
+fn write_value(f: &mut fmt::Formatter, val: &[bool], id: &str) -> fmt::Result {
+    let bits: String = val.iter().rev().map(|bit| if *bit { '1' } else { '0' }).collect();
+    match val.len() {
+        1 => writeln!(f, "{}{}", bits, id),
+        _ => writeln!(f, "b{} {}", bits, id),
+    }
+}
+
+impl fmt::Display for Vcd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "$version nandputer $end")?;
+        writeln!(f, "$timescale 1ns $end")?;
+        writeln!(f, "$scope module {} $end", self.top)?;
+
+        // sorted paths keep each scope's signals together
+        let mut order: Vec<usize> = (0..self.vars.len()).collect();
+        order.sort_by(|a, b| self.vars[*a].cmp(&self.vars[*b]));
+        let mut open: Vec<&str> = Vec::new();
+        for id in order {
+            let (name, scope) = self.vars[id].split_last().unwrap();
+            let common = open.iter().zip(scope.iter()).take_while(|(a, b)| *a == b).count();
+            for _ in common..open.len() {
+                writeln!(f, "$upscope $end")?;
+            }
+            open.truncate(common);
+            for name in &scope[common..] {
+                writeln!(f, "$scope module {} $end", name)?;
+                open.push(name);
+            }
+            writeln!(f, "$var wire {} {} {} $end", self.widths[id], var_id(id), name)?;
+        }
+        for _ in 0..=open.len() {
+            writeln!(f, "$upscope $end")?;
+        }
+        writeln!(f, "$enddefinitions $end")?;
+
+        for (time, step) in &self.changes {
+            writeln!(f, "#{}", time)?;
+            for (id, val) in step {
+                write_value(f, val, &var_id(*id))?;
+            }
+        }
+        writeln!(f, "#{}", self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "vcd")]
+    use crate::*;
+
+    #[test]
+    fn test_var_id_works() {
+        assert_eq!(var_id(0), "!");
+        assert_eq!(var_id(93), "~");
+        assert_eq!(var_id(94), "!!");
+        assert_eq!(var_id(95), "\"!");
+    }
+
+    #[test]
+    fn test_hooks_write_nested_scopes() {
+        start("Top", &[]);
+        for val in [false, true, true] {
+            child_at("Part", 1, || ports(&[("a", &[val])]));
+            ports(&[("x", &[val, false])]);
+        }
+        let vcd = stop().unwrap();
+        assert_eq!(vcd.signals(), ["Part_1.a", "x"]);
+        assert_eq!(vcd.value_at("Part_1.a", 1), Some(vec![true]));
+        assert_eq!(vcd.value_at("x", 2), Some(vec![true, false]));
+        assert_eq!(vcd.to_string(), "$version nandputer $end\n$timescale 1ns $end\n\
+            $scope module Top $end\n$scope module Part_1 $end\n$var wire 1 ! a $end\n$upscope $end\n\
+            $var wire 2 \" x $end\n$upscope $end\n$enddefinitions $end\n\
+            #0\n0!\nb00 \"\n#1\n1!\nb01 \"\n#3\n");
+    }
+
+    #[test]
+    #[cfg(feature = "vcd")]
+    fn test_register_trace_works() {
+        let mut reg = memory::Register::new();
+        start("Register", &[]);
+        reg.clk_cycle(&utils::bytes_to_boollist(&[0, 5]), true);
+        reg.clk_cycle(&utils::bytes_to_boollist(&[0, 9]), false);
+        reg.clk_cycle(&utils::bytes_to_boollist(&[0, 9]), false);
+        let vcd = stop().unwrap();
+        assert!(stop().is_none());
+
+        assert_eq!(vcd.len(), 3);
+        assert_eq!(vcd.value_at("out", 0), Some(utils::bytes_to_boollist(&[0, 0]).to_vec()));
+        assert_eq!(vcd.value_at("out", 1), Some(utils::bytes_to_boollist(&[0, 5]).to_vec()));
+        assert_eq!(vcd.value_at("load", 1), Some(vec![false]));
+        assert_eq!(vcd.value_at("BitRegister_2.in", 0), Some(vec![true]));
+        assert_eq!(vcd.value_at("BitRegister_2.out", 2), Some(vec![true]));
+        assert_eq!(vcd.signals().len(), 3 + 16 * 3);
+
+        let text = vcd.to_string();
+        assert!(text.starts_with("$version nandputer $end\n$timescale 1ns $end\n$scope module Register $end\n"));
+        assert!(text.contains("$scope module BitRegister_0 $end\n$var wire 1 "));
+        assert!(text.contains("$var wire 16 "));
+        assert!(text.contains("#1\n"));
+        assert!(!text.contains("#2\n"), "nothing changes in the last cycle");
+        assert!(text.ends_with("#3\n"));
+        assert_eq!(text.matches("$scope").count(), text.matches("$upscope").count());
+    }
+
+    #[test]
+    #[cfg(feature = "vcd")]
+    fn test_watch_limits_the_trace() {
+        let mut ram = memory::RAM64::new();
+        start("RAM64", &["address", "load", "RAM8_2.Register_5.out"]);
+        for (addr, load) in [(21, true), (21, false), (3, true)] {
+            ram.clk_cycle(&utils::bytes_to_boollist(&[0, 77]), &utils::gen_memaddr(addr), load);
+        }
+        let vcd = stop().unwrap();
+        assert_eq!(vcd.signals(), ["RAM8_2.Register_5.out", "address", "load"]);
+        assert_eq!(vcd.value_at("address", 0), Some(utils::gen_memaddr(21)[0..6].to_vec()));
+        assert_eq!(vcd.value_at("RAM8_2.Register_5.out", 1), Some(utils::bytes_to_boollist(&[0, 77]).to_vec()));
+        assert_eq!(vcd.len(), 3);
+
+        // untraced threads see nothing
+        ram.clk_cycle(&utils::bytes_to_boollist(&[0, 1]), &utils::gen_memaddr(0), true);
+        assert!(stop().is_none());
+    }
+}
@@ -0,0 +1,84 @@
+// Combinational and sequential chips, through the public API only
+use nandputer::{adder, gates, memory};
+use nandputer::{bytes_to_boollist, bytes_to_boollist32, gen_memaddr, split_u16, split_u32, WordMemory};
+
+fn bus16(val: u16) -> [bool; 16] {
+    bytes_to_boollist(&split_u16(val))
+}
+
+fn bus32(val: u32) -> [bool; 32] {
+    bytes_to_boollist32(&split_u32(val))
+}
+
+#[test]
+fn test_gates_derive_from_nand() {
+    for (a, b) in [(false, false), (false, true), (true, false), (true, true)] {
+        assert_eq!(gates::nand(a, b), !(a && b));
+        assert_eq!(gates::and(a, b), gates::not(gates::nand(a, b)));
+        assert_eq!(gates::xor(a, b), a != b);
+        assert_eq!(gates::mux(a, b, true), b);
+        assert_eq!(gates::dmux(a, b), (a && !b, a && b));
+    }
+    assert_eq!(gates::mux32(&bus32(1), &bus32(0xdeadbeef), true), bus32(0xdeadbeef));
+}
+
+#[test]
+fn test_arithmetic_works() {
+    assert_eq!(adder::full_adder(true, true, true), (true, true));
+    assert_eq!(adder::add16(&bus16(0xfffe), &bus16(3)), bus16(1));
+    assert_eq!(adder::inc32(&bus32(0xffffffff)), bus32(0));
+    assert_eq!(adder::add32_carry(&bus32(0x7fffffff), &bus32(1), false), (bus32(0x80000000), false, true));
+
+    // Hack ALU x-y: zx=0 nx=1 zy=0 ny=0 f=1 no=1
+    let out = adder::hack_alu(&bus16(7), &bus16(9), &adder::HackCtrl::new(false, true, false, false, true, true));
+    assert_eq!(out.out, bus16(0xfffe));
+    assert_eq!((out.zr, out.ng), (false, true));
+
+    // RV32I ALU sub: R-type arithmetic, c=0 d=0, negated Y
+    let out = adder::riscv_alu(&bus32(5), &bus32(5), &adder::RISCvCtrl::new(true, false, false, false, true));
+    assert_eq!(out.out, bus32(0));
+    assert!(out.zr && out.cr);
+}
+
+#[test]
+fn test_sequential_chips_work() {
+    let mut dff = memory::DFF::new();
+    assert!(!dff.clk_cycle(true));
+    assert!(dff.read());
+
+    let mut reg = memory::Register::default();
+    reg.clk_cycle(&bus16(0xbeef), true);
+    assert_eq!(reg.clk_cycle(&bus16(0), false), bus16(0xbeef));
+
+    let mut pc = memory::PC32::new();
+    pc.clk_cycle(&bus32(0x100), false, false, true, false);
+    pc.clk_cycle(&bus32(0), true, true, false, false);
+    assert_eq!(pc.read(), bus32(0x104));
+
+    let mut regs = memory::RegisterFile32::new();
+    let (x1, x0) = ([true, false, false, false, false], [false; 5]);
+    regs.clk_cycle(&x0, &x0, &x0, &bus32(42), true);
+    regs.clk_cycle(&x0, &x0, &x1, &bus32(42), true);
+    assert_eq!(regs.read(0), bus32(0));
+    assert_eq!(regs.read(1), bus32(42));
+}
+
+#[test]
+fn test_word_memories_work() {
+    let mut ram = memory::RAM512::new();
+    let mut rom = memory::ROM32K::new();
+    for mem in [&mut ram as &mut dyn WordMemory, &mut rom] {
+        let last = mem.size() - 1;
+        mem.write_word(last, &bus16(0x1234));
+        assert_eq!(mem.read_word(last), bus16(0x1234));
+        assert_eq!(mem.read_word(0), bus16(0));
+    }
+    assert_eq!(rom.clk_cycle(&gen_memaddr(0x7fff)), bus16(0x1234));
+
+    let mut bytes = memory::ByteRAM::new();
+    bytes.clk_cycle(&bus32(0xdeadbeef), &bus32(8), (true, false), false, true);
+    // lb at byte 11 sign-extends 0xde
+    assert_eq!(bytes.clk_cycle(&bus32(0), &bus32(11), (false, false), true, false), (bus32(0xffffffde), false));
+    assert!(bytes.clk_cycle(&bus32(0), &bus32(9), (false, true), true, false).1);
+    assert!(bytes.clk_cycle(&bus32(0), &bus32(16384), (false, false), true, false).1);
+}
@@ -0,0 +1,137 @@
+// Whole machines and the tooling around them, through the public API only
+use nandputer::{bus, cpu, disasm, hack_asm, memfile, memory, rv_asm};
+use nandputer::{Behavioral, Bus, Computer, Cpu, WordMemory};
+use nandputer::{boollist_to_u32, bytes_to_boollist, bytes_to_boollist32, split_u16, split_u32};
+
+fn bus16(val: u16) -> [bool; 16] {
+    bytes_to_boollist(&split_u16(val))
+}
+
+#[test]
+fn test_cpu_runs_assembled_program() {
+    let image = rv_asm::assemble("
+            li t0, 40
+            li a0, 0
+            li t1, 4
+    loop:   lw t2, 0(t0)
+            add a0, a0, t2
+            addi t0, t0, 4
+            addi t1, t1, -1
+            bne t1, zero, loop
+            sw a0, 0(t0)
+            ebreak
+        .data
+    nums:   .word 1, 20, 300, 4000
+    ").unwrap();
+    assert_eq!(image.symbols["nums"], 40);
+    let mut cpu = Cpu::new();
+    cpu.load_program(&image.words());
+    cpu.run(1000);
+    assert!(cpu.is_halted());
+    assert_eq!(boollist_to_u32(&cpu.read_reg(10)), 4321);
+    let sum_addr = image.symbols["nums"] + 16;
+    assert_eq!(boollist_to_u32(&cpu.read_mem(&bytes_to_boollist32(&split_u32(sum_addr)))), 4321);
+    assert_eq!(disasm::disassemble(image.words()[9], 36), "ebreak");
+}
+
+#[test]
+fn test_cpu_memory_loads_from_image_files() {
+    let mut cpu = Cpu::new();
+    // addi a0, zero, 7 ; ecall as $readmemh halfwords
+    memfile::load_readmemh(cpu.mem(), "0513 0070 0073 0000", 0).unwrap();
+    cpu.run(10);
+    assert_eq!(boollist_to_u32(&cpu.read_reg(10)), 7);
+    assert_eq!(memfile::dump_readmemh(cpu.mem(), 0, 2).unwrap(), "@0000\n0513\n0070\n");
+    assert_eq!(cpu.mem().size(), 8192);
+}
+
+#[test]
+fn test_hack_computer_runs_assembled_program() {
+    // R2 = max(R0, R1)
+    let program = hack_asm::assemble("
+        @R0
+        D=M
+        @R1
+        D=D-M
+        @FIRST
+        D;JGT
+        @R1
+        D=M
+        @OUT
+        0;JMP
+    (FIRST)
+        @R0
+        D=M
+    (OUT)
+        @R2
+        M=D
+    (END)
+        @END
+        0;JMP
+    ").unwrap();
+    let mut computer = Computer::<Behavioral>::default();
+    computer.load_program(&program);
+    computer.write_mem(&bus16(0), &bus16(17));
+    computer.write_mem(&bus16(1), &bus16(42));
+    computer.run(20);
+    assert_eq!(computer.read_mem(&bus16(2)), bus16(42));
+    assert_eq!(computer.cpu().pc(), bus16(14));
+}
+
+#[test]
+fn test_cpu_talks_to_uart_on_io_bus() {
+    let image = rv_asm::assemble("
+            li t0, 0x80000000
+            li a0, 104
+            sw a0, 0(t0)        # 'h'
+            li a0, 0x169
+            sb a0, 0(t0)        # 'i', only the low byte is sent
+            lw a1, 2(t0)        # status: received byte waiting, ready to send
+            lb a2, 0(t0)
+            lhu a3, 2(t0)
+            li t1, 0x80020000
+            sw a0, 0(t1)        # past the 16-bit bus, halts
+            li a4, 1
+            ebreak
+    ").unwrap();
+    let mut cpu = Cpu::<Behavioral>::with_backend();
+    cpu.io().map(0, bus::Uart::new()).unwrap();
+    cpu.io().device_mut::<bus::Uart>(0).unwrap().send(&[0xc8]);
+    cpu.load_program(&image.words());
+    cpu.run(100);
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.io().device_mut::<bus::Uart>(0).unwrap().take_output(), b"hi");
+    assert_eq!(boollist_to_u32(&cpu.read_reg(11)), 0b11);
+    assert_eq!(boollist_to_u32(&cpu.read_reg(12)), 0xffff_ffc8);
+    assert_eq!(boollist_to_u32(&cpu.read_reg(13)), 0b10);
+    assert_eq!(boollist_to_u32(&cpu.read_reg(14)), 0);
+    assert_eq!(boollist_to_u32(&cpu.pc()), 36);
+    // memory under the bus is untouched
+    assert_eq!(boollist_to_u32(&cpu.read_mem(&bytes_to_boollist32(&split_u32(cpu::MEM_BYTES - 4)))), 0);
+}
+
+#[test]
+fn test_hack_computer_on_bus() {
+    // the Hack memory map with a UART where the keyboard's neighbours would be
+    let mut io = Bus::new(15);
+    io.map(0x0000, memory::RAM16384::new()).unwrap();
+    io.map(0x6002, bus::Uart::new()).unwrap();
+    let program = hack_asm::assemble("
+        @72
+        D=A
+        @24578
+        M=D
+        @24579
+        D=M
+        @R0
+        M=D
+    (END)
+        @END
+        0;JMP
+    ").unwrap();
+    let mut computer = Computer::<Behavioral, Bus>::with_memory(io);
+    computer.load_program(&program);
+    computer.run(20);
+    assert_eq!(computer.mem().device_mut::<bus::Uart>(0x6002).unwrap().take_output(), b"H");
+    assert_eq!(computer.read_mem(&bus16(0)), bus16(0b10));
+}